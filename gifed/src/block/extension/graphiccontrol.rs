@@ -54,7 +54,7 @@ impl GraphicControl {
 			DisposalMethod::NoAction => self.packed.set_disposal_method(0),
 			DisposalMethod::DoNotDispose => self.packed.set_disposal_method(1),
 			DisposalMethod::RestoreBackground => self.packed.set_disposal_method(2),
-			DisposalMethod::RestorePrevious => self.packed.set_disposal_method(4),
+			DisposalMethod::RestorePrevious => self.packed.set_disposal_method(3),
 		}
 	}
 
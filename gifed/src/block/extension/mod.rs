@@ -1,5 +1,7 @@
 mod application;
 mod graphiccontrol;
+mod plaintext;
 
 pub use self::application::Application;
 pub use graphiccontrol::{DisposalMethod, GraphicControl};
+pub use plaintext::PlainText;
@@ -0,0 +1,75 @@
+use std::convert::TryInto;
+
+/// Text to be drawn over the logical screen in a grid of character cells.
+/// The text is rendered using the Global Color Table.
+#[derive(Clone, Debug)]
+pub struct PlainText {
+	/// Column of the grid's left edge, in pixels, on the logical screen
+	pub left: u16,
+	/// Row of the grid's top edge, in pixels, on the logical screen
+	pub top: u16,
+	/// Width of the grid in pixels
+	pub width: u16,
+	/// Height of the grid in pixels
+	pub height: u16,
+	/// Width of each character cell in pixels
+	pub cell_width: u8,
+	/// Height of each character cell in pixels
+	pub cell_height: u8,
+	/// Index into the Global Color Table for the text's color
+	pub foreground_color_index: u8,
+	/// Index into the Global Color Table for the cell background
+	pub background_color_index: u8,
+	/// The text itself. Decoders are only required to understand 7-bit ASCII,
+	/// anything else should be drawn as a space.
+	pub text: Vec<u8>,
+}
+
+impl PlainText {
+	pub fn text(&self) -> &[u8] {
+		&self.text
+	}
+
+	/// The number of character cells in a row, or zero if cells are 0 wide.
+	pub fn columns(&self) -> u16 {
+		self.width.checked_div(self.cell_width as u16).unwrap_or(0)
+	}
+
+	/// The number of rows of character cells, or zero if cells are 0 tall.
+	pub fn rows(&self) -> u16 {
+		self.height
+			.checked_div(self.cell_height as u16)
+			.unwrap_or(0)
+	}
+
+	/// The twelve bytes that follow the block size in the extension.
+	pub(crate) fn header_bytes(&self) -> [u8; 12] {
+		let mut header = [0; 12];
+		header[0..2].copy_from_slice(&self.left.to_le_bytes());
+		header[2..4].copy_from_slice(&self.top.to_le_bytes());
+		header[4..6].copy_from_slice(&self.width.to_le_bytes());
+		header[6..8].copy_from_slice(&self.height.to_le_bytes());
+		header[8] = self.cell_width;
+		header[9] = self.cell_height;
+		header[10] = self.foreground_color_index;
+		header[11] = self.background_color_index;
+
+		header
+	}
+}
+
+impl From<[u8; 12]> for PlainText {
+	fn from(arr: [u8; 12]) -> Self {
+		Self {
+			left: u16::from_le_bytes(arr[0..2].try_into().unwrap()),
+			top: u16::from_le_bytes(arr[2..4].try_into().unwrap()),
+			width: u16::from_le_bytes(arr[4..6].try_into().unwrap()),
+			height: u16::from_le_bytes(arr[6..8].try_into().unwrap()),
+			cell_width: arr[8],
+			cell_height: arr[9],
+			foreground_color_index: arr[10],
+			background_color_index: arr[11],
+			text: vec![],
+		}
+	}
+}
@@ -1,6 +1,9 @@
-use std::convert::TryInto;
+use std::{
+	convert::TryInto,
+	io::{self, Write},
+};
 
-use super::{packed::ImagePacked, Palette};
+use super::{packed::ImagePacked, Palette, Version};
 
 #[derive(Clone, Debug)]
 pub struct ImageDescriptor {
@@ -34,6 +37,15 @@ impl ImageDescriptor {
 		crate::packed_to_color_table_length(self.packed.color_table_size())
 	}
 
+	/// The sort flag was reserved in GIF87a and had to be zero.
+	pub fn required_version(&self) -> Version {
+		if self.packed.sorted() {
+			Version::Gif89a
+		} else {
+			Version::Gif87a
+		}
+	}
+
 	pub fn as_bytes(&self) -> Vec<u8> {
 		let mut vec = vec![];
 
@@ -46,6 +58,10 @@ impl ImageDescriptor {
 
 		vec
 	}
+
+	pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+		writer.write_all(&self.as_bytes())
+	}
 }
 
 impl From<[u8; 9]> for ImageDescriptor {
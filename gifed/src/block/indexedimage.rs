@@ -1,6 +1,15 @@
-use crate::{reader::DecodeError, EncodeError};
+use std::{
+	borrow::Cow,
+	io::{self, Write},
+};
 
-use super::{ImageDescriptor, Palette};
+use crate::{
+	lzw::Lossy,
+	reader::{DecodeError, DecodeWarning},
+	EncodeError,
+};
+
+use super::{ImageDescriptor, IndexMap, Palette};
 
 #[derive(Clone, Debug)]
 pub struct IndexedImage {
@@ -35,33 +44,171 @@ impl IndexedImage {
 	/// LZW Minimum Code Size here. It is equal to the value of [Palette::packed_len] + 1 but
 	/// must be at least 2.
 	pub fn compress(self, lzw_code_size: Option<u8>) -> Result<CompressedImage, EncodeError> {
-		let mcs = match self.local_color_table.as_ref() {
-			Some(palette) => palette.lzw_code_size(),
-			None => match lzw_code_size {
-				None => return Err(EncodeError::InvalidCodeSize { lzw_code_size: 0 }),
-				Some(mcs) => mcs.max(2),
-			},
-		};
+		let mcs = self.code_size(lzw_code_size)?;
+		Ok(self.compress_with_code_size(mcs))
+	}
+
+	/// Compress with exactly this LZW minimum code size, regardless of the
+	/// palette. Every index must fit in `mcs` bits.
+	pub(crate) fn compress_with_code_size(self, mcs: u8) -> CompressedImage {
+		let indicies = self.stream_order();
 
 		#[cfg(not(feature = "weezl-encode"))]
-		let compressed = crate::LZW::new(mcs).encode(&self.indicies);
+		let compressed = crate::LZW::new(mcs).encode(&indicies);
 
 		#[cfg(feature = "weezl-encode")]
 		let compressed = weezl::encode::Encoder::new(weezl::BitOrder::Lsb, mcs)
-			.encode(&self.indicies)
+			.encode(&indicies)
 			.unwrap();
 
+		self.into_compressed(mcs, &compressed)
+	}
+
+	/// Compress this image lossily, which can change pixels to any color
+	/// that's close enough to make the data smaller. See [Lossy] for the
+	/// options and [IndexedImage::compress] for what `lzw_code_size` should be.
+	///
+	/// This always uses the built-in encoder, even with `weezl-encode`.
+	pub fn compress_lossy(
+		self,
+		lzw_code_size: Option<u8>,
+		lossy: Lossy,
+	) -> Result<CompressedImage, EncodeError> {
+		let mcs = self.code_size(lzw_code_size)?;
+		let compressed = crate::LZW::new(mcs)
+			.lossy(lossy)
+			.encode(&self.stream_order());
+
+		Ok(self.into_compressed(mcs, &compressed))
+	}
+
+	fn into_compressed(self, mcs: u8, compressed: &[u8]) -> CompressedImage {
 		let mut blocks = vec![];
 		for chunk in compressed.chunks(255) {
 			blocks.push(chunk.to_vec());
 		}
 
-		Ok(CompressedImage {
+		CompressedImage {
 			image_descriptor: self.image_descriptor,
 			local_color_table: self.local_color_table,
 			lzw_code_size: mcs,
 			blocks,
-		})
+		}
+	}
+
+	/// Compress this image and write the whole image block to `writer`,
+	/// as [CompressedImage::as_bytes] would, without holding the compressed
+	/// data in memory. See [IndexedImage::compress] for what `lzw_code_size`
+	/// should be.
+	pub fn compress_to<W: Write>(
+		&self,
+		lzw_code_size: Option<u8>,
+		mut writer: W,
+	) -> Result<(), EncodeError> {
+		let mcs = self.code_size(lzw_code_size)?;
+
+		self.image_descriptor.write_to(&mut writer)?;
+		if let Some(palette) = &self.local_color_table {
+			palette.write_to(&mut writer)?;
+		}
+		writer.write_all(&[mcs])?;
+
+		let indicies = self.stream_order();
+
+		#[cfg(not(feature = "weezl-encode"))]
+		crate::LZW::new(mcs).encode_to(&indicies, writer)?;
+
+		#[cfg(feature = "weezl-encode")]
+		{
+			let mut blocks = crate::lzw::SubBlockWriter::new(writer);
+			weezl::encode::Encoder::new(weezl::BitOrder::Lsb, mcs)
+				.into_stream(&mut blocks)
+				.encode_all(indicies.as_ref())
+				.status?;
+			blocks.finish()?;
+		}
+
+		Ok(())
+	}
+
+	/// Move every index to where `map` says it went. `transparent` is the
+	/// image's transparent index, which is returned after it's moved. If
+	/// it'd end up the same as a color the image uses it's moved to an index
+	/// that isn't used instead, and if no pixels are transparent and it was
+	/// removed, you get None. Indicies that were removed are left alone.
+	pub fn remap(&mut self, map: &IndexMap, transparent: Option<u8>) -> Option<u8> {
+		let used = self.index_counts().map(|count| count > 0);
+
+		// Where the colors the image uses end up
+		let mut taken = [false; 256];
+		for idx in (0..=255u8).filter(|idx| used[*idx as usize] && Some(*idx) != transparent) {
+			taken[map.get(idx).unwrap_or(idx) as usize] = true;
+		}
+
+		let transparent = transparent.map(|idx| {
+			let moved = map.get(idx).filter(|moved| !taken[*moved as usize]);
+			match moved {
+				Some(moved) => (idx, Some(moved)),
+				None if used[idx as usize] => {
+					(idx, (0..=255u8).find(|free| !taken[*free as usize]))
+				}
+				None => (idx, None),
+			}
+		});
+
+		for idx in self.indicies.iter_mut() {
+			*idx = match transparent {
+				// There are 255 colors at most, so there's always a free index
+				Some((from, Some(to))) if from == *idx => to,
+				_ => map.get(*idx).unwrap_or(*idx),
+			};
+		}
+
+		transparent.and_then(|(_, to)| to)
+	}
+
+	/// The indicies in the order they're written to the data stream. This is
+	/// only different from [IndexedImage::indicies] if the image is interlaced.
+	fn stream_order(&self) -> Cow<'_, [u8]> {
+		if !self.image_descriptor.packed.interlaced() {
+			return Cow::Borrowed(&self.indicies);
+		}
+
+		let width = self.width() as usize;
+		let mut stream = Vec::with_capacity(self.indicies.len());
+		for (_, y) in interlaced_rows(self.height()) {
+			let start = y as usize * width;
+			if let Some(row) = self.indicies.get(start..start + width) {
+				stream.extend_from_slice(row);
+			}
+		}
+
+		Cow::Owned(stream)
+	}
+
+	/// How many pixels use each index.
+	pub fn index_counts(&self) -> [u32; 256] {
+		let mut counts = [0; 256];
+		for &idx in &self.indicies {
+			counts[idx as usize] += 1;
+		}
+		counts
+	}
+
+	/// The smallest LZW minimum code size every index fits in.
+	pub(crate) fn min_code_size(&self) -> u8 {
+		let max = self.indicies.iter().max().copied().unwrap_or(0);
+		((u8::BITS - max.leading_zeros()) as u8).max(2)
+	}
+
+	fn code_size(&self, lzw_code_size: Option<u8>) -> Result<u8, EncodeError> {
+		match self.local_color_table.as_ref() {
+			Some(palette) => Ok(palette.lzw_code_size()),
+			None => match lzw_code_size {
+				None => Err(EncodeError::InvalidCodeSize { lzw_code_size: 0 }),
+				Some(mcs) => Ok(mcs.max(2)),
+			},
+		}
 	}
 }
 
@@ -96,45 +243,394 @@ impl CompressedImage {
 
 	pub fn as_bytes(&self) -> Vec<u8> {
 		let mut ret = vec![];
+		// Writing to a Vec can't fail
+		self.write_to(&mut ret).unwrap();
+		ret
+	}
 
-		ret.extend_from_slice(&self.image_descriptor.as_bytes());
+	/// Write the whole image block, descriptor and palette included, to
+	/// `writer`.
+	pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+		self.image_descriptor.write_to(&mut writer)?;
 
 		if let Some(palette) = &self.local_color_table {
-			ret.extend_from_slice(&palette.as_bytes());
+			palette.write_to(&mut writer)?;
 		}
 
-		ret.push(self.lzw_code_size);
+		writer.write_all(&[self.lzw_code_size])?;
 
 		for block in &self.blocks {
-			ret.push(block.len() as u8);
-			ret.extend_from_slice(block);
+			writer.write_all(&[block.len() as u8])?;
+			writer.write_all(block)?;
 		}
 
 		// A zero length block indicates the end of the data stream
-		ret.push(0x00);
+		writer.write_all(&[0x00])
+	}
 
-		ret
+	/// Move every index to where `map` says it went and compress the image
+	/// again. Set the [local color table](CompressedImage::local_color_table)
+	/// to the changed palette first if the image has one. See
+	/// [IndexedImage::remap] for how `transparent` is moved.
+	pub fn remap(
+		&mut self,
+		map: &IndexMap,
+		transparent: Option<u8>,
+	) -> Result<Option<u8>, DecodeError> {
+		let mut indexed = self.clone().decompress()?;
+		let transparent = indexed.remap(map, transparent);
+
+		indexed
+			.image_descriptor
+			.set_color_table_metadata(indexed.local_color_table.as_ref());
+
+		let mcs = indexed
+			.palette()
+			.map_or(2, Palette::lzw_code_size)
+			.max(indexed.min_code_size());
+		*self = indexed.compress_with_code_size(mcs);
+
+		Ok(transparent)
 	}
 
+	/// Decompress the image. If the image is interlaced, the rows are put
+	/// back in to the order they're displayed in.
 	pub fn decompress(self) -> Result<IndexedImage, DecodeError> {
+		let stream = self.decompress_stream()?;
+		Ok(self.into_indexed(stream))
+	}
+
+	/// Decompress the image, recovering from broken data where we can. If the
+	/// LZW data has an invalid code, what was decoded before it is kept. The
+	/// indicies are then padded with zeros, or cut short, so there's exactly
+	/// one for every pixel. Anything that had to be fixed is returned as a
+	/// [DecodeWarning].
+	pub fn decompress_lenient(self) -> (IndexedImage, Vec<DecodeWarning>) {
+		let mut warnings = vec![];
+		let mut stream = vec![];
+
+		// One past what we need is enough to know there was too much
+		let expected = self.width() as usize * self.height() as usize;
+		let limit = Some(expected + 1);
+		let data = self.blocks.concat();
+
+		if !(1..=8).contains(&self.lzw_code_size) {
+			warnings.push(DecodeWarning::InvalidCodeSize {
+				lzw_code_size: self.lzw_code_size,
+			});
+		} else if let Err(DecodeError::LzwInvalidCode { code, next_code }) =
+			crate::LZW::new(self.lzw_code_size).decode_into(&data, &mut stream, limit)
+		{
+			warnings.push(DecodeWarning::LzwInvalidCode { code, next_code });
+		}
+
+		if stream.len() != expected {
+			warnings.push(DecodeWarning::ImageDataLength {
+				expected,
+				got: stream.len(),
+			});
+			stream.resize(expected, 0);
+		}
+
+		(self.into_indexed(stream), warnings)
+	}
+
+	/// Put the decompressed data in display order and make an [IndexedImage].
+	fn into_indexed(self, stream: Vec<u8>) -> IndexedImage {
+		let width = self.width() as usize;
+
+		let indicies = if width == 0 {
+			// There are no pixels to put anything in
+			vec![]
+		} else if self.image_descriptor.packed.interlaced() {
+			// Rows missing from a short stream are left as zero
+			let mut indicies = vec![0; width * self.height() as usize];
+
+			for ((_, y), row) in interlaced_rows(self.height()).zip(stream.chunks(width)) {
+				let start = y as usize * width;
+				indicies[start..start + row.len()].copy_from_slice(row);
+			}
+
+			indicies
+		} else {
+			stream
+		};
+
 		let CompressedImage {
 			image_descriptor,
 			local_color_table,
-			lzw_code_size,
-			blocks,
+			..
 		} = self;
 
-		let data: Vec<u8> = blocks.into_iter().flat_map(<_>::into_iter).collect();
-		let mut decompressor = weezl::decode::Decoder::new(weezl::BitOrder::Lsb, lzw_code_size);
-		let indicies = match decompressor.decode(&data) {
-			Err(weezl::LzwError::InvalidCode) => Err(DecodeError::LzwInvalidCode),
-			Ok(o) => Ok(o),
-		}?;
-
-		Ok(IndexedImage {
+		IndexedImage {
 			image_descriptor,
 			local_color_table,
 			indicies,
-		})
+		}
+	}
+
+	/// Decompress the image and call `row` with each row in the order it
+	/// appears in the data stream. For interlaced images this gives you a
+	/// rough version of the whole image after the first pass, which is useful
+	/// for showing a preview.
+	///
+	/// The arguments to `row` are the interlace pass, the row's y position in
+	/// the image, and the row itself. The pass is 0 for images that are not
+	/// interlaced and 1 through 4 for those that are.
+	pub fn decompress_progressive<F>(&self, mut row: F) -> Result<(), DecodeError>
+	where
+		F: FnMut(u8, u16, &[u8]),
+	{
+		let stream = self.decompress_stream()?;
+		let width = (self.width() as usize).max(1);
+
+		if self.image_descriptor.packed.interlaced() {
+			for ((pass, y), data) in interlaced_rows(self.height()).zip(stream.chunks(width)) {
+				row(pass, y, data);
+			}
+		} else {
+			for (y, data) in (0..self.height()).zip(stream.chunks(width)) {
+				row(0, y, data);
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Decompress the LZW data without reordering interlaced rows. It stops
+	/// once there's an index for every pixel, anything after that is ignored.
+	fn decompress_stream(&self) -> Result<Vec<u8>, DecodeError> {
+		let lzw_code_size = self.lzw_code_size;
+
+		// Indicies are bytes, so the code size can't be larger than 8 bits
+		if !(1..=8).contains(&lzw_code_size) {
+			return Err(DecodeError::InvalidCodeSize { lzw_code_size });
+		}
+
+		let data: Vec<u8> = self.blocks.concat();
+		let limit = self.width() as usize * self.height() as usize;
+
+		#[cfg(not(feature = "weezl-decode"))]
+		let indicies = {
+			let mut indicies = vec![];
+			crate::LZW::new(lzw_code_size).decode_into(&data, &mut indicies, Some(limit))?;
+			indicies
+		};
+
+		#[cfg(feature = "weezl-decode")]
+		let indicies = {
+			let mut decompressor = weezl::decode::Decoder::new(weezl::BitOrder::Lsb, lzw_code_size);
+			let mut indicies = vec![];
+			let mut buffer = [0; 4096];
+			let mut input = data.as_slice();
+
+			while indicies.len() < limit {
+				let room = (limit - indicies.len()).min(buffer.len());
+				let result = decompressor.decode_bytes(input, &mut buffer[..room]);
+				input = &input[result.consumed_in..];
+				indicies.extend_from_slice(&buffer[..result.consumed_out]);
+
+				match result.status {
+					Err(weezl::LzwError::InvalidCode) => {
+						// weezl doesn't say which code was wrong, so decode it
+						// again with ours to find out. If ours manages it, take that
+						let mut indicies = vec![];
+						crate::LZW::new(lzw_code_size).decode_into(
+							&data,
+							&mut indicies,
+							Some(limit),
+						)?;
+						return Ok(indicies);
+					}
+					Ok(weezl::LzwStatus::Ok) => (),
+					// Done, or the data ran out without an End of Information code
+					Ok(_) => break,
+				}
+			}
+
+			indicies
+		};
+
+		Ok(indicies)
+	}
+}
+
+/// The rows of an interlaced image in the order they're stored, paired with
+/// the pass they're a part of.
+///
+/// Pass 1 is every 8th row starting at 0, pass 2 every 8th row starting at 4,
+/// pass 3 every 4th row starting at 2, and pass 4 is every other row starting
+/// at 1.
+pub fn interlaced_rows(height: u16) -> impl Iterator<Item = (u8, u16)> {
+	[(1, 0, 8), (2, 4, 8), (3, 2, 4), (4, 1, 2)]
+		.into_iter()
+		.flat_map(move |(pass, start, step)| (start..height).step_by(step).map(move |y| (pass, y)))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::writer::ImageBuilder;
+
+	#[test]
+	fn compress_to_matches_compress() {
+		let indicies: Vec<u8> = (0..64 * 64u32).map(|n| (n % 7 * n % 13) as u8).collect();
+		let image = ImageBuilder::new(64, 64).build(indicies).unwrap().image;
+
+		let mut streamed = vec![];
+		image.compress_to(Some(4), &mut streamed).unwrap();
+
+		assert_eq!(streamed, image.compress(Some(4)).unwrap().as_bytes());
+	}
+
+	#[test]
+	fn compress_lossy_is_smaller() {
+		let mut seed = 7u32;
+		let indicies: Vec<u8> = (0..32 * 32)
+			.map(|_| {
+				seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+				(seed >> 16) as u8 % 3
+			})
+			.collect();
+		let image = ImageBuilder::new(32, 32).build(indicies).unwrap().image;
+
+		let palette: Palette = vec![(0, 0, 0), (1, 1, 1), (2, 2, 2), (255, 255, 255)]
+			.try_into()
+			.unwrap();
+		let compressed = image
+			.clone()
+			.compress_lossy(Some(2), Lossy::new(palette, 4))
+			.unwrap();
+		let size = |image: &CompressedImage| image.blocks.iter().map(Vec::len).sum::<usize>();
+		assert!(size(&compressed) < size(&image.compress(Some(2)).unwrap()));
+
+		let decompressed = compressed.decompress().unwrap();
+		assert_eq!(decompressed.indicies.len(), 32 * 32);
+		assert!(decompressed.indicies.iter().all(|&idx| idx < 3));
+	}
+
+	#[test]
+	fn remap_keeps_transparency_apart() {
+		let mut image = ImageBuilder::new(2, 2)
+			.build(vec![0, 1, 2, 3])
+			.unwrap()
+			.image;
+
+		// Merge 1 in to 0, which is where the transparent index wants to go
+		let mut map = IndexMap::identity();
+		map.set(1, Some(0));
+		map.set(3, Some(0));
+
+		let transparent = image.remap(&map, Some(3));
+		assert_eq!(transparent, Some(1));
+		assert_eq!(image.indicies, vec![0, 0, 2, 1]);
+
+		// None of these pixels are transparent, so there's no need for one
+		let mut image = ImageBuilder::new(2, 2)
+			.build(vec![0, 1, 2, 2])
+			.unwrap()
+			.image;
+		let mut map = IndexMap::identity();
+		map.set(3, None);
+		assert_eq!(image.remap(&map, Some(3)), None);
+	}
+
+	#[test]
+	fn decompress_lenient_pads() {
+		let image = ImageBuilder::new(4, 4).build(vec![1; 16]).unwrap().image;
+		let mut compressed = image.compress(Some(2)).unwrap();
+		compressed.blocks.clear();
+
+		let (indexed, warnings) = compressed.decompress_lenient();
+		assert_eq!(indexed.indicies, vec![0; 16]);
+		assert_eq!(
+			warnings,
+			vec![DecodeWarning::ImageDataLength {
+				expected: 16,
+				got: 0
+			}]
+		);
+	}
+
+	#[test]
+	fn interlaced_row_order() {
+		let rows: Vec<u16> = interlaced_rows(10).map(|(_, y)| y).collect();
+		assert_eq!(rows, vec![0, 8, 4, 2, 6, 1, 3, 5, 7, 9]);
+	}
+
+	#[test]
+	fn interlaced_round_trip() {
+		// Each row is filled with its y position
+		let indicies: Vec<u8> = (0..10u8).flat_map(|y| [y; 3]).collect();
+		let image = ImageBuilder::new(3, 10)
+			.interlaced(true)
+			.build(indicies.clone())
+			.unwrap()
+			.image;
+
+		let compressed = image.compress(Some(4)).unwrap();
+		let mut stream_rows = vec![];
+		compressed
+			.decompress_progressive(|pass, y, row| {
+				assert_eq!(row, &[y as u8; 3]);
+				stream_rows.push((pass, y));
+			})
+			.unwrap();
+
+		assert_eq!(stream_rows, interlaced_rows(10).collect::<Vec<_>>());
+		assert_eq!(compressed.decompress().unwrap().indicies, indicies);
+	}
+
+	#[test]
+	fn decompress_stops_at_pixels() {
+		let indicies: Vec<u8> = (0..100 * 100u32).map(|n| (n % 3) as u8).collect();
+		let image = ImageBuilder::new(100, 100).build(indicies).unwrap().image;
+
+		// Far more data than a 2x2 image can hold
+		let mut compressed = image.compress(Some(2)).unwrap();
+		compressed.image_descriptor.width = 2;
+		compressed.image_descriptor.height = 2;
+
+		assert_eq!(compressed.decompress().unwrap().indicies, [0, 1, 2, 0]);
+	}
+
+	#[test]
+	fn decompress_invalid_code() {
+		let image = ImageBuilder::new(2, 2).build(vec![0; 4]).unwrap().image;
+
+		// A clear code and then 7, which isn't in the table yet
+		let mut compressed = image.compress(Some(2)).unwrap();
+		compressed.blocks = vec![vec![0b0011_1100]];
+
+		assert!(matches!(
+			compressed.clone().decompress(),
+			Err(DecodeError::LzwInvalidCode {
+				code: 7,
+				next_code: 6
+			})
+		));
+		assert!(matches!(
+			compressed.decompress_lenient().1[0],
+			DecodeWarning::LzwInvalidCode {
+				code: 7,
+				next_code: 6
+			}
+		));
+	}
+
+	#[test]
+	fn interlaced_zero_width() {
+		let image = ImageBuilder::new(3, 10)
+			.interlaced(true)
+			.build(vec![1; 30])
+			.unwrap()
+			.image;
+
+		// Data for pixels the image doesn't have
+		let mut compressed = image.compress(Some(4)).unwrap();
+		compressed.image_descriptor.width = 0;
+
+		assert!(compressed.clone().decompress().unwrap().indicies.is_empty());
+		assert!(compressed.decompress_lenient().0.indicies.is_empty());
 	}
 }
@@ -1,3 +1,5 @@
+use std::io::{self, Write};
+
 pub mod extension;
 mod imagedescriptor;
 mod indexedimage;
@@ -7,14 +9,16 @@ mod screendescriptor;
 mod version;
 
 pub use imagedescriptor::ImageDescriptor;
+pub use indexedimage::interlaced_rows;
 pub use indexedimage::CompressedImage;
 pub use indexedimage::IndexedImage;
-pub use palette::Palette;
+pub use palette::{IndexMap, Palette};
 pub use screendescriptor::ScreenDescriptor;
 pub use version::Version;
 
 use self::extension::Application;
 use self::extension::GraphicControl;
+use self::extension::PlainText;
 
 #[derive(Clone, Debug)]
 pub enum Block {
@@ -22,12 +26,21 @@ pub enum Block {
 	//TODO: Extension(Extension),
 	GraphicControlExtension(GraphicControl),
 	CommentExtension(Vec<u8>),
-	//TODO: PlainTextExtension(PlainTextExtension),
+	PlainTextExtension(PlainText),
 	ApplicationExtension(Application),
-	LoopingExtension(LoopCount),
+	LoopingExtension(LoopCount, AnimationApplication),
+	/// The NETSCAPE2.0 buffering sub-block. The size, in bytes, of the buffer
+	/// the decoder should fill before playing the animation.
+	BufferingExtension(u32, AnimationApplication),
+	/// An extension with a label we don't recognize. The data sub-blocks are
+	/// kept exactly as they were so the extension can be written back out.
+	UnknownExtension {
+		label: u8,
+		data: Vec<Vec<u8>>,
+	},
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum LoopCount {
 	Forever,
 	Number(u16),
@@ -41,66 +54,120 @@ impl LoopCount {
 	}
 }
 
-pub(crate) fn encode_block(block: &Block) -> Vec<u8> {
-	match block {
-		Block::CompressedImage(img) => img.as_bytes(),
-		Block::GraphicControlExtension(_) => encode_extension(block),
-		Block::CommentExtension(_) => encode_extension(block),
-		Block::ApplicationExtension(_) => encode_extension(block),
-		Block::LoopingExtension(_) => encode_extension(block),
+/// The application extension a [Block::LoopingExtension] or
+/// [Block::BufferingExtension] is written in. They both mean the same thing,
+/// but NETSCAPE2.0 is what nearly everything writes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AnimationApplication {
+	#[default]
+	Netscape,
+	AnimExts,
+}
+
+impl AnimationApplication {
+	/// Figure out which this is from an application extension's identifier
+	/// and authentication code. None if it's neither.
+	pub fn from_identifier(identifier: &[u8; 8], authentication_code: &[u8; 3]) -> Option<Self> {
+		match (identifier, authentication_code) {
+			(b"NETSCAPE", b"2.0") => Some(Self::Netscape),
+			(b"ANIMEXTS", b"1.0") => Some(Self::AnimExts),
+			_ => None,
+		}
+	}
+
+	/// The identifier and authentication code, together, as they're written.
+	pub fn identifier(&self) -> &'static [u8; 11] {
+		match self {
+			Self::Netscape => b"NETSCAPE2.0",
+			Self::AnimExts => b"ANIMEXTS1.0",
+		}
 	}
 }
 
-fn encode_extension(block: &Block) -> Vec<u8> {
-	let mut vec = vec![];
-	vec.push(0x21); // Extension Introducer
+/// Write a block to `writer`. Writing is unbuffered, so wrap files and
+/// sockets in a [BufWriter](std::io::BufWriter).
+pub(crate) fn write_block<W: Write>(block: &Block, writer: W) -> io::Result<()> {
+	match block {
+		Block::CompressedImage(img) => img.write_to(writer),
+		_ => write_extension(block, writer),
+	}
+}
 
+fn write_extension<W: Write>(block: &Block, mut writer: W) -> io::Result<()> {
 	match block {
 		Block::CompressedImage(_) => unreachable!(),
 		Block::GraphicControlExtension(gce) => {
-			vec.push(0xF9); // Graphic control label
-			vec.push(0x04); // Block size for this extension is always 4
-			vec.push(gce.packed.raw);
-			vec.extend_from_slice(&gce.delay.to_le_bytes());
-			vec.push(gce.transparency_index);
+			let [delay_low, delay_high] = gce.delay.to_le_bytes();
+			writer.write_all(&[
+				0x21, // Extension Introducer
+				0xF9, // Graphic control label
+				0x04, // Block size for this extension is always 4
+				gce.packed.raw,
+				delay_low,
+				delay_high,
+				gce.transparency_index,
+			])?;
 		}
 		Block::CommentExtension(comment) => {
-			vec.push(0xFE); // Comment label
-
-			for chnk in comment.chunks(255) {
-				vec.push(chnk.len() as u8);
-				vec.extend_from_slice(chnk);
-			}
+			writer.write_all(&[0x21, 0xFE])?; // Comment label
+			write_sub_blocks(&mut writer, comment)?;
+		}
+		Block::PlainTextExtension(text) => {
+			writer.write_all(&[0x21, 0x01])?; // Plain text label
+			writer.write_all(&[0x0C])?; // Block size for this extension is always 12
+			writer.write_all(&text.header_bytes())?;
+			write_sub_blocks(&mut writer, &text.text)?;
 		}
 		Block::ApplicationExtension(app) => {
-			vec.push(0xFF); // Application extension label
-			vec.push(0x0B); // 11 bytes, fixed, for the ident and auth
-			vec.extend_from_slice(&app.identifier);
-			vec.extend_from_slice(&app.authentication_code);
-
-			for chnk in app.data.chunks(255) {
-				vec.push(chnk.len() as u8);
-				vec.extend_from_slice(chnk);
-			}
+			writer.write_all(&[0x21, 0xFF])?; // Application extension label
+			writer.write_all(&[0x0B])?; // 11 bytes, fixed, for the ident and auth
+			writer.write_all(&app.identifier)?;
+			writer.write_all(&app.authentication_code)?;
+			write_sub_blocks(&mut writer, &app.data)?;
 		}
-		Block::LoopingExtension(lc) => {
-			vec.push(0xFF); // Application extension label
-			vec.push(0x0B); // 11 bytes in this block
-			vec.extend_from_slice(b"NETSCAPE2.0"); // App. ident. and "auth code"
-			vec.push(0x03); // Sub-block length
-			vec.push(0x01); // Identifies netscape looping extension
+		Block::LoopingExtension(lc, app) => {
+			writer.write_all(&[0x21, 0xFF])?; // Application extension label
+			writer.write_all(&[0x0B])?; // 11 bytes in this block
+			writer.write_all(app.identifier())?; // App. ident. and "auth code"
+			writer.write_all(&[0x03])?; // Sub-block length
+			writer.write_all(&[0x01])?; // Identifies netscape looping extension
 
 			match lc {
-				LoopCount::Forever => vec.extend_from_slice(&[0x00, 0x00]),
-				LoopCount::Number(count) => vec.extend_from_slice(&count.to_le_bytes()),
+				LoopCount::Forever => writer.write_all(&[0x00, 0x00])?,
+				LoopCount::Number(count) => writer.write_all(&count.to_le_bytes())?,
+			}
+		}
+		Block::BufferingExtension(size, app) => {
+			writer.write_all(&[0x21, 0xFF])?; // Application extension label
+			writer.write_all(&[0x0B])?; // 11 bytes in this block
+			writer.write_all(app.identifier())?; // App. ident. and "auth code"
+			writer.write_all(&[0x05])?; // Sub-block length
+			writer.write_all(&[0x02])?; // Identifies netscape buffering extension
+			writer.write_all(&size.to_le_bytes())?;
+		}
+		Block::UnknownExtension { label, data } => {
+			writer.write_all(&[0x21, *label])?;
+
+			for sub in data {
+				writer.write_all(&[sub.len() as u8])?;
+				writer.write_all(sub)?;
 			}
 		}
 	}
 
 	// Zero length sub-block indicates end of extension
-	vec.push(0x00);
+	writer.write_all(&[0x00])
+}
+
+/// Write `data` in sub-blocks of up to 255 bytes. This doesn't write the
+/// zero-length block that ends them.
+pub(crate) fn write_sub_blocks<W: Write>(mut writer: W, data: &[u8]) -> io::Result<()> {
+	for chnk in data.chunks(255) {
+		writer.write_all(&[chnk.len() as u8])?;
+		writer.write_all(chnk)?;
+	}
 
-	vec
+	Ok(())
 }
 
 impl From<GraphicControl> for Block {
@@ -115,8 +182,25 @@ impl From<Application> for Block {
 	}
 }
 
+impl From<PlainText> for Block {
+	fn from(text: PlainText) -> Self {
+		Block::PlainTextExtension(text)
+	}
+}
+
+impl Block {
+	/// The oldest version of the spec this block can be written in. Every
+	/// extension needs [Version::Gif89a].
+	pub fn required_version(&self) -> Version {
+		match self {
+			Block::CompressedImage(img) => img.image_descriptor.required_version(),
+			_ => Version::Gif89a,
+		}
+	}
+}
+
 impl From<LoopCount> for Block {
 	fn from(count: LoopCount) -> Self {
-		Block::LoopingExtension(count)
+		Block::LoopingExtension(count, AnimationApplication::Netscape)
 	}
 }
@@ -23,7 +23,7 @@ impl GraphicPacked {
 	pub fn set_disposal_method(&mut self, disposal: u8) {
 		// Care about 3 least significant bits and we want them three from the top
 		// from 000_001_1_1 into 000_111_0_0
-		self.raw = (disposal & 0b0000_0111) << 2;
+		self.raw = (self.raw & 0b111_000_1_1) | ((disposal & 0b0000_0111) << 2);
 	}
 
 	pub fn user_input(&self) -> bool {
@@ -121,7 +121,7 @@ impl ImagePacked {
 	pub fn set_color_table_size(&mut self, size: u8) {
 		// The color table is the least significant already, don't do anything
 		// except select the bits
-		self.raw |= size & 0b0_0_0_00_111;
+		self.raw = (self.raw & !0b0_0_0_00_111) | (size & 0b0_0_0_00_111);
 	}
 }
 
@@ -168,6 +168,6 @@ impl ScreenPacked {
 	}
 
 	pub fn set_color_table_size(&mut self, size: u8) {
-		self.raw |= size & 0b0_0_0_00_111;
+		self.raw = (self.raw & !0b0_0_0_00_111) | (size & 0b0_0_0_00_111);
 	}
 }
@@ -2,6 +2,7 @@ pub use crate::Color;
 use crate::EncodeError;
 use std::{
 	convert::{TryFrom, TryInto},
+	io::{self, Write},
 	ops::Deref,
 };
 
@@ -30,7 +31,7 @@ impl Palette {
 	}
 
 	pub fn is_empty(&self) -> bool {
-		self.len() > 0
+		self.len() == 0
 	}
 
 	/// Returns the number of items that the decoder *thinks* is in the palette.
@@ -55,6 +56,108 @@ impl Palette {
 			.map(|idx| idx as u8)
 	}
 
+	/// The index of the color closest to `color`, by euclidean distance in
+	/// RGB. Returns None if the palette is empty.
+	pub fn nearest(&self, color: Color) -> Option<u8> {
+		self.table
+			.iter()
+			.enumerate()
+			.min_by_key(|(_, clr)| crate::quantize::distance(color, **clr))
+			.map(|(idx, _)| idx as u8)
+	}
+
+	/// Remove colors that are in the palette more than once. Every index of a
+	/// duplicate maps to the first one.
+	pub fn dedup(&mut self) -> IndexMap {
+		let mut map = IndexMap::identity();
+		let mut table: Vec<Color> = Vec::with_capacity(self.table.len());
+
+		for (idx, color) in self.table.iter().enumerate() {
+			match table.iter().position(|clr| clr == color) {
+				Some(first) => map.map[idx] = Some(first as u8),
+				None => {
+					map.map[idx] = Some(table.len() as u8);
+					table.push(*color);
+				}
+			}
+		}
+
+		self.table = table;
+		map
+	}
+
+	/// Remove the colors that nothing uses. `counts` is how many pixels use
+	/// each index, like from [Gif::global_index_counts](crate::Gif::global_index_counts).
+	/// The removed indicies don't map anywhere.
+	pub fn remove_unused(&mut self, counts: &[u32]) -> IndexMap {
+		let order = (0..self.len())
+			.filter(|idx| count(counts, *idx) > 0)
+			.collect();
+		self.reorder(order)
+	}
+
+	/// Sort the colors so the most used come first. Colors that are used the
+	/// same amount stay in the order they were in.
+	pub fn sort_by_frequency(&mut self, counts: &[u32]) -> IndexMap {
+		let mut order: Vec<usize> = (0..self.len()).collect();
+		order.sort_by_key(|idx| std::cmp::Reverse(count(counts, *idx)));
+		self.reorder(order)
+	}
+
+	/// Bring the palette down to at most `len` colors by keeping the most
+	/// used and mapping the rest to whichever kept color is closest. This
+	/// changes how images look if the colors that are dropped are used.
+	pub fn shrink_to(&mut self, len: usize, counts: &[u32]) -> IndexMap {
+		if self.len() <= len {
+			return IndexMap::identity();
+		}
+
+		let mut keep: Vec<usize> = (0..self.len()).collect();
+		keep.sort_by_key(|idx| std::cmp::Reverse(count(counts, *idx)));
+		keep.truncate(len);
+		keep.sort_unstable();
+
+		let old = std::mem::take(&mut self.table);
+		let mut map = self.reorder_from(&old, keep);
+
+		for (idx, color) in old.iter().enumerate() {
+			if map.map[idx].is_none() {
+				map.map[idx] = self.nearest(*color);
+			}
+		}
+
+		map
+	}
+
+	/// Shrink the palette to the power of two at or below its length, so none
+	/// of the color table written out is padding. See [Palette::shrink_to].
+	pub fn shrink_to_power_of_two(&mut self, counts: &[u32]) -> IndexMap {
+		match self.len() {
+			0..=2 => IndexMap::identity(),
+			len => self.shrink_to(1 << len.ilog2(), counts),
+		}
+	}
+
+	/// Make a new table of the colors at the indicies in `order`.
+	fn reorder(&mut self, order: Vec<usize>) -> IndexMap {
+		let old = std::mem::take(&mut self.table);
+		self.reorder_from(&old, order)
+	}
+
+	fn reorder_from(&mut self, old: &[Color], order: Vec<usize>) -> IndexMap {
+		let mut map = IndexMap::identity();
+		for slot in &mut map.map[..old.len()] {
+			*slot = None;
+		}
+
+		for idx in order {
+			map.map[idx] = Some(self.table.len() as u8);
+			self.table.push(old[idx]);
+		}
+
+		map
+	}
+
 	/// How many padding bytes we need to write.
 	/// We need to pad the colour table because the size must be a power of two.
 	//TODO: gen- better docs
@@ -65,14 +168,69 @@ impl Palette {
 
 	/// The palette with padding if required
 	pub fn as_bytes(&self) -> Vec<u8> {
+		let mut bytes = Vec::with_capacity(self.computed_len() * 3);
+		// Writing to a Vec can't fail
+		self.write_to(&mut bytes).unwrap();
+		bytes
+	}
+
+	/// Write the palette, with padding if required, to `writer`.
+	pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
 		let mut bytes = Vec::with_capacity(self.table.len() * 3);
 		for color in &self.table {
 			bytes.extend_from_slice(&[color.r, color.g, color.b]);
 		}
+		writer.write_all(&bytes)?;
 
-		bytes.extend(std::iter::repeat(0u8).take(self.padding()));
+		writer.write_all(&[0u8; 256 * 3][..self.padding()])
+	}
+}
 
-		bytes
+fn count(counts: &[u32], idx: usize) -> u32 {
+	counts.get(idx).copied().unwrap_or(0)
+}
+
+/// Where each index of a [Palette] went after it was changed. Indicies past
+/// the end of the palette, which can still be used as transparent indicies,
+/// stay where they are.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexMap {
+	map: [Option<u8>; 256],
+}
+
+impl IndexMap {
+	/// A map where nothing moves.
+	pub fn identity() -> Self {
+		Self {
+			map: std::array::from_fn(|idx| Some(idx as u8)),
+		}
+	}
+
+	/// Where `index` went, or None if its color was removed.
+	pub fn get(&self, index: u8) -> Option<u8> {
+		self.map[index as usize]
+	}
+
+	/// Move `from` to `to`, or remove it if `to` is None.
+	pub fn set(&mut self, from: u8, to: Option<u8>) {
+		self.map[from as usize] = to;
+	}
+
+	/// The map you get from doing this one and then `next`.
+	pub fn then(&self, next: &IndexMap) -> IndexMap {
+		Self {
+			map: self.map.map(|idx| idx.and_then(|idx| next.get(idx))),
+		}
+	}
+
+	pub fn is_identity(&self) -> bool {
+		*self == Self::identity()
+	}
+}
+
+impl Default for IndexMap {
+	fn default() -> Self {
+		Self::identity()
 	}
 }
 
@@ -117,7 +275,7 @@ impl TryFrom<&[u8]> for Palette {
 	type Error = ();
 
 	fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-		if value.len() % 3 != 0 {
+		if !value.len().is_multiple_of(3) {
 			Err(())
 		} else {
 			Ok(Self {
@@ -269,6 +427,52 @@ mod test {
 		test_n_with_padding(256, 0);
 	}
 
+	fn grays(levels: &[u8]) -> Palette {
+		levels
+			.iter()
+			.map(|l| (*l, *l, *l))
+			.collect::<Vec<_>>()
+			.try_into()
+			.unwrap()
+	}
+
+	#[test]
+	fn dedup_maps_to_first() {
+		let mut palette = grays(&[0, 10, 0, 20, 10]);
+		let map = palette.dedup();
+
+		assert_eq!(palette, grays(&[0, 10, 20]));
+		let moved: Vec<Option<u8>> = (0..5).map(|idx| map.get(idx)).collect();
+		assert_eq!(moved, vec![Some(0), Some(1), Some(0), Some(2), Some(1)]);
+		assert_eq!(map.get(200), Some(200));
+	}
+
+	#[test]
+	fn removes_unused_and_sorts() {
+		let mut palette = grays(&[0, 10, 20, 30]);
+		let removed = palette.remove_unused(&[5, 0, 1, 9]);
+		assert_eq!(palette.as_bytes()[..9], [0, 0, 0, 20, 20, 20, 30, 30, 30]);
+		assert_eq!(removed.get(1), None);
+
+		let sorted = palette.sort_by_frequency(&[5, 1, 9]);
+		assert_eq!(palette.as_bytes()[..9], [30, 30, 30, 0, 0, 0, 20, 20, 20]);
+
+		// From the palette we started with to the one we have now
+		let map = removed.then(&sorted);
+		let moved: Vec<Option<u8>> = (0..4).map(|idx| map.get(idx)).collect();
+		assert_eq!(moved, vec![Some(1), None, Some(2), Some(0)]);
+	}
+
+	#[test]
+	fn shrinks_to_nearest() {
+		let mut palette = grays(&[0, 100, 110, 200, 250]);
+		let map = palette.shrink_to_power_of_two(&[9, 9, 1, 9, 9]);
+
+		assert_eq!(palette.len(), 4);
+		assert_eq!(map.get(2), Some(1));
+		assert_eq!(map.get(4), Some(3));
+	}
+
 	#[test]
 	fn packed_len_are_correct() {
 		let black = Color::new(0, 0, 0);
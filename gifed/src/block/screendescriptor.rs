@@ -1,6 +1,9 @@
-use std::convert::TryInto;
+use std::{
+	convert::TryInto,
+	io::{self, Write},
+};
 
-use super::{packed::ScreenPacked, Palette};
+use super::{packed::ScreenPacked, Palette, Version};
 
 #[derive(Clone, Debug)]
 pub struct ScreenDescriptor {
@@ -56,6 +59,16 @@ impl ScreenDescriptor {
 		self.background_color_index = index;
 	}
 
+	/// The sort flag and the pixel aspect ratio were reserved in GIF87a and
+	/// had to be zero.
+	pub fn required_version(&self) -> Version {
+		if self.packed.sorted() || self.pixel_aspect_ratio != 0 {
+			Version::Gif89a
+		} else {
+			Version::Gif87a
+		}
+	}
+
 	pub fn as_bytes(&self) -> Vec<u8> {
 		let mut vec = vec![];
 		vec.extend_from_slice(&self.width.to_le_bytes());
@@ -66,6 +79,10 @@ impl ScreenDescriptor {
 
 		vec
 	}
+
+	pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+		writer.write_all(&self.as_bytes())
+	}
 }
 
 impl From<[u8; 7]> for ScreenDescriptor {
@@ -1,6 +1,7 @@
 use std::fmt;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// The versions are ordered, so the newer is greater.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Version {
 	Gif87a,
 	Gif89a,
@@ -1,19 +1,27 @@
-use std::{fs::File, io::Write, path::Path, time::Duration};
+use std::{
+	fs::File,
+	io::{self, BufWriter, Write},
+	path::Path,
+	time::Duration,
+};
 
 use crate::{
 	block::{
-		encode_block,
-		extension::{DisposalMethod, GraphicControl},
-		Block, CompressedImage, IndexedImage, Palette, ScreenDescriptor, Version,
+		extension::{DisposalMethod, GraphicControl, PlainText},
+		write_block, Block, CompressedImage, IndexMap, IndexedImage, Palette, ScreenDescriptor,
+		Version,
 	},
-	writer::{EncodeBlock, ImageBuilder},
+	quantize::{MedianCut, QuantizeOptions, Quantizer},
+	reader::DecodeError,
+	render::Renderer,
+	writer::{EncodeBlock, ImageBuilder, Strict87a},
 	Color, EncodeError,
 };
 
 #[derive(Clone, Debug)]
 pub struct Gif {
-	/// Usually [Version::Gif89a], but might be [Version::Gif87a] for very
-	/// simple images.
+	/// The version to write. If the blocks need a newer one, that's written
+	/// instead. See [Gif::required_version].
 	pub version: Version,
 	pub descriptor: ScreenDescriptor,
 	pub palette: Option<Palette>,
@@ -23,8 +31,7 @@ pub struct Gif {
 impl Gif {
 	pub fn new(width: u16, height: u16) -> Self {
 		Self {
-			//TODO: gen- select lower when possible
-			version: Version::Gif89a,
+			version: Version::Gif87a,
 			descriptor: ScreenDescriptor::new(width, height),
 			palette: None,
 			blocks: vec![],
@@ -37,31 +44,96 @@ impl Gif {
 	///
 	/// ## Errors
 	/// [EncodeError::TooManyColors] if the number of unique colors in the RGB
-	/// image are greater than 256.
-	pub fn from_rgb(width: u16, height: u16, mut data: Vec<u8>) -> Result<Self, EncodeError> {
+	/// image are greater than 256. Use [Gif::from_rgb_quantized] if there
+	/// might be more.
+	pub fn from_rgb(width: u16, height: u16, data: Vec<u8>) -> Result<Self, EncodeError> {
 		let mut pal = Palette::new();
-		for idx in 0..data.len() / 3 {
-			let raw = &data[idx * 3..idx * 3 + 3];
+		let mut indicies = Vec::with_capacity(data.len() / 3);
+
+		for raw in data.chunks_exact(3) {
 			let clr = Color::new(raw[0], raw[1], raw[2]);
 
 			match pal.from_color(clr) {
-				Some(clr_idx) => data[idx] = clr_idx,
+				Some(clr_idx) => indicies.push(clr_idx),
 				None => {
 					if pal.len() == 256 {
 						// Error if we're already at max size
 						return Err(EncodeError::TooManyColors);
 					} else {
 						// Set as the next index, then fill that index in the palette
-						data[idx] = pal.len() as u8;
+						indicies.push(pal.len() as u8);
 						pal.push(clr);
 					}
 				}
 			}
 		}
-		data.resize(width as usize * height as usize, 0);
+		indicies.resize(width as usize * height as usize, 0);
+
+		let mut gif = Gif::new(width, height);
+		let img = ImageBuilder::new(width, height)
+			.palette(pal)
+			.build(indicies)?;
+		gif.push(img);
+
+		Ok(gif)
+	}
+
+	/// Create a new GIF with a single frame, using `quantizer` to bring the
+	/// colors down to `options.max_colors` if there are more.
+	///
+	/// `data` should be a buffer of RGB data with a length of width * height * 3.
+	///
+	/// ## Errors
+	/// [EncodeError::IndicieSizeMismatch] if `data` is the wrong length.
+	pub fn from_rgb_quantized<Q: Quantizer + ?Sized>(
+		width: u16,
+		height: u16,
+		data: &[u8],
+		quantizer: &Q,
+		options: QuantizeOptions,
+	) -> Result<Self, EncodeError> {
+		let pixels: Vec<Color> = data
+			.chunks_exact(3)
+			.map(|rgb| Color::new(rgb[0], rgb[1], rgb[2]))
+			.collect();
+
+		let mut gif = Gif::new(width, height);
+		let img = ImageBuilder::new(width, height).build_quantized(&pixels, quantizer, options)?;
+		gif.push(img);
+
+		Ok(gif)
+	}
 
+	/// Create a new GIF with a single frame from RGBA data.
+	///
+	/// `data` should be a buffer of RGBA data with a length of
+	/// width * height * 4. Pixels with an alpha below 128 are transparent.
+	/// If there are more than 256 colors they're brought down with
+	/// [MedianCut]. See [Gif::from_rgba_quantized] to choose how.
+	///
+	/// ## Errors
+	/// [EncodeError::IndicieSizeMismatch] if `data` is the wrong length.
+	pub fn from_rgba(width: u16, height: u16, data: &[u8]) -> Result<Self, EncodeError> {
+		Self::from_rgba_quantized(width, height, data, &MedianCut, QuantizeOptions::new())
+	}
+
+	/// Create a new GIF with a single frame from RGBA data, using
+	/// `quantizer` to bring the colors down to `options.max_colors` if there
+	/// are more. Pixels with an alpha below `options.alpha_threshold` are
+	/// transparent and a palette entry is kept for them. See
+	/// [ImageBuilder::build_rgba].
+	///
+	/// ## Errors
+	/// [EncodeError::IndicieSizeMismatch] if `data` is the wrong length.
+	pub fn from_rgba_quantized<Q: Quantizer + ?Sized>(
+		width: u16,
+		height: u16,
+		data: &[u8],
+		quantizer: &Q,
+		options: QuantizeOptions,
+	) -> Result<Self, EncodeError> {
 		let mut gif = Gif::new(width, height);
-		let img = ImageBuilder::new(width, height).palette(pal).build(data)?;
+		let img = ImageBuilder::new(width, height).build_rgba(data, quantizer, options)?;
 		gif.push(img);
 
 		Ok(gif)
@@ -105,28 +177,99 @@ impl Gif {
 		}
 	}
 
+	/// The oldest version of the spec that everything in this gif can be
+	/// written in. Any extension, or any of the flags that were reserved in
+	/// GIF87a being set, needs [Version::Gif89a].
+	pub fn required_version(&self) -> Version {
+		self.blocks
+			.iter()
+			.map(Block::required_version)
+			.chain(std::iter::once(self.descriptor.required_version()))
+			.max()
+			.unwrap_or(Version::Gif87a)
+	}
+
+	/// Remove everything GIF87a doesn't have, so the gif can be written as
+	/// one. Every extension is removed, including Graphic Control Extensions,
+	/// so transparency and delays are lost, and the flags that were reserved
+	/// are cleared.
+	pub fn strip_89a(&mut self) {
+		self.version = Version::Gif87a;
+		self.descriptor.packed.set_sorted(false);
+		self.descriptor.pixel_aspect_ratio = 0;
+
+		self.blocks.retain_mut(|block| match block {
+			Block::CompressedImage(img) => {
+				img.image_descriptor.packed.set_sorted(false);
+				true
+			}
+			_ => false,
+		});
+	}
+
+	/// Write the gif as GIF87a no matter what [Gif::version] is. What happens
+	/// to anything that needs GIF89a depends on `strict`.
+	///
+	/// ## Errors
+	/// [EncodeError::RequiresGif89a] if `strict` is [Strict87a::Error] and
+	/// something needs GIF89a.
+	pub fn as_bytes_87a(&self, strict: Strict87a) -> Result<Vec<u8>, EncodeError> {
+		let mut out = vec![];
+
+		if self.required_version() == Version::Gif87a {
+			// Writing to a Vec can't fail
+			self.encode_to(Version::Gif87a, &mut out).unwrap();
+			return Ok(out);
+		}
+
+		match strict {
+			Strict87a::Error => Err(EncodeError::RequiresGif89a),
+			Strict87a::Strip => {
+				let mut stripped = self.clone();
+				stripped.strip_89a();
+				stripped.encode_to(Version::Gif87a, &mut out).unwrap();
+				Ok(out)
+			}
+		}
+	}
+
+	/// Write the gif. The version written is [Gif::version] or, if the gif
+	/// needs it, [Version::Gif89a].
 	pub fn as_bytes(&self) -> Vec<u8> {
 		let mut out = vec![];
+		// Writing to a Vec can't fail
+		self.write_to(&mut out).unwrap();
+		out
+	}
+
+	/// Write the gif to `writer` block by block, the same bytes
+	/// [Gif::as_bytes] would give you, without building them all in memory
+	/// first. Lots of small writes happen, so wrap files and sockets in a
+	/// [BufWriter].
+	pub fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+		self.encode_to(self.version.max(self.required_version()), writer)
+	}
 
-		out.extend_from_slice(self.version.as_bytes());
-		out.extend_from_slice(&self.descriptor.as_bytes());
+	fn encode_to<W: Write>(&self, version: Version, mut writer: W) -> io::Result<()> {
+		writer.write_all(version.as_bytes())?;
+		self.descriptor.write_to(&mut writer)?;
 
 		if let Some(gct) = &self.palette {
-			out.extend_from_slice(&gct.as_bytes());
+			gct.write_to(&mut writer)?;
 		}
 
 		for block in self.blocks.iter() {
-			out.extend_from_slice(&encode_block(block));
+			write_block(block, &mut writer)?;
 		}
 
 		// Write Trailer
-		out.push(0x3B);
-
-		out
+		writer.write_all(&[0x3B])
 	}
 
-	pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
-		File::create(path.as_ref())?.write_all(&self.as_bytes())
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		let mut writer = BufWriter::new(File::create(path.as_ref())?);
+		self.write_to(&mut writer)?;
+		writer.flush()
 	}
 
 	pub fn push<I: Into<EncodeBlock>>(&mut self, image: I) {
@@ -149,6 +292,116 @@ impl Gif {
 		}
 	}
 
+	/// How many pixels use each index of the global color table, counting
+	/// every image without a local color table. Transparent pixels are
+	/// counted too and so is the background color, once, so that
+	/// [Palette::remove_unused] keeps it.
+	pub fn global_index_counts(&self) -> Result<[u32; 256], DecodeError> {
+		let mut counts = [0; 256];
+
+		for image in self.images() {
+			if image.compressed.palette().is_some() {
+				continue;
+			}
+
+			let image = image.compressed.clone().decompress()?.index_counts();
+			for (count, image) in counts.iter_mut().zip(image) {
+				*count += image;
+			}
+		}
+
+		if let Some(idx) = self.background_color() {
+			counts[idx as usize] += 1;
+		}
+
+		Ok(counts)
+	}
+
+	/// Update everything that uses the global color table after it was
+	/// changed. Every image without a local color table has its indicies
+	/// moved to where `map` says, along with its transparent index, and so
+	/// does the background color. Set the new palette with
+	/// [Gif::set_palette] first.
+	pub fn remap_global(&mut self, map: &IndexMap) -> Result<(), DecodeError> {
+		let images: Vec<usize> = self
+			.image_blocks()
+			.iter()
+			.enumerate()
+			.filter(|(_, blocks)| match &self.blocks[blocks.image] {
+				Block::CompressedImage(image) => image.palette().is_none(),
+				_ => false,
+			})
+			.map(|(idx, _)| idx)
+			.collect();
+
+		for idx in images {
+			self.remap_image(idx, map)?;
+		}
+
+		if let Some(idx) = self.background_color() {
+			self.set_background_color(map.get(idx).unwrap_or(idx));
+		}
+
+		Ok(())
+	}
+
+	/// Move the indicies of the `image`th image, and its transparent index,
+	/// to where `map` says. Use this after changing an image's local color
+	/// table. Does nothing if there aren't that many images.
+	pub fn remap_image(&mut self, image: usize, map: &IndexMap) -> Result<(), DecodeError> {
+		let Some(blocks) = self.image_blocks().get(image).copied() else {
+			return Ok(());
+		};
+
+		let transparent = match blocks.gce.map(|idx| &self.blocks[idx]) {
+			Some(Block::GraphicControlExtension(gce)) => gce.transparent_index(),
+			_ => None,
+		};
+
+		let transparent = match &mut self.blocks[blocks.image] {
+			Block::CompressedImage(compressed) => compressed.remap(map, transparent)?,
+			_ => unreachable!(),
+		};
+
+		if let Some(idx) = blocks.gce {
+			if let Block::GraphicControlExtension(gce) = &mut self.blocks[idx] {
+				gce.set_transparent_index(transparent);
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Where each image is in [Gif::blocks], along with the blocks that go
+	/// with it.
+	pub(crate) fn image_blocks(&self) -> Vec<ImageBlocks> {
+		let mut images = vec![];
+		let mut gce = None;
+		let mut plain_text = false;
+
+		for (idx, block) in self.blocks.iter().enumerate() {
+			match block {
+				Block::GraphicControlExtension(_) => gce = Some(idx),
+				Block::PlainTextExtension(_) => {
+					// The GCE before this was for the text
+					gce = None;
+					plain_text = true;
+				}
+				Block::CompressedImage(_) => {
+					images.push(ImageBlocks {
+						image: idx,
+						gce: gce.take(),
+						plain_text,
+					});
+					plain_text = false;
+				}
+				_ => (),
+			}
+		}
+
+		images
+	}
+
 	/// An iterator over the discrete images in the gif.
 	pub fn images(&self) -> ImageIterator<'_> {
 		ImageIterator {
@@ -156,6 +409,22 @@ impl Gif {
 			block_index: 0,
 		}
 	}
+
+	/// An iterator over the frames of the gif, each composited onto a full
+	/// canvas. See [Renderer].
+	pub fn render(&self) -> Renderer<'_> {
+		Renderer::new(self)
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ImageBlocks {
+	pub(crate) image: usize,
+	/// The Graphic Control Extension that applies to this image
+	pub(crate) gce: Option<usize>,
+	/// Whether there's a Plain Text Extension between this image and the
+	/// one before it
+	pub(crate) plain_text: bool,
 }
 
 pub struct ImageIterator<'a> {
@@ -213,15 +482,28 @@ impl<'a> Image<'a> {
 	}
 
 	pub fn graphic_control(&self) -> Option<&GraphicControl> {
-		for block in self.blocks {
-			if let Block::GraphicControlExtension(gce) = block {
-				return Some(gce);
+		// A Graphic Control Extension applies to the next graphic rendering
+		// block, which could be a Plain Text Extension and not this image.
+		for block in self.blocks.iter().rev() {
+			match block {
+				Block::GraphicControlExtension(gce) => return Some(gce),
+				Block::PlainTextExtension(_) => return None,
+				_ => (),
 			}
 		}
 
 		None
 	}
 
+	/// The Plain Text Extensions that come between the previous image and
+	/// this one.
+	pub fn plain_text(&self) -> impl Iterator<Item = &'a PlainText> {
+		self.blocks.iter().filter_map(|block| match block {
+			Block::PlainTextExtension(text) => Some(text),
+			_ => None,
+		})
+	}
+
 	pub fn transparent_index(&self) -> Option<u8> {
 		self.graphic_control()
 			.and_then(|gce| gce.transparent_index())
@@ -294,3 +576,211 @@ pub enum FrameControl {
 	Input,
 	InputOrDelay(Duration),
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::{block::LoopCount, writer::Writer};
+
+	fn rgba(gif: &Gif) -> Vec<Vec<u8>> {
+		gif.render().map(|frame| frame.unwrap().rgba).collect()
+	}
+
+	#[test]
+	fn from_rgb_keeps_colors() {
+		// Writing the indicies over the RGB data as it's read used to turn
+		// the later pixels in to whatever the indicies looked like
+		let colors = [[10, 20, 30], [0, 0, 0], [1, 1, 1], [10, 20, 30]];
+		let gif = Gif::from_rgb(2, 2, colors.concat()).unwrap();
+
+		let rgba: Vec<u8> = colors
+			.iter()
+			.flat_map(|[r, g, b]| [*r, *g, *b, 255])
+			.collect();
+		assert_eq!(self::rgba(&gif), vec![rgba]);
+	}
+
+	#[test]
+	fn from_rgba_is_transparent() {
+		// A gradient with more colors than fit, with a transparent hole
+		let mut data = vec![];
+		for y in 0..32u8 {
+			for x in 0..32u8 {
+				let alpha = if (8..16).contains(&x) && (8..16).contains(&y) {
+					0
+				} else {
+					255
+				};
+				data.extend_from_slice(&[x * 8, y * 8, 128, alpha]);
+			}
+		}
+
+		let gif = Gif::from_rgba(32, 32, &data).unwrap();
+		let image = gif.images().next().unwrap();
+		let transparent = image.transparent_index().unwrap();
+		assert!(image.palette().len() <= 256);
+
+		let frame = &rgba(&gif)[0];
+		for (px, want) in frame.chunks(4).zip(data.chunks(4)) {
+			assert_eq!(px[3], want[3]);
+		}
+
+		let indicies = image.decompress().indicies;
+		assert_eq!(
+			indicies.iter().filter(|idx| **idx == transparent).count(),
+			64
+		);
+	}
+
+	#[test]
+	fn writes_minimal_version() {
+		let mut gif = Gif::new(1, 1);
+		gif.set_palette(Some(vec![(0, 0, 0), (255, 255, 255)].try_into().unwrap()));
+		gif.push(ImageBuilder::new(1, 1).build(vec![1]).unwrap());
+		assert_eq!(&gif.as_bytes()[..6], b"GIF87a");
+
+		// Decoded gifs keep the version they had
+		gif.version = Version::Gif89a;
+		assert_eq!(&gif.as_bytes()[..6], b"GIF89a");
+		gif.version = Version::Gif87a;
+
+		gif.push(ImageBuilder::new(1, 1).delay(10).build(vec![0]).unwrap());
+		assert_eq!(gif.required_version(), Version::Gif89a);
+		assert_eq!(&gif.as_bytes()[..6], b"GIF89a");
+	}
+
+	#[test]
+	fn write_to_matches_writer() {
+		let palette: Palette = vec![(0, 0, 0), (255, 255, 255)].try_into().unwrap();
+		let comment = vec![b'a'; 600];
+		let image = || ImageBuilder::new(2, 2).delay(10).build(vec![0, 1, 1, 0]);
+
+		let mut gif = Gif::new(2, 2);
+		gif.version = Version::Gif89a;
+		gif.set_palette(Some(palette.clone()));
+		gif.push(LoopCount::Forever);
+		gif.push(Block::CommentExtension(comment.clone()));
+		gif.push(image().unwrap());
+
+		let mut written = vec![];
+		gif.write_to(&mut written).unwrap();
+		assert_eq!(written, gif.as_bytes());
+
+		let mut streamed = vec![];
+		let mut writer = Writer::new(&mut streamed, 2, 2, Some(palette)).unwrap();
+		writer.repeat(LoopCount::Forever).unwrap();
+		writer.push(Block::CommentExtension(comment)).unwrap();
+		writer.push(image().unwrap()).unwrap();
+		writer.done().unwrap();
+		assert_eq!(written, streamed);
+
+		// Errors from the writer come back out
+		let mut short = [0u8; 16];
+		assert!(gif.write_to(&mut short[..]).is_err());
+	}
+
+	#[test]
+	fn strict_87a() {
+		let mut gif = Gif::new(1, 1);
+		gif.set_palette(Some(vec![(0, 0, 0), (255, 255, 255)].try_into().unwrap()));
+		gif.push(Block::CommentExtension(b"hello".to_vec()));
+		gif.push(ImageBuilder::new(1, 1).delay(10).build(vec![1]).unwrap());
+
+		assert!(matches!(
+			gif.as_bytes_87a(Strict87a::Error),
+			Err(EncodeError::RequiresGif89a)
+		));
+
+		let bytes = gif.as_bytes_87a(Strict87a::Strip).unwrap();
+		let stripped = crate::reader::Decoder::new(std::io::Cursor::new(bytes))
+			.read_all()
+			.unwrap();
+
+		assert_eq!(stripped.version, Version::Gif87a);
+		assert_eq!(stripped.blocks.len(), 1);
+		assert_eq!(rgba(&stripped), rgba(&gif));
+	}
+
+	/// Encode and decode `gif`, so tests see what would be written.
+	fn round_trip(gif: &Gif) -> Gif {
+		crate::reader::Decoder::new(gif.as_bytes().as_slice())
+			.read_all()
+			.unwrap()
+	}
+
+	#[test]
+	fn remap_global_keeps_looks() {
+		// Eight colors, but only three different ones
+		let palette: Palette = vec![
+			(0, 0, 0),
+			(255, 255, 255),
+			(0, 0, 0),
+			(255, 0, 0),
+			(255, 255, 255),
+			(0, 0, 0),
+			(255, 255, 255),
+			(0, 0, 0),
+		]
+		.try_into()
+		.unwrap();
+
+		let mut gif = Gif::new(2, 2);
+		gif.set_palette(Some(palette));
+		gif.set_background_color(3);
+		gif.push(ImageBuilder::new(2, 2).build(vec![0, 1, 2, 4]).unwrap());
+		gif.push(
+			ImageBuilder::new(2, 2)
+				.transparent_index(Some(2))
+				.build(vec![2, 7, 5, 6])
+				.unwrap(),
+		);
+		let before = rgba(&gif);
+
+		let mut palette = gif.palette.clone().unwrap();
+		let sorted = palette.sort_by_frequency(&gif.global_index_counts().unwrap());
+		let map = sorted.then(&palette.dedup());
+		assert_eq!(palette.len(), 3);
+
+		gif.set_palette(Some(palette));
+		gif.remap_global(&map).unwrap();
+
+		// The screen descriptor has to say the table is smaller too
+		let decoded = round_trip(&gif);
+		assert_eq!(decoded.descriptor.color_table_len(), 4);
+		assert_eq!(rgba(&decoded), before);
+		// Red only shows up as the background
+		assert_eq!(decoded.background_color(), Some(2));
+	}
+
+	#[test]
+	fn remap_local_keeps_looks() {
+		let mut local = Palette::new();
+		for gray in 0..32 {
+			local.push(Color::new(gray, gray, gray));
+		}
+
+		let mut gif = Gif::new(2, 2);
+		gif.push(
+			ImageBuilder::new(2, 2)
+				.palette(local)
+				.build(vec![3, 30, 3, 12])
+				.unwrap(),
+		);
+		let before = rgba(&gif);
+
+		let Block::CompressedImage(image) = &mut gif.blocks[0] else {
+			unreachable!()
+		};
+		let mut palette = image.palette().unwrap().clone();
+		let map = palette.remove_unused(&image.clone().decompress().unwrap().index_counts());
+		assert_eq!(palette.len(), 3);
+
+		image.local_color_table = Some(palette);
+		image.remap(&map, None).unwrap();
+
+		let decoded = round_trip(&gif);
+		let image = decoded.images().next().unwrap();
+		assert_eq!(image.compressed.image_descriptor.color_table_size(), 4);
+		assert_eq!(rgba(&decoded), before);
+	}
+}
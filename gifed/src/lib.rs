@@ -2,7 +2,10 @@ mod gif;
 mod lzw;
 
 pub mod block;
+pub mod optimize;
+pub mod quantize;
 pub mod reader;
+pub mod render;
 #[cfg(feature = "videoish")]
 pub mod videogif;
 pub mod writer;
@@ -11,7 +14,7 @@ pub use reader::DecodeError;
 pub use writer::EncodeError;
 
 pub use gif::{Gif, Image};
-pub use lzw::LZW;
+pub use lzw::{Lossy, LZW};
 
 #[cfg(feature = "rgb")]
 pub type Color = rgb::RGB8;
@@ -1,130 +1,458 @@
-use std::collections::HashMap;
-
-use bitvec::prelude::*;
-
-#[rustfmt::skip]
-const DEFAULT_DICT: [u8; 256] = [
-	0,   1,   2,   3,   4,   5,   6,   7,   8,   9,  10,  11,  12,  13,  14,  15, 
-	16,  17,  18,  19,  20,  21,  22,  23,  24,  25,  26,  27,  28,  29,  30,  31, 
-	32,  33,  34,  35,  36,  37,  38,  39,  40,  41,  42,  43,  44,  45,  46,  47, 
-	48,  49,  50,  51,  52,  53,  54,  55,  56,  57,  58,  59,  60,  61,  62,  63, 
-	64,  65,  66,  67,  68,  69,  70,  71,  72,  73,  74,  75,  76,  77,  78,  79, 
-	80,  81,  82,  83,  84,  85,  86,  87,  88,  89,  90,  91,  92,  93,  94,  95, 
-	96,  97,  98,  99, 100, 101, 102, 103, 104, 105, 106, 107, 108, 109, 110, 111, 
-	112, 113, 114, 115, 116, 117, 118, 119, 120, 121, 122, 123, 124, 125, 126, 127, 
-	128, 129, 130, 131, 132, 133, 134, 135, 136, 137, 138, 139, 140, 141, 142, 143, 
-	144, 145, 146, 147, 148, 149, 150, 151, 152, 153, 154, 155, 156, 157, 158, 159, 
-	160, 161, 162, 163, 164, 165, 166, 167, 168, 169, 170, 171, 172, 173, 174, 175, 
-	176, 177, 178, 179, 180, 181, 182, 183, 184, 185, 186, 187, 188, 189, 190, 191, 
-	192, 193, 194, 195, 196, 197, 198, 199, 200, 201, 202, 203, 204, 205, 206, 207, 
-	208, 209, 210, 211, 212, 213, 214, 215, 216, 217, 218, 219, 220, 221, 222, 223, 
-	224, 225, 226, 227, 228, 229, 230, 231, 232, 233, 234, 235, 236, 237, 238, 239, 
-	240, 241, 242, 243, 244, 245, 246, 247, 248, 249, 250, 251, 252, 253, 254, 255, 
-];
+use std::{
+	convert::Infallible,
+	io::{self, Write},
+};
+
+use crate::{block::Palette, quantize::distance, reader::DecodeError};
+
+/// Codes are at most 12 bits, so there can only be 4096 of them.
+const MAX_CODE: u16 = 1 << 12;
+
+/// A node in the encoder's code table. The table is a trie where each code is
+/// the string of its parent plus one byte, `suffix`. Children of a code are
+/// kept as a linked list through `sibling`.
+///
+/// No code can be the child of another code *and* have a value of 0, so we use
+/// it to mean "nothing" for both `child` and `sibling`.
+#[derive(Clone, Copy, Debug, Default)]
+struct Code {
+	child: u16,
+	sibling: u16,
+	suffix: u8,
+}
 
-pub struct LZW<'a> {
+pub struct LZW {
 	minimum_size: u8,
 	clear_code: u16,
 	end_of_information_code: u16,
-	dictionary: HashMap<&'a [u8], u16>,
+	codes: Vec<Code>,
+	lossy: Option<Lossy>,
 }
 
-impl<'a> LZW<'a> {
-	pub fn new(minimum_size: u8) -> Self {
-		let mut dictionary: HashMap<&'a [u8], u16> = HashMap::new();
+/// Settings for lossy encoding. See [LZW::lossy].
+#[derive(Clone, Debug)]
+pub struct Lossy {
+	palette: Palette,
+	/// Squared, so we can compare it with [distance]
+	max_distance: u32,
+	transparent_index: Option<u8>,
+}
 
-		let clear_code = 1u16 << minimum_size;
-		let end_of_information_code = clear_code + 1;
+impl Lossy {
+	/// `palette` is the palette the indices are for. An index can be
+	/// swapped for another if their colors are no more than `max_distance`
+	/// apart, measured as the euclidean distance in RGB.
+	pub fn new(palette: Palette, max_distance: u32) -> Self {
+		Self {
+			palette,
+			max_distance: max_distance.saturating_mul(max_distance),
+			transparent_index: None,
+		}
+	}
+
+	/// The transparent index is never swapped for another index and no
+	/// index is swapped for it.
+	pub fn transparent_index(mut self, index: Option<u8>) -> Self {
+		self.transparent_index = index;
+		self
+	}
 
-		// Fill dictionary with self-descriptive values
-		for value in 0..clear_code {
-			dictionary.insert(&DEFAULT_DICT[value as usize..value as usize + 1], value);
+	/// How far apart two indices' colors are. None if they can't be swapped.
+	fn distance(&self, a: u8, b: u8) -> Option<u32> {
+		if self.transparent_index == Some(a) || self.transparent_index == Some(b) {
+			return None;
 		}
 
-		Self {
+		let dist = distance(self.palette.get(a)?, self.palette.get(b)?);
+		(dist <= self.max_distance).then_some(dist)
+	}
+}
+
+impl LZW {
+	pub fn new(minimum_size: u8) -> Self {
+		let clear_code = 1u16 << minimum_size;
+		let end_of_information_code = clear_code + 1;
+
+		let mut this = Self {
 			minimum_size,
 			clear_code,
 			end_of_information_code,
-			dictionary,
-		}
+			codes: Vec::with_capacity(MAX_CODE as usize),
+			lossy: None,
+		};
+		this.reset();
+
+		this
 	}
 
+	/// Encode lossily. When the next index doesn't continue a string that's
+	/// in the table, one that's close enough in color can be used instead,
+	/// which makes for longer strings and smaller data at the cost of
+	/// changing some pixels. Only the encoder is affected.
+	pub fn lossy(mut self, lossy: Lossy) -> Self {
+		self.lossy = Some(lossy);
+		self
+	}
+
+	/// Clear the code table so it only contains the self-descriptive codes.
 	pub fn reset(&mut self) {
-		*self = Self::new(self.minimum_size)
+		self.codes.clear();
+
+		// The clear and end of information codes don't represent anything, but
+		// they take up space in the table all the same.
+		for value in 0..=self.end_of_information_code {
+			self.codes.push(Code {
+				child: 0,
+				sibling: 0,
+				suffix: value as u8,
+			});
+		}
 	}
 
-	pub fn decode(&mut self, encoded: &[u8]) -> Vec<u8> {
-		let mut input = BitStream::new();
-		for &byte in encoded {
-			input.push_bits(8, byte as u16);
+	/// Find the code for the string of `prefix` followed by `byte`.
+	fn find(&self, prefix: u16, byte: u8) -> Option<u16> {
+		let mut code = self.codes[prefix as usize].child;
+
+		while code != 0 {
+			let node = self.codes[code as usize];
+			if node.suffix == byte {
+				return Some(code);
+			}
+
+			code = node.sibling;
 		}
 
+		None
+	}
+
+	/// Find the code for the string of `prefix` followed by `byte`, or, when
+	/// encoding lossily, followed by the closest byte that's close enough.
+	fn find_close(&self, prefix: u16, byte: u8) -> Option<u16> {
+		let lossy = match &self.lossy {
+			None => return self.find(prefix, byte),
+			Some(lossy) => lossy,
+		};
+
+		let mut best = None;
+		let mut code = self.codes[prefix as usize].child;
+
+		while code != 0 {
+			let node = self.codes[code as usize];
+			if node.suffix == byte {
+				return Some(code);
+			}
+
+			if let Some(dist) = lossy.distance(byte, node.suffix) {
+				if best.is_none_or(|(_, best_dist)| dist < best_dist) {
+					best = Some((code, dist));
+				}
+			}
+
+			code = node.sibling;
+		}
+
+		best.map(|(code, _)| code)
+	}
+
+	/// Add the string of `prefix` followed by `byte` as the next code.
+	fn add(&mut self, prefix: u16, byte: u8) {
+		let code = self.codes.len() as u16;
+		let parent = &mut self.codes[prefix as usize];
+
+		let sibling = parent.child;
+		parent.child = code;
+
+		self.codes.push(Code {
+			child: 0,
+			sibling,
+			suffix: byte,
+		});
+	}
+
+	/// Decode a GIF LZW data stream. The stream may end without an End of
+	/// Information code, and any data after it is ignored.
+	pub fn decode(&mut self, encoded: &[u8]) -> Result<Vec<u8>, DecodeError> {
+		let mut out = vec![];
+		self.decode_into(encoded, &mut out, None)?;
+
+		Ok(out)
+	}
+
+	/// Decode into the end of `out`, stopping after `limit` indicies if
+	/// there is one. If there's an error, `out` has everything that was
+	/// decoded before it.
+	pub(crate) fn decode_into(
+		&mut self,
+		encoded: &[u8],
+		out: &mut Vec<u8>,
+		limit: Option<usize>,
+	) -> Result<(), DecodeError> {
+		let mut decompressor = Decompressor::new(self.minimum_size);
+		if let Some(limit) = limit {
+			decompressor = decompressor.limit(limit);
+		}
+
+		decompressor.push(encoded, out)
+	}
+
+	/// Encode the indices into a GIF LZW data stream. Every index must be
+	/// less than 2^minimum_size.
+	pub fn encode(&mut self, indices: &[u8]) -> Vec<u8> {
 		let mut out = BitStream::new();
 
-		todo!();
+		if let Err(never) = self.encode_into(indices, &mut out) {
+			match never {}
+		}
 
 		out.vec()
 	}
 
-	pub fn encode(&mut self, indices: &'a [u8]) -> Vec<u8> {
-		let mut next_code = self.end_of_information_code + 1;
+	/// Encode the indices and write them to `writer` as data sub-blocks,
+	/// including the zero-length block that ends the image data. Only a single
+	/// sub-block is ever buffered.
+	pub fn encode_to<W: Write>(&mut self, indices: &[u8], writer: W) -> io::Result<()> {
+		let mut out = SubBlockWriter::new(writer);
+		self.encode_into(indices, &mut out)?;
+		out.finish()?;
+
+		Ok(())
+	}
+
+	fn encode_into<S: CodeSink>(&mut self, indices: &[u8], out: &mut S) -> Result<(), S::Error> {
 		let mut code_size = self.minimum_size + 1;
 
-		let mut out = BitStream::new();
-		let mut buffer_start = 0;
-		let mut buffer_len = 0;
-
-		out.push_bits(code_size, self.clear_code);
-
-		for idx in 0..indices.len() {
-			buffer_len += 1;
-			let buffer = &indices[buffer_start..buffer_start + buffer_len];
-
-			if !self.dictionary.contains_key(&buffer) {
-				if let Some(&code) = self.dictionary.get(&buffer[..buffer.len() - 1]) {
-					out.push_bits(code_size, code);
-
-					// add the vec to the dict
-					self.dictionary.insert(buffer, next_code);
-					next_code += 1;
-
-					// If the next_code can't fit in the code_size, we have to increase it
-					if next_code - 1 == 1 << code_size {
-						code_size += 1;
-					}
-
-					if next_code >= (1 << 12) {
-						out.push_bits(code_size, self.clear_code);
-						self.reset();
-						next_code = self.end_of_information_code + 1;
-						code_size = self.minimum_size + 1;
-					}
-
-					buffer_start = idx;
-					buffer_len = 1;
-				} else {
-					println!("index is: {idx}");
-					println!("buffer is: {:?}", buffer);
-					println!("dictionary: {:?}", self.dictionary);
-					unreachable!()
+		self.reset();
+		out.push_bits(code_size, self.clear_code)?;
+
+		let mut indices = indices.iter();
+		let mut current = match indices.next() {
+			Some(&byte) => byte as u16,
+			None => return out.push_bits(code_size, self.end_of_information_code),
+		};
+
+		for &byte in indices {
+			if let Some(code) = self.find_close(current, byte) {
+				current = code;
+				continue;
+			}
+
+			out.push_bits(code_size, current)?;
+
+			let next_code = self.codes.len() as u16;
+			self.add(current, byte);
+
+			// If the code we just made doesn't fit in the code_size, the
+			// decoder is going to be expecting a bigger code next time
+			if next_code == 1 << code_size && code_size < 12 {
+				code_size += 1;
+			}
+
+			// The decoder is one code behind us, so we wait until the table
+			// is overfull before clearing. The last code is never sent.
+			if self.codes.len() > MAX_CODE as usize {
+				out.push_bits(code_size, self.clear_code)?;
+				self.reset();
+				code_size = self.minimum_size + 1;
+			}
+
+			current = byte as u16;
+		}
+
+		out.push_bits(code_size, current)?;
+
+		// When the decoder reads that last code it'll add one more to its
+		// table, and that might bump the code size before the EOI.
+		if self.codes.len() >= 1 << code_size && code_size < 12 {
+			code_size += 1;
+		}
+		out.push_bits(code_size, self.end_of_information_code)
+	}
+}
+
+/// An LZW decoder that can be given the data a piece at a time, like as the
+/// sub-blocks of an image arrive. The bits of a code split across two pieces
+/// are kept until the rest of it shows up.
+pub(crate) struct Decompressor {
+	minimum_size: u8,
+	clear_code: u16,
+	end_of_information_code: u16,
+
+	// Each code is a prefix code and a suffix byte. The codes below the
+	// clear code are the literals and don't have a prefix.
+	prefix: Vec<u16>,
+	suffix: Vec<u8>,
+	lengths: Vec<u16>,
+
+	next_code: u16,
+	code_size: u8,
+	previous: Option<u16>,
+
+	/// The start of a code that the last piece of data ended in the middle of
+	bits: u16,
+	bit_count: u8,
+	/// Whether we've seen the End of Information code. Anything after it is
+	/// ignored.
+	finished: bool,
+	/// How many more indicies we'll decode before acting like we saw the
+	/// End of Information code. Keeps bad data from decompressing to far
+	/// more than the image can hold.
+	limit: Option<usize>,
+}
+
+impl Decompressor {
+	pub fn new(minimum_size: u8) -> Self {
+		let clear_code = 1u16 << minimum_size;
+
+		let prefix = vec![0; MAX_CODE as usize];
+		let mut suffix = vec![0; MAX_CODE as usize];
+		let mut lengths = vec![0; MAX_CODE as usize];
+		for literal in 0..clear_code {
+			suffix[literal as usize] = literal as u8;
+			lengths[literal as usize] = 1;
+		}
+
+		Self {
+			minimum_size,
+			clear_code,
+			end_of_information_code: clear_code + 1,
+			prefix,
+			suffix,
+			lengths,
+			next_code: clear_code + 2,
+			code_size: minimum_size + 1,
+			previous: None,
+			bits: 0,
+			bit_count: 0,
+			finished: false,
+			limit: None,
+		}
+	}
+
+	/// Stop after decoding `limit` indicies, ignoring anything after them.
+	pub fn limit(mut self, limit: usize) -> Self {
+		self.limit = Some(limit);
+		self
+	}
+
+	/// Decode the next piece of data into the end of `out`. If there's an
+	/// error, `out` has everything that was decoded before it.
+	pub fn push(&mut self, encoded: &[u8], out: &mut Vec<u8>) -> Result<(), DecodeError> {
+		let mut input = BitPopper::new(encoded);
+		let start = out.len();
+
+		while !self.finished {
+			let needed = self.code_size - self.bit_count;
+
+			if input.bits_left() < needed as usize {
+				// Keep what's left for the next piece
+				let left = input.bits_left() as u8;
+				if left > 0 {
+					self.bits |= input.pop_bits(left) << self.bit_count;
+					self.bit_count += left;
+				}
+
+				break;
+			}
+
+			let code = self.bits | (input.pop_bits(needed) << self.bit_count);
+			self.bits = 0;
+			self.bit_count = 0;
+
+			self.code(code, out)?;
+
+			if let Some(limit) = self.limit {
+				if out.len() - start >= limit {
+					out.truncate(start + limit);
+					self.finished = true;
 				}
 			}
 		}
 
-		let buffer = &indices[buffer_start..buffer_start + buffer_len];
-		if !buffer.is_empty() {
-			match self.dictionary.get(&buffer) {
-				Some(&code) => out.push_bits(code_size, code),
-				None => {
-					unreachable!(
-						"Codes left in the buffer but the buffer is not a valid dictionary key!"
-					)
+		if let Some(limit) = self.limit.as_mut() {
+			*limit -= out.len() - start;
+		}
+
+		Ok(())
+	}
+
+	fn code(&mut self, code: u16, out: &mut Vec<u8>) -> Result<(), DecodeError> {
+		if code == self.clear_code {
+			self.next_code = self.end_of_information_code + 1;
+			self.code_size = self.minimum_size + 1;
+			self.previous = None;
+			return Ok(());
+		} else if code == self.end_of_information_code {
+			self.finished = true;
+			return Ok(());
+		}
+
+		let prev = match self.previous {
+			None => {
+				// The first code after a clear has to be a literal
+				if code >= self.clear_code {
+					return Err(DecodeError::LzwInvalidCode {
+						code,
+						next_code: self.next_code,
+					});
 				}
+
+				out.push(code as u8);
+				self.previous = Some(code);
+				return Ok(());
+			}
+			Some(prev) => prev,
+		};
+
+		let (prefix, suffix, lengths) = (&self.prefix, &self.suffix, &self.lengths);
+		let start = out.len();
+		let first = if code < self.next_code {
+			push_code(out, code, prefix, suffix, lengths);
+			out[start]
+		} else if code == self.next_code && self.next_code < MAX_CODE {
+			// The code we're being asked for is the one we're about to
+			// make. It's the previous string plus its own first byte.
+			push_code(out, prev, prefix, suffix, lengths);
+			let first = out[start];
+			out.push(first);
+
+			first
+		} else {
+			return Err(DecodeError::LzwInvalidCode {
+				code,
+				next_code: self.next_code,
+			});
+		};
+
+		// Once the table is full we stop adding codes and wait for the
+		// encoder to send a clear code. This is the "deferred clear".
+		if self.next_code < MAX_CODE {
+			let next = self.next_code as usize;
+			self.prefix[next] = prev;
+			self.suffix[next] = first;
+			self.lengths[next] = self.lengths[prev as usize] + 1;
+			self.next_code += 1;
+
+			if self.next_code == 1 << self.code_size && self.code_size < 12 {
+				self.code_size += 1;
 			}
 		}
-		out.push_bits(code_size, self.end_of_information_code);
 
-		out.vec()
+		self.previous = Some(code);
+		Ok(())
+	}
+}
+
+/// Write the string that `code` represents to the end of `out`. The table is
+/// walked from the last byte to the first, so we make room first.
+fn push_code(out: &mut Vec<u8>, code: u16, prefix: &[u16], suffix: &[u8], lengths: &[u16]) {
+	let len = lengths[code as usize] as usize;
+	let start = out.len();
+	out.resize(start + len, 0);
+
+	let mut code = code;
+	for idx in (start..start + len).rev() {
+		out[idx] = suffix[code as usize];
+		code = prefix[code as usize];
 	}
 }
 
@@ -151,6 +479,10 @@ mod lzw_test {
 			.unwrap();
 
 		assert_eq!(us_decode, weezl_decode);
+
+		// And decode both of them ourselves
+		assert_eq!(LZW::new(lzw_size).decode(&weezl).unwrap(), indices);
+		assert_eq!(LZW::new(lzw_size).decode(&us).unwrap(), indices);
 	}
 
 	#[test]
@@ -184,38 +516,325 @@ mod lzw_test {
 
 		assert_eq!(lzout, output);
 	}
+
+	#[test]
+	fn encode_to_subblocks() {
+		let indices: Vec<u8> = (0..10_000u32).map(|n| (n * 31 % 251) as u8).collect();
+		let lzout = LZW::new(8).encode(&indices);
+
+		let mut streamed = vec![];
+		LZW::new(8).encode_to(&indices, &mut streamed).unwrap();
+
+		let mut expected = vec![];
+		for chunk in lzout.chunks(255) {
+			expected.push(chunk.len() as u8);
+			expected.extend_from_slice(chunk);
+		}
+		expected.push(0x00);
+
+		assert_eq!(streamed, expected);
+	}
+
+	#[test]
+	fn encode_empty() {
+		let lzout = LZW::new(2).encode(&[]);
+
+		// Clear code and then End of Information, both three bits
+		assert_eq!(lzout, vec![0b0010_1100]);
+		assert!(LZW::new(2).decode(&lzout).unwrap().is_empty());
+	}
+
+	/// A gray gradient of 64 colors, each 4 apart
+	fn grays() -> Palette {
+		let mut palette = Palette::new();
+		for gray in 0..64u8 {
+			palette.push(crate::Color::new(gray * 4, gray * 4, gray * 4));
+		}
+		palette
+	}
+
+	#[test]
+	fn lossy() {
+		// A gradient with a bit of noise
+		let mut seed = 1u32;
+		let indices: Vec<u8> = (0..64 * 64u32)
+			.map(|n| {
+				seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+				(n % 64) as u8 / 8 * 8 + (seed >> 16) as u8 % 3
+			})
+			.collect();
+
+		let lossless = LZW::new(6).encode(&indices);
+		let lossy = LZW::new(6)
+			.lossy(Lossy::new(grays(), 14).transparent_index(Some(57)))
+			.encode(&indices);
+		assert!(lossy.len() < lossless.len());
+
+		let decoded = LZW::new(6).decode(&lossy).unwrap();
+		assert_eq!(decoded.len(), indices.len());
+		for (&got, &want) in decoded.iter().zip(&indices) {
+			// The palette colors are sqrt(3 * 4^2) ~= 6.9 apart, so an
+			// index can move by at most two
+			assert!(got.abs_diff(want) <= 2);
+			assert_eq!(got == 57, want == 57);
+		}
+	}
+
+	#[test]
+	fn lossy_zero_distance_is_lossless() {
+		let indices: Vec<u8> = (0..4096u32).map(|n| (n * 13 % 61) as u8).collect();
+		let lossy = LZW::new(6).lossy(Lossy::new(grays(), 0)).encode(&indices);
+
+		assert_eq!(lossy, LZW::new(6).encode(&indices));
+	}
+
+	#[test]
+	fn decode() {
+		let indices = vec![0, 0, 0, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 0, 0, 0];
+		let encoded = [0x84, 0x1D, 0x81, 0x7A, 0x50];
+
+		let lzout = LZW::new(2).decode(&encoded).unwrap();
+
+		assert_eq!(lzout, indices);
+	}
+
+	#[test]
+	fn decode_without_eoi() {
+		let mut bs = BitStream::new();
+		bs.push_bits(3, 4);
+		bs.push_bits(3, 0);
+		bs.push_bits(3, 1);
+		bs.push_bits(3, 2);
+		// We've added two codes to the table now, so we're at four bits
+		bs.push_bits(4, 1);
+
+		let lzout = LZW::new(2).decode(&bs.vec()).unwrap();
+
+		assert_eq!(lzout, vec![0, 1, 2, 1]);
+	}
+
+	#[test]
+	fn decode_ignores_data_after_eoi() {
+		let indices = vec![0, 0, 0, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 0, 0, 0];
+		let encoded = [0x84, 0x1D, 0x81, 0x7A, 0x50, 0xFF, 0xFF];
+
+		let lzout = LZW::new(2).decode(&encoded).unwrap();
+
+		assert_eq!(lzout, indices);
+	}
+
+	#[test]
+	fn decode_invalid_code() {
+		// Clear code followed by code 7, which doesn't exist yet
+		let encoded = [0b0011_1100];
+
+		assert!(matches!(
+			LZW::new(2).decode(&encoded),
+			Err(DecodeError::LzwInvalidCode {
+				code: 7,
+				next_code: 6
+			})
+		));
+	}
+
+	#[test]
+	fn decode_deferred_clear() {
+		// Fill the table and keep going without a clear code. The decoder
+		// should stop adding codes and stay at 12 bits.
+		let mut bs = BitStream::new();
+		bs.push_bits(3, 4);
+
+		let mut next_code = 6;
+		let mut code_size = 3;
+		for idx in 0..5000 {
+			bs.push_bits(code_size, 0);
+
+			if idx > 0 && next_code < MAX_CODE {
+				next_code += 1;
+				if next_code == 1 << code_size && code_size < 12 {
+					code_size += 1;
+				}
+			}
+		}
+		bs.push_bits(code_size, 5);
+
+		let lzout = LZW::new(2).decode(&bs.vec()).unwrap();
+		assert_eq!(lzout, vec![0; 5000]);
+	}
+
+	#[test]
+	fn decompressor_in_pieces() {
+		let indices: Vec<u8> = (0..5000u32).map(|n| (n * n % 7) as u8).collect();
+		let encoded = LZW::new(3).encode(&indices);
+
+		// Every split lands somewhere different in a code
+		for piece in [1, 2, 3, 7, 255] {
+			let mut decompressor = Decompressor::new(3);
+			let mut out = vec![];
+			for chunk in encoded.chunks(piece) {
+				decompressor.push(chunk, &mut out).unwrap();
+			}
+
+			assert_eq!(out, indices);
+		}
+	}
+
+	#[test]
+	fn decompressor_limit() {
+		let indices: Vec<u8> = (0..5000u32).map(|n| (n * n % 7) as u8).collect();
+		let encoded = LZW::new(3).encode(&indices);
+
+		for piece in [1, 7, encoded.len()] {
+			let mut decompressor = Decompressor::new(3).limit(1234);
+			let mut out = vec![];
+			for chunk in encoded.chunks(piece) {
+				decompressor.push(chunk, &mut out).unwrap();
+			}
+
+			assert_eq!(out, indices[..1234]);
+		}
+	}
 }
 
+/// Something the encoder can push codes in to.
+trait CodeSink {
+	type Error;
+
+	fn push_bits(&mut self, count: u8, data: u16) -> Result<(), Self::Error>;
+}
+
+/// Packs codes of varying bit lengths into bytes, least significant bit first.
 struct BitStream {
-	formed: BitVec<u8, Lsb0>,
+	formed: Vec<u8>,
+	buffer: u32,
+	buffered_bits: u8,
 }
 
 impl BitStream {
 	fn new() -> Self {
 		Self {
-			formed: BitVec::EMPTY,
+			formed: vec![],
+			buffer: 0,
+			buffered_bits: 0,
 		}
 	}
 
 	fn push_bits(&mut self, count: u8, data: u16) {
-		for i in 0..count {
-			self.formed.push((data & (1 << i)) > 0)
+		let data = data as u32 & ((1 << count) - 1);
+		self.buffer |= data << self.buffered_bits;
+		self.buffered_bits += count;
+
+		while self.buffered_bits >= 8 {
+			self.formed.push(self.buffer as u8);
+			self.buffer >>= 8;
+			self.buffered_bits -= 8;
 		}
 	}
 
-	fn pop_bits(&mut self, count: u8) -> u16 {
-		let mut out = 0;
-		for i in (0..count).filter_map(|_| self.formed.pop()) {
-			out <<= 1;
-			let int: u16 = i.into();
-			out |= int;
+	fn vec(mut self) -> Vec<u8> {
+		if self.buffered_bits > 0 {
+			self.formed.push(self.buffer as u8);
 		}
-		out
+
+		self.formed
 	}
+}
 
-	fn vec(mut self) -> Vec<u8> {
-		self.formed.set_uninitialized(false);
-		self.formed.into_vec()
+impl CodeSink for BitStream {
+	type Error = Infallible;
+
+	fn push_bits(&mut self, count: u8, data: u16) -> Result<(), Self::Error> {
+		BitStream::push_bits(self, count, data);
+		Ok(())
+	}
+}
+
+/// Splits a stream of bytes into length-prefixed data sub-blocks of at most
+/// 255 bytes and writes them as soon as they're full.
+///
+/// As a [CodeSink] it packs codes the same way [BitStream] does. You can write
+/// bytes to it with [Write] as well, but don't mix the two.
+pub(crate) struct SubBlockWriter<W: Write> {
+	writer: W,
+	block: Vec<u8>,
+	buffer: u32,
+	buffered_bits: u8,
+}
+
+impl<W: Write> SubBlockWriter<W> {
+	pub fn new(writer: W) -> Self {
+		Self {
+			writer,
+			block: Vec::with_capacity(255),
+			buffer: 0,
+			buffered_bits: 0,
+		}
+	}
+
+	fn push_byte(&mut self, byte: u8) -> io::Result<()> {
+		self.block.push(byte);
+
+		if self.block.len() == 255 {
+			self.flush_block()?;
+		}
+
+		Ok(())
+	}
+
+	fn flush_block(&mut self) -> io::Result<()> {
+		if !self.block.is_empty() {
+			self.writer.write_all(&[self.block.len() as u8])?;
+			self.writer.write_all(&self.block)?;
+			self.block.clear();
+		}
+
+		Ok(())
+	}
+
+	/// Write out anything buffered and then the zero-length block that ends
+	/// the data.
+	pub fn finish(mut self) -> io::Result<W> {
+		if self.buffered_bits > 0 {
+			self.push_byte(self.buffer as u8)?;
+			self.buffered_bits = 0;
+		}
+
+		self.flush_block()?;
+		self.writer.write_all(&[0x00])?;
+
+		Ok(self.writer)
+	}
+}
+
+impl<W: Write> CodeSink for SubBlockWriter<W> {
+	type Error = io::Error;
+
+	fn push_bits(&mut self, count: u8, data: u16) -> Result<(), Self::Error> {
+		let data = data as u32 & ((1 << count) - 1);
+		self.buffer |= data << self.buffered_bits;
+		self.buffered_bits += count;
+
+		while self.buffered_bits >= 8 {
+			self.push_byte(self.buffer as u8)?;
+			self.buffer >>= 8;
+			self.buffered_bits -= 8;
+		}
+
+		Ok(())
+	}
+}
+
+impl<W: Write> Write for SubBlockWriter<W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		for &byte in buf {
+			self.push_byte(byte)?;
+		}
+
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.writer.flush()
 	}
 }
 
@@ -240,6 +859,11 @@ impl<'d> BitPopper<'d> {
 		Self { data, idx: 0 }
 	}
 
+	/// How many bits are left to pop
+	pub fn bits_left(&self) -> usize {
+		self.data.len() * 8 - self.idx as usize
+	}
+
 	pub fn pop_bits(&mut self, mut bit_count: u8) -> u16 {
 		let mut ret = 0u16;
 
@@ -269,7 +893,7 @@ impl<'d> BitPopper<'d> {
 		let bits_can_has = bits_left.min(bit_count);
 
 		let shifted = self.data[0] >> self.idx;
-		let result = shifted & MASKS[bits_can_has as usize];
+		let result = shifted & MASKS[bits_can_has as usize - 1];
 
 		self.idx += bits_can_has;
 		if self.idx >= 8 {
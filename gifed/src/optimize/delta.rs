@@ -0,0 +1,452 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+	block::{extension::DisposalMethod, Block, Palette},
+	quantize::pack,
+	render::{self, Frame},
+	writer::ImageBuilder,
+	Color, EncodeError, Gif, Image,
+};
+
+use super::{index_lookup, OptimizeError};
+
+/// Re-encode the images of an animation so that each only covers what
+/// changed since the frame before it.
+///
+/// The gif is rendered and each frame is compared to what's on the canvas
+/// before it's drawn. The image is cropped to the pixels that changed, the
+/// pixels inside that haven't changed are made transparent so they compress
+/// better, and a disposal method is picked for each image that leaves the
+/// least for the next one to draw. The optimized gif renders exactly the
+/// same as the original with [Gif::render].
+///
+/// Blocks that aren't images or Graphic Control Extensions are kept where
+/// they were. The user input flag of the Graphic Control Extensions is lost.
+///
+/// ## Errors
+/// - [OptimizeError::Decode] if the gif can't be rendered.
+/// - [OptimizeError::Encode] with [EncodeError::TooManyColors] if what
+///   changed has more than 256 colors. This can only happen when a disposal
+///   method brings back colors that aren't in the image's palette.
+/// - [OptimizeError::Undrawable] if a frame can't be drawn.
+pub fn optimize_frames(gif: &Gif) -> Result<Gif, OptimizeError> {
+	let frames: Vec<Frame> = gif.render().collect::<Result<_, _>>()?;
+	let images: Vec<Image> = gif.images().collect();
+	let palettes: Vec<(Palette, bool)> = images
+		.iter()
+		.map(|image| match image.compressed.palette() {
+			Some(local) => (local.clone(), true),
+			None => (image.global_palette.cloned().unwrap_or_default(), false),
+		})
+		.collect();
+
+	let width = gif.width() as usize;
+	let background = render::background(gif);
+
+	let mut out = Gif {
+		version: gif.version,
+		descriptor: gif.descriptor.clone(),
+		palette: gif.palette.clone(),
+		blocks: vec![],
+	};
+
+	let mut before = background.repeat(width * gif.height() as usize);
+	for (idx, frame) in frames.iter().enumerate() {
+		let bounds = Bounds::changed(&before, &frame.rgba, width).unwrap_or(Bounds {
+			left: 0,
+			top: 0,
+			right: 1.min(width),
+			bottom: 1.min(gif.height() as usize),
+		});
+
+		let (palette, local) = &palettes[idx];
+		let plan = Plan::new(&before, &frame.rgba, width, bounds, palette, *local)?;
+
+		// Pick whatever leaves the canvas closest to the next frame
+		let (disposal_method, after) = match frames.get(idx + 1) {
+			None => (DisposalMethod::NoAction, frame.rgba.clone()),
+			Some(next) => {
+				let mut cleared = frame.rgba.clone();
+				bounds.fill(&mut cleared, width, background);
+
+				[
+					(DisposalMethod::NoAction, frame.rgba.clone()),
+					(DisposalMethod::RestorePrevious, before.clone()),
+					(DisposalMethod::RestoreBackground, cleared),
+				]
+				.into_iter()
+				.filter(|(_, canvas)| drawable(canvas, &next.rgba))
+				.min_by_key(|(_, canvas)| {
+					Bounds::changed(canvas, &next.rgba, width).map_or(0, |b| b.area())
+				})
+				.ok_or(OptimizeError::Undrawable { frame: idx + 1 })?
+			}
+		};
+
+		let mut builder = ImageBuilder::new(bounds.width() as u16, bounds.height() as u16)
+			.offset(bounds.left as u16, bounds.top as u16)
+			.delay(frame.delay)
+			.disposal_method(disposal_method)
+			.transparent_index(plan.transparent_index);
+
+		if let Some(palette) = plan.palette {
+			builder = builder.palette(palette);
+		}
+
+		let built = builder.build(plan.indicies)?;
+
+		for block in images[idx].blocks {
+			match block {
+				Block::GraphicControlExtension(_) | Block::CompressedImage(_) => (),
+				other => out.blocks.push(other.clone()),
+			}
+		}
+		out.push(built);
+
+		before = after;
+	}
+
+	// Blocks after the last image
+	let last_image = gif
+		.blocks
+		.iter()
+		.rposition(|block| matches!(block, Block::CompressedImage(_)));
+	let trailing = last_image.map_or(0, |idx| idx + 1);
+	out.blocks.extend_from_slice(&gif.blocks[trailing..]);
+
+	Ok(out)
+}
+
+/// Whether `target` can be drawn on top of `canvas`. Images can't make
+/// pixels transparent again, so every pixel that changes has to be opaque.
+fn drawable(canvas: &[u8], target: &[u8]) -> bool {
+	canvas
+		.chunks(4)
+		.zip(target.chunks(4))
+		.all(|(have, want)| have == want || want[3] == 255)
+}
+
+/// A rectangle of the canvas. `right` and `bottom` are exclusive.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Bounds {
+	left: usize,
+	top: usize,
+	right: usize,
+	bottom: usize,
+}
+
+impl Bounds {
+	/// The smallest rectangle holding every pixel that's different between
+	/// two RGBA canvases. None if they're the same.
+	fn changed(a: &[u8], b: &[u8], width: usize) -> Option<Self> {
+		let mut bounds: Option<Bounds> = None;
+
+		for (idx, (pa, pb)) in a.chunks(4).zip(b.chunks(4)).enumerate() {
+			if pa == pb {
+				continue;
+			}
+
+			let (x, y) = (idx % width, idx / width);
+			bounds = Some(match bounds {
+				None => Bounds {
+					left: x,
+					top: y,
+					right: x + 1,
+					bottom: y + 1,
+				},
+				Some(b) => Bounds {
+					left: b.left.min(x),
+					top: b.top.min(y),
+					right: b.right.max(x + 1),
+					bottom: b.bottom.max(y + 1),
+				},
+			});
+		}
+
+		bounds
+	}
+
+	fn width(&self) -> usize {
+		self.right - self.left
+	}
+
+	fn height(&self) -> usize {
+		self.bottom - self.top
+	}
+
+	fn area(&self) -> usize {
+		self.width() * self.height()
+	}
+
+	/// The canvas index of every pixel, row by row.
+	fn pixels(&self, width: usize) -> impl Iterator<Item = usize> + '_ {
+		(self.top..self.bottom)
+			.flat_map(move |y| (self.left..self.right).map(move |x| y * width + x))
+	}
+
+	fn fill(&self, canvas: &mut [u8], width: usize, color: [u8; 4]) {
+		for idx in self.pixels(width) {
+			canvas[idx * 4..idx * 4 + 4].copy_from_slice(&color);
+		}
+	}
+}
+
+/// How one frame is going to be encoded.
+struct Plan {
+	/// None if it's the palette the image had, and that was the global one.
+	palette: Option<Palette>,
+	indicies: Vec<u8>,
+	transparent_index: Option<u8>,
+}
+
+impl Plan {
+	/// Work out the indicies for the pixels in `bounds`. The image's own
+	/// palette is used if it has all the colors, otherwise there's a new
+	/// local palette. Pixels that haven't changed are transparent if there's
+	/// an index free for it.
+	fn new(
+		before: &[u8],
+		target: &[u8],
+		width: usize,
+		bounds: Bounds,
+		palette: &Palette,
+		local: bool,
+	) -> Result<Self, OptimizeError> {
+		// The color of each pixel, or None if it hasn't changed
+		let region: Vec<Option<Color>> = bounds
+			.pixels(width)
+			.map(|idx| {
+				let px = &target[idx * 4..idx * 4 + 4];
+				if px == &before[idx * 4..idx * 4 + 4] {
+					None
+				} else {
+					Some(Color::new(px[0], px[1], px[2]))
+				}
+			})
+			.collect();
+
+		// Drawing every pixel only works if they're all opaque
+		let all: Option<Vec<Color>> = bounds
+			.pixels(width)
+			.map(|idx| {
+				let px = &target[idx * 4..idx * 4 + 4];
+				(px[3] == 255).then(|| Color::new(px[0], px[1], px[2]))
+			})
+			.collect();
+
+		let lookup = index_lookup(palette);
+		let original = if local { Some(palette.clone()) } else { None };
+
+		// The palette the image had, with the unchanged pixels transparent
+		let changed: Option<Vec<u8>> = region
+			.iter()
+			.flatten()
+			.map(|clr| lookup.get(&pack(*clr)).copied())
+			.collect();
+
+		if let Some(changed) = changed {
+			let mut palette = palette.clone();
+			if let Some(transparent) = free_index(&mut palette, local, &changed) {
+				return Ok(Plan {
+					palette: original.map(|_| palette),
+					indicies: transparent_fill(&region, &lookup, transparent),
+					transparent_index: Some(transparent),
+				});
+			}
+		}
+
+		// The palette the image had, drawing everything
+		if let Some(all) = &all {
+			let indicies: Option<Vec<u8>> = all
+				.iter()
+				.map(|clr| lookup.get(&pack(*clr)).copied())
+				.collect();
+
+			if let Some(indicies) = indicies {
+				return Ok(Plan {
+					palette: original,
+					indicies,
+					transparent_index: None,
+				});
+			}
+		}
+
+		// A new palette of just the colors that changed
+		let mut palette = unique(region.iter().flatten());
+		if palette.len() < 256 {
+			let transparent = palette.len() as u8;
+			palette.push(Color::new(0, 0, 0));
+
+			let lookup = index_lookup(&palette);
+			return Ok(Plan {
+				indicies: transparent_fill(&region, &lookup, transparent),
+				palette: Some(palette),
+				transparent_index: Some(transparent),
+			});
+		}
+
+		// A new palette of every color, drawing everything
+		if let Some(all) = all {
+			let palette = unique(all.iter());
+			if palette.len() <= 256 {
+				let lookup = index_lookup(&palette);
+				return Ok(Plan {
+					indicies: all.iter().map(|clr| lookup[&pack(*clr)]).collect(),
+					palette: Some(palette),
+					transparent_index: None,
+				});
+			}
+		}
+
+		Err(EncodeError::TooManyColors.into())
+	}
+}
+
+/// Find an index that none of the changed pixels use. The padding at the
+/// end of the palette is used first. If there isn't one and the palette is
+/// local, a color is added.
+fn free_index(palette: &mut Palette, local: bool, used: &[u8]) -> Option<u8> {
+	if palette.len() < palette.computed_len() {
+		return Some(palette.len() as u8);
+	}
+
+	let used: HashSet<u8> = used.iter().copied().collect();
+	if let Some(free) = (0..palette.len()).find(|idx| !used.contains(&(*idx as u8))) {
+		return Some(free as u8);
+	}
+
+	if local && palette.len() < 256 {
+		palette.push(Color::new(0, 0, 0));
+		return Some((palette.len() - 1) as u8);
+	}
+
+	None
+}
+
+fn transparent_fill(
+	region: &[Option<Color>],
+	lookup: &HashMap<u32, u8>,
+	transparent: u8,
+) -> Vec<u8> {
+	region
+		.iter()
+		.map(|px| match px {
+			None => transparent,
+			Some(clr) => lookup[&pack(*clr)],
+		})
+		.collect()
+}
+
+/// The first index of every color in the palette.
+fn unique<'a, I: Iterator<Item = &'a Color>>(colors: I) -> Palette {
+	let mut seen = HashSet::new();
+	let mut palette = Palette::new();
+
+	for clr in colors {
+		if seen.insert(pack(*clr)) {
+			palette.push(*clr);
+		}
+	}
+
+	palette
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn palette() -> Palette {
+		vec![(0, 0, 0), (255, 255, 255), (255, 0, 0)]
+			.try_into()
+			.unwrap()
+	}
+
+	/// Full frame images of a red dot moving across black
+	fn moving_dot() -> Gif {
+		let mut gif = Gif::new(8, 8);
+		gif.set_palette(Some(palette()));
+
+		for frame in 0..4 {
+			let mut indicies = vec![0; 64];
+			indicies[frame * 9] = 2;
+			gif.push(ImageBuilder::new(8, 8).delay(10).build(indicies).unwrap());
+		}
+
+		gif
+	}
+
+	fn assert_same(a: &Gif, b: &Gif) {
+		let a: Vec<Frame> = a.render().map(Result::unwrap).collect();
+		let b: Vec<Frame> = b.render().map(Result::unwrap).collect();
+
+		assert_eq!(a.len(), b.len());
+		for (a, b) in a.iter().zip(&b) {
+			assert_eq!(a.rgba, b.rgba);
+			assert_eq!(a.delay, b.delay);
+		}
+	}
+
+	#[test]
+	fn crops_to_changes() {
+		let gif = moving_dot();
+		let optimized = optimize_frames(&gif).unwrap();
+
+		assert_same(&gif, &optimized);
+		assert!(optimized.as_bytes().len() < gif.as_bytes().len());
+
+		let sizes: Vec<(u16, u16)> = optimized
+			.images()
+			.map(|image| (image.width(), image.height()))
+			.collect();
+
+		// The background is black, so the first frame is only the dot. Each
+		// dot is disposed of after it's shown, so the rest are only the dot
+		// too.
+		assert_eq!(sizes, vec![(1, 1); 4]);
+	}
+
+	#[test]
+	fn restores_previous() {
+		let mut gif = Gif::new(4, 4);
+		gif.set_palette(Some(palette()));
+
+		let mut dot = vec![1; 16];
+		dot[5] = 2;
+		for indicies in [vec![1; 16], dot, vec![1; 16]] {
+			gif.push(ImageBuilder::new(4, 4).delay(5).build(indicies).unwrap());
+		}
+
+		let optimized = optimize_frames(&gif).unwrap();
+		assert_same(&gif, &optimized);
+
+		// The dot blinks, so it's cheapest to put back what was under it
+		let disposals: Vec<DisposalMethod> = optimized
+			.images()
+			.map(|image| image.disposal_method())
+			.collect();
+		assert_eq!(disposals[1], DisposalMethod::RestorePrevious);
+	}
+
+	#[test]
+	fn keeps_local_palettes() {
+		let mut gif = Gif::new(4, 4);
+		for color in [(10, 20, 30), (40, 50, 60)] {
+			let mut indicies = vec![0; 16];
+			indicies[0] = 1;
+
+			let palette = vec![(0, 0, 0), color].try_into().unwrap();
+			gif.push(
+				ImageBuilder::new(4, 4)
+					.palette(palette)
+					.build(indicies)
+					.unwrap(),
+			);
+		}
+
+		let optimized = optimize_frames(&gif).unwrap();
+		assert_same(&gif, &optimized);
+
+		let second = optimized.images().nth(1).unwrap();
+		assert_eq!((second.width(), second.height()), (1, 1));
+	}
+}
@@ -0,0 +1,602 @@
+use std::fmt;
+
+use crate::{
+	block::{
+		extension::{DisposalMethod, GraphicControl},
+		Block, CompressedImage, IndexMap,
+	},
+	gif::ImageBlocks,
+	quantize::pack,
+	reader::{DecodeError, Decoder},
+	render::Frame,
+	Gif,
+};
+
+use super::{index_lookup, OptimizeError};
+
+/// Something [optimize] tries to make a gif smaller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+	/// Remove images that don't draw anything, giving their delay to the
+	/// image before them.
+	EmptyFrames,
+	/// Remove images that don't change what's on the canvas, giving their
+	/// delay to the image before them.
+	IdenticalFrames,
+	/// Remove local color tables that the global color table has all the
+	/// colors of, making one global if there isn't one.
+	LocalPalettes,
+	/// Remove the colors that no image uses from every color table.
+	TrimPalettes,
+	/// Use the smallest LZW minimum code size each image's indicies fit in.
+	CodeSize,
+	/// Compress every image again.
+	Recompress,
+}
+
+impl Step {
+	/// Every step, in the order [optimize] runs them.
+	pub const ALL: [Step; 6] = [
+		Step::EmptyFrames,
+		Step::IdenticalFrames,
+		Step::LocalPalettes,
+		Step::TrimPalettes,
+		Step::CodeSize,
+		Step::Recompress,
+	];
+
+	fn run(self, gif: &mut Gif) -> Result<(), OptimizeError> {
+		match self {
+			Step::EmptyFrames => remove_frames(gif, |gif, groups, _, idx| {
+				let disposal = disposal_method(gif, &groups[idx]);
+				Ok(disposal != DisposalMethod::RestoreBackground
+					&& draws_nothing(gif, &groups[idx])?)
+			}),
+			Step::IdenticalFrames => remove_frames(gif, |gif, groups, kept, idx| {
+				let before = disposal_method(gif, &groups[kept]);
+				let this = disposal_method(gif, &groups[idx]);
+
+				// The frames look the same, so if the canvas is left alone
+				// between them this image didn't change anything
+				let unchanged = !matches!(
+					before,
+					DisposalMethod::RestoreBackground | DisposalMethod::RestorePrevious
+				) && this != DisposalMethod::RestoreBackground;
+
+				// Drawing the same image twice in a row, disposing of it the
+				// same way each time, is the same as drawing it once
+				let repeated = kept + 1 == idx
+					&& image(gif, &groups[kept]).as_bytes() == image(gif, &groups[idx]).as_bytes()
+					&& same_control(
+						graphic_control(gif, &groups[kept]),
+						graphic_control(gif, &groups[idx]),
+					);
+
+				Ok(unchanged || repeated)
+			}),
+			Step::LocalPalettes => local_palettes(gif),
+			Step::TrimPalettes => trim_palettes(gif),
+			Step::CodeSize => recompress(gif, |_, needed| needed),
+			Step::Recompress => recompress(gif, |current, needed| current.max(needed)),
+		}
+	}
+}
+
+impl fmt::Display for Step {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Step::EmptyFrames => write!(f, "removed empty frames"),
+			Step::IdenticalFrames => write!(f, "merged identical frames"),
+			Step::LocalPalettes => write!(f, "removed local color tables"),
+			Step::TrimPalettes => write!(f, "trimmed color tables"),
+			Step::CodeSize => write!(f, "shrunk LZW code sizes"),
+			Step::Recompress => write!(f, "recompressed images"),
+		}
+	}
+}
+
+/// A gif that's gone through [optimize].
+#[derive(Clone, Debug)]
+pub struct Optimized {
+	pub gif: Gif,
+	/// How big the gif was before it was optimized, in bytes.
+	pub original_size: usize,
+	/// How many bytes each step saved, in the order they were run.
+	pub saved: Vec<(Step, usize)>,
+}
+
+impl Optimized {
+	/// How big the optimized gif is, in bytes.
+	pub fn size(&self) -> usize {
+		self.original_size - self.total_saved()
+	}
+
+	pub fn total_saved(&self) -> usize {
+		self.saved.iter().map(|(_, saved)| saved).sum()
+	}
+}
+
+/// Make a gif smaller without changing what it looks like, by running every
+/// [Step] over it in turn.
+///
+/// A step is only kept if it made the gif smaller and, once encoded and
+/// decoded again, it still renders the same with [Gif::render]. Frames that
+/// were merged are shown for as long as they were before. Steps that weren't
+/// kept are reported as saving nothing.
+///
+/// This doesn't change the area each image covers. Run
+/// [optimize_frames](super::optimize_frames) first for that.
+///
+/// ## Errors
+/// [OptimizeError::Decode] if the gif can't be rendered.
+pub fn optimize(gif: &Gif) -> Result<Optimized, OptimizeError> {
+	let timeline = timeline(gif)?;
+	let original_size = gif.as_bytes().len();
+
+	let mut optimized = Optimized {
+		gif: gif.clone(),
+		original_size,
+		saved: vec![],
+	};
+	let mut size = original_size;
+
+	for step in Step::ALL {
+		let mut candidate = optimized.gif.clone();
+		step.run(&mut candidate)?;
+
+		// Check what would be written, not just what's in memory, so that
+		// anything that doesn't survive encoding is caught
+		let bytes = candidate.as_bytes();
+		let candidate_size = bytes.len();
+		let looks_same = Decoder::new(bytes.as_slice())
+			.read_all()
+			.and_then(|decoded| self::timeline(&decoded))
+			.is_ok_and(|other| other == timeline);
+
+		if candidate_size < size && looks_same {
+			optimized.saved.push((step, size - candidate_size));
+			optimized.gif = candidate;
+			size = candidate_size;
+		} else {
+			optimized.saved.push((step, 0));
+		}
+	}
+
+	Ok(optimized)
+}
+
+/// What the gif shows and for how long. Frames that look the same as the
+/// one before them are merged in to it.
+fn timeline(gif: &Gif) -> Result<Vec<(Vec<u8>, u32)>, DecodeError> {
+	let mut timeline: Vec<(Vec<u8>, u32)> = vec![];
+
+	for frame in gif.render() {
+		let frame = frame?;
+		match timeline.last_mut() {
+			Some((rgba, delay)) if *rgba == frame.rgba => *delay += frame.delay as u32,
+			_ => timeline.push((frame.rgba, frame.delay as u32)),
+		}
+	}
+
+	Ok(timeline)
+}
+
+fn image<'a>(gif: &'a Gif, group: &ImageBlocks) -> &'a CompressedImage {
+	match &gif.blocks[group.image] {
+		Block::CompressedImage(image) => image,
+		_ => unreachable!(),
+	}
+}
+
+fn graphic_control<'a>(gif: &'a Gif, group: &ImageBlocks) -> Option<&'a GraphicControl> {
+	match &gif.blocks[group.gce?] {
+		Block::GraphicControlExtension(gce) => Some(gce),
+		_ => unreachable!(),
+	}
+}
+
+fn transparent_index(gif: &Gif, group: &ImageBlocks) -> Option<u8> {
+	graphic_control(gif, group).and_then(|gce| gce.transparent_index())
+}
+
+fn disposal_method(gif: &Gif, group: &ImageBlocks) -> DisposalMethod {
+	graphic_control(gif, group)
+		.and_then(|gce| gce.disposal_method())
+		.unwrap_or(DisposalMethod::NoAction)
+}
+
+fn delay(gif: &Gif, group: &ImageBlocks) -> u16 {
+	graphic_control(gif, group).map_or(0, |gce| gce.delay())
+}
+
+fn same_control(a: Option<&GraphicControl>, b: Option<&GraphicControl>) -> bool {
+	match (a, b) {
+		(None, None) => true,
+		(Some(a), Some(b)) => {
+			a.packed.raw == b.packed.raw && a.transparent_index() == b.transparent_index()
+		}
+		_ => false,
+	}
+}
+
+/// Whether an image is entirely off the canvas or entirely transparent.
+fn draws_nothing(gif: &Gif, group: &ImageBlocks) -> Result<bool, DecodeError> {
+	let image = image(gif, group);
+	if image.left() >= gif.width()
+		|| image.top() >= gif.height()
+		|| image.width() == 0
+		|| image.height() == 0
+	{
+		return Ok(true);
+	}
+
+	match transparent_index(gif, group) {
+		None => Ok(false),
+		Some(transparent) => Ok(image
+			.clone()
+			.decompress()?
+			.indicies
+			.iter()
+			.all(|&idx| idx == transparent)),
+	}
+}
+
+/// Remove the images that `removable` says can go, adding their delay on to
+/// the last image that's kept before them. `removable` is given the index of
+/// that image and the one that might be removed, and is only asked about
+/// images that render the same as it.
+fn remove_frames<F>(gif: &mut Gif, mut removable: F) -> Result<(), OptimizeError>
+where
+	F: FnMut(&Gif, &[ImageBlocks], usize, usize) -> Result<bool, DecodeError>,
+{
+	let frames: Vec<Frame> = gif.render().collect::<Result<_, _>>()?;
+	let groups = gif.image_blocks();
+
+	let mut delays: Vec<u16> = groups.iter().map(|group| delay(gif, group)).collect();
+	let mut removed = vec![false; groups.len()];
+	let mut kept = 0;
+
+	for idx in 1..groups.len() {
+		let group = &groups[idx];
+		let user_input = graphic_control(gif, group).is_some_and(|gce| gce.user_input());
+		let delay = delays[kept].checked_add(delays[idx]);
+
+		let remove = !group.plain_text
+			&& !user_input
+			&& frames[idx].rgba == frames[kept].rgba
+			&& delay.is_some()
+			&& removable(gif, &groups, kept, idx)?;
+
+		match delay {
+			Some(delay) if remove => {
+				removed[idx] = true;
+				delays[kept] = delay;
+			}
+			_ => kept = idx,
+		}
+	}
+
+	let mut blocks = Vec::with_capacity(gif.blocks.len());
+	let mut current = 0;
+
+	for (idx, block) in std::mem::take(&mut gif.blocks).into_iter().enumerate() {
+		let Some(group) = groups.get(current) else {
+			// Everything after the last image
+			blocks.push(block);
+			continue;
+		};
+
+		match block {
+			Block::CompressedImage(image) => {
+				if !removed[current] {
+					if group.gce.is_none() && delays[current] > 0 {
+						blocks.push(Block::GraphicControlExtension(GraphicControl::new(
+							DisposalMethod::NoAction,
+							false,
+							false,
+							delays[current],
+							0,
+						)));
+					}
+
+					blocks.push(Block::CompressedImage(image));
+				}
+
+				current += 1;
+			}
+			Block::GraphicControlExtension(_) if removed[current] => (),
+			Block::GraphicControlExtension(mut gce) if group.gce == Some(idx) => {
+				*gce.delay_mut() = delays[current];
+				blocks.push(Block::GraphicControlExtension(gce));
+			}
+			other => blocks.push(other),
+		}
+	}
+
+	gif.blocks = blocks;
+	Ok(())
+}
+
+/// Swap local color tables for the global one where it has every color the
+/// image uses. If there's no global color table, the first local one becomes
+/// the global one.
+fn local_palettes(gif: &mut Gif) -> Result<(), OptimizeError> {
+	if gif.palette.is_none() {
+		let first = gif
+			.images()
+			.find_map(|image| image.compressed.palette().cloned());
+
+		match first {
+			None => return Ok(()),
+			Some(palette) => gif.set_palette(Some(palette)),
+		}
+	}
+
+	let global = gif.palette.clone().unwrap_or_default();
+	let lookup = index_lookup(&global);
+
+	for (nth, blocks) in gif.image_blocks().into_iter().enumerate() {
+		let image = image(gif, &blocks);
+		let Some(local) = image.palette() else {
+			continue;
+		};
+
+		let transparent = transparent_index(gif, &blocks);
+		let counts = image.clone().decompress()?.index_counts();
+
+		// Where each index the image uses is in the global color table
+		let mut map = IndexMap::identity();
+		let fits = (0..=255u8)
+			.filter(|idx| counts[*idx as usize] > 0 && Some(*idx) != transparent)
+			.all(
+				|idx| match local.get(idx).and_then(|clr| lookup.get(&pack(clr))) {
+					Some(&global_idx) => {
+						map.set(idx, Some(global_idx));
+						true
+					}
+					None => false,
+				},
+			);
+
+		if fits {
+			if let Block::CompressedImage(image) = &mut gif.blocks[blocks.image] {
+				image.local_color_table = None;
+			}
+			gif.remap_image(nth, &map)?;
+		}
+	}
+
+	Ok(())
+}
+
+/// Remove the colors nobody uses from the global color table and from each
+/// local color table.
+fn trim_palettes(gif: &mut Gif) -> Result<(), OptimizeError> {
+	for (nth, blocks) in gif.image_blocks().into_iter().enumerate() {
+		let Block::CompressedImage(image) = &mut gif.blocks[blocks.image] else {
+			unreachable!()
+		};
+		let Some(local) = image.palette() else {
+			continue;
+		};
+
+		let mut palette = local.clone();
+		let map = palette.remove_unused(&image.clone().decompress()?.index_counts());
+
+		if palette.len() < local.len() {
+			image.local_color_table = Some(palette);
+			gif.remap_image(nth, &map)?;
+		}
+	}
+
+	let Some(global) = gif.palette.clone() else {
+		return Ok(());
+	};
+
+	let mut palette = global.clone();
+	let map = palette.remove_unused(&gif.global_index_counts()?);
+
+	if palette.len() < global.len() {
+		gif.set_palette(Some(palette));
+		gif.remap_global(&map)?;
+	}
+
+	Ok(())
+}
+
+/// Compress every image again with the LZW minimum code size `code_size`
+/// gives, from its current one and the smallest its indicies fit in. The
+/// new data is only kept if it's smaller.
+fn recompress<F>(gif: &mut Gif, code_size: F) -> Result<(), OptimizeError>
+where
+	F: Fn(u8, u8) -> u8,
+{
+	for blocks in gif.image_blocks() {
+		let Block::CompressedImage(image) = &mut gif.blocks[blocks.image] else {
+			unreachable!()
+		};
+		let indexed = image.clone().decompress()?;
+
+		let mcs = code_size(image.lzw_code_size, indexed.min_code_size());
+		let compressed = indexed.compress_with_code_size(mcs);
+
+		if data_len(&compressed) < data_len(image) {
+			*image = compressed;
+		}
+	}
+
+	Ok(())
+}
+
+fn data_len(image: &CompressedImage) -> usize {
+	image.blocks.iter().map(|block| block.len() + 1).sum()
+}
+
+/// The first index of every color in the palette.
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::{block::Palette, writer::ImageBuilder, Color};
+
+	fn palette() -> Palette {
+		vec![(0, 0, 0), (255, 255, 255), (255, 0, 0), (0, 0, 255)]
+			.try_into()
+			.unwrap()
+	}
+
+	/// Check `b` looks like `a` after going through bytes, since that's what
+	/// matters.
+	fn assert_same(a: &Gif, b: &Gif) {
+		let decoded = Decoder::new(b.as_bytes().as_slice()).read_all().unwrap();
+		assert_eq!(timeline(a).unwrap(), timeline(&decoded).unwrap());
+	}
+
+	fn delays(gif: &Gif) -> Vec<u16> {
+		gif.render().map(|frame| frame.unwrap().delay).collect()
+	}
+
+	#[test]
+	fn merges_identical_frames() {
+		let mut gif = Gif::new(4, 4);
+		gif.set_palette(Some(palette()));
+		for indicies in [vec![1; 16], vec![1; 16], vec![2; 16]] {
+			gif.push(ImageBuilder::new(4, 4).delay(10).build(indicies).unwrap());
+		}
+
+		let optimized = optimize(&gif).unwrap();
+		assert_same(&gif, &optimized.gif);
+		assert_eq!(delays(&optimized.gif), vec![20, 10]);
+	}
+
+	#[test]
+	fn removes_empty_frames() {
+		let mut gif = Gif::new(4, 4);
+		gif.set_palette(Some(palette()));
+		gif.push(
+			ImageBuilder::new(4, 4)
+				.delay(10)
+				.build(vec![1; 16])
+				.unwrap(),
+		);
+		gif.push(
+			ImageBuilder::new(2, 2)
+				.delay(5)
+				.transparent_index(Some(3))
+				.build(vec![3; 4])
+				.unwrap(),
+		);
+		gif.push(
+			ImageBuilder::new(4, 4)
+				.delay(10)
+				.build(vec![2; 16])
+				.unwrap(),
+		);
+
+		let optimized = optimize(&gif).unwrap();
+		assert_same(&gif, &optimized.gif);
+		assert_eq!(delays(&optimized.gif), vec![15, 10]);
+
+		let (step, saved) = optimized.saved[0];
+		assert_eq!(step, Step::EmptyFrames);
+		assert!(saved > 0);
+	}
+
+	#[test]
+	fn drops_local_palettes() {
+		let mut gif = Gif::new(4, 4);
+		gif.set_palette(Some(palette()));
+
+		// Red and black, the other way round from the global palette
+		let local: Palette = vec![(255, 0, 0), (0, 0, 0)].try_into().unwrap();
+		let mut indicies = vec![0; 16];
+		indicies[5] = 1;
+		gif.push(
+			ImageBuilder::new(4, 4)
+				.palette(local)
+				.build(indicies)
+				.unwrap(),
+		);
+
+		let optimized = optimize(&gif).unwrap();
+		assert_same(&gif, &optimized.gif);
+		assert!(optimized
+			.gif
+			.images()
+			.all(|image| image.compressed.palette().is_none()));
+	}
+
+	#[test]
+	fn trims_palettes() {
+		let mut palette = Palette::new();
+		for gray in 0..64 {
+			palette.push(Color::new(gray, gray, gray));
+		}
+
+		let mut gif = Gif::new(4, 4);
+		gif.set_palette(Some(palette));
+		gif.push(ImageBuilder::new(4, 4).build(vec![40; 16]).unwrap());
+		gif.push(
+			ImageBuilder::new(4, 4)
+				.delay(10)
+				.transparent_index(Some(63))
+				.build([63, 50, 40, 63].repeat(4))
+				.unwrap(),
+		);
+
+		let optimized = optimize(&gif).unwrap();
+		assert_same(&gif, &optimized.gif);
+
+		// The background, the two grays and the transparent index
+		assert_eq!(optimized.gif.palette.as_ref().unwrap().len(), 4);
+		assert_eq!(optimized.gif.descriptor.color_table_len(), 4);
+		assert!(optimized
+			.gif
+			.images()
+			.all(|image| image.compressed.lzw_code_size == 2));
+	}
+
+	#[test]
+	fn trims_local_palettes() {
+		let mut local = Palette::new();
+		for gray in 0..64 {
+			local.push(Color::new(gray, gray, gray));
+		}
+
+		let mut gif = Gif::new(4, 4);
+		gif.set_palette(Some(palette()));
+		gif.push(
+			ImageBuilder::new(4, 4)
+				.palette(local)
+				.build([10, 20].repeat(8))
+				.unwrap(),
+		);
+
+		let optimized = optimize(&gif).unwrap();
+		assert_same(&gif, &optimized.gif);
+
+		let image = optimized.gif.images().next().unwrap().compressed;
+		assert_eq!(image.palette().unwrap().len(), 2);
+		assert_eq!(image.image_descriptor.color_table_size(), 2);
+	}
+
+	#[test]
+	fn reports_savings() {
+		let mut gif = Gif::new(8, 8);
+		gif.set_palette(Some(palette()));
+		for _ in 0..3 {
+			gif.push(ImageBuilder::new(8, 8).delay(4).build(vec![3; 64]).unwrap());
+		}
+
+		let optimized = optimize(&gif).unwrap();
+		assert_eq!(optimized.size(), optimized.gif.as_bytes().len());
+		assert!(optimized.size() < gif.as_bytes().len());
+		assert_eq!(
+			optimized
+				.saved
+				.iter()
+				.map(|(step, _)| *step)
+				.collect::<Vec<_>>(),
+			Step::ALL
+		);
+	}
+}
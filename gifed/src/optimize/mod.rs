@@ -0,0 +1,58 @@
+//! Make existing GIFs smaller without changing what they look like.
+
+mod delta;
+mod file;
+
+use std::{collections::HashMap, error::Error, fmt};
+
+pub use delta::optimize_frames;
+pub use file::{optimize, Optimized, Step};
+
+use crate::{block::Palette, quantize::pack, DecodeError, EncodeError};
+
+#[derive(Debug)]
+pub enum OptimizeError {
+	/// The GIF being optimized couldn't be decoded.
+	Decode(DecodeError),
+	/// The optimized GIF couldn't be encoded.
+	Encode(EncodeError),
+	/// There's no way to draw this frame on top of the frame before it. This
+	/// happens when a frame has transparent pixels where the frame before
+	/// it had color and none of the disposal methods clear them.
+	Undrawable { frame: usize },
+}
+
+impl Error for OptimizeError {}
+impl fmt::Display for OptimizeError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			OptimizeError::Decode(error) => write!(f, "{error}"),
+			OptimizeError::Encode(error) => write!(f, "{error}"),
+			OptimizeError::Undrawable { frame } => {
+				write!(f, "Frame {frame} can't be drawn over the frame before it")
+			}
+		}
+	}
+}
+
+impl From<DecodeError> for OptimizeError {
+	fn from(error: DecodeError) -> Self {
+		OptimizeError::Decode(error)
+	}
+}
+
+impl From<EncodeError> for OptimizeError {
+	fn from(error: EncodeError) -> Self {
+		OptimizeError::Encode(error)
+	}
+}
+
+/// Where to find each color in `palette`. Colors that show up more than once
+/// map to the first of them.
+pub(crate) fn index_lookup(palette: &Palette) -> HashMap<u32, u8> {
+	let mut lookup = HashMap::new();
+	for (idx, clr) in palette.iter().enumerate() {
+		lookup.entry(pack(*clr)).or_insert(idx as u8);
+	}
+	lookup
+}
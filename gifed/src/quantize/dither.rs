@@ -0,0 +1,475 @@
+use std::collections::HashMap;
+
+use crate::{block::Palette, Color};
+
+use super::{distance, pack};
+
+/// How to spread out the difference between a pixel and the palette color
+/// it's mapped to, so gradients don't turn in to bands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DitherMethod {
+	/// Every pixel is the closest color. No dithering.
+	None,
+	/// Error diffusion to four neighbours.
+	FloydSteinberg,
+	/// Error diffusion to six neighbours. Only three quarters of the error is
+	/// passed on, so it keeps more contrast but loses detail in the darks
+	/// and lights.
+	Atkinson,
+	/// Error diffusion to ten neighbours over three rows. Smoother than
+	/// Floyd-Steinberg.
+	Sierra,
+	/// Ordered dithering with a 2x2 Bayer matrix.
+	Bayer2,
+	/// Ordered dithering with a 4x4 Bayer matrix.
+	Bayer4,
+	/// Ordered dithering with an 8x8 Bayer matrix.
+	Bayer8,
+}
+
+/// Map pixels on to a [Palette] with dithering.
+///
+/// Error diffusion passes what's left over from each pixel on to the ones
+/// after it. Ordered dithering nudges each pixel by a fixed pattern so it
+/// doesn't depend on its neighbours, which keeps the pattern still across
+/// the frames of an animation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Dither {
+	method: DitherMethod,
+	strength: f32,
+	serpentine: bool,
+}
+
+impl Dither {
+	pub fn new(method: DitherMethod) -> Self {
+		Self {
+			method,
+			strength: 1.0,
+			serpentine: true,
+		}
+	}
+
+	/// No dithering at all.
+	pub fn none() -> Self {
+		Self::new(DitherMethod::None)
+	}
+
+	/// How much of the error to pass on, or how strong the ordered pattern
+	/// is. Clamped to between 0 and 1, which is the default.
+	pub fn strength(mut self, strength: f32) -> Self {
+		self.strength = strength.clamp(0.0, 1.0);
+		self
+	}
+
+	/// Go right to left on every other row when diffusing error, so the error
+	/// doesn't always drift the same direction. On by default.
+	pub fn serpentine(mut self, serpentine: bool) -> Self {
+		self.serpentine = serpentine;
+		self
+	}
+
+	pub fn method(&self) -> DitherMethod {
+		self.method
+	}
+
+	/// Map every pixel to an index in the palette.
+	///
+	/// # Panics
+	/// If the palette is empty.
+	pub fn remap(&self, pixels: &[Color], width: usize, palette: &Palette) -> Vec<u8> {
+		self.remap_with(pixels.len(), width, palette, None, |idx| Some(pixels[idx]))
+	}
+
+	/// Like [Dither::remap] for a buffer of RGB data, three bytes a pixel.
+	pub fn remap_rgb(&self, rgb: &[u8], width: usize, palette: &Palette) -> Vec<u8> {
+		self.remap_with(rgb.len() / 3, width, palette, None, |idx| {
+			Some(Color::new(rgb[idx * 3], rgb[idx * 3 + 1], rgb[idx * 3 + 2]))
+		})
+	}
+
+	/// Like [Dither::remap] for a buffer of RGBA data, four bytes a pixel.
+	/// Pixels with an alpha below 128 become `transparent_index` and don't
+	/// take part in the dithering. If there's no transparent index, alpha is
+	/// ignored.
+	pub fn remap_rgba(
+		&self,
+		rgba: &[u8],
+		width: usize,
+		palette: &Palette,
+		transparent_index: Option<u8>,
+	) -> Vec<u8> {
+		self.remap_alpha(rgba, width, palette, transparent_index, 128)
+	}
+
+	/// [Dither::remap_rgba] with pixels that have an alpha below `threshold`
+	/// being transparent.
+	pub(crate) fn remap_alpha(
+		&self,
+		rgba: &[u8],
+		width: usize,
+		palette: &Palette,
+		transparent_index: Option<u8>,
+		threshold: u8,
+	) -> Vec<u8> {
+		self.remap_with(rgba.len() / 4, width, palette, transparent_index, |idx| {
+			let px = &rgba[idx * 4..idx * 4 + 4];
+
+			if transparent_index.is_some() && px[3] < threshold {
+				None
+			} else {
+				Some(Color::new(px[0], px[1], px[2]))
+			}
+		})
+	}
+
+	/// `pixel` gives the color at an index, or None if it's transparent. It
+	/// should only give None if there's a transparent index. Opaque pixels
+	/// are never mapped to the transparent index.
+	fn remap_with<F>(
+		&self,
+		len: usize,
+		width: usize,
+		palette: &Palette,
+		transparent_index: Option<u8>,
+		pixel: F,
+	) -> Vec<u8>
+	where
+		F: Fn(usize) -> Option<Color>,
+	{
+		let mut nearest = Nearest::new(palette, transparent_index);
+		let transparent = transparent_index.unwrap_or(0);
+		let width = width.max(1);
+
+		match self.method {
+			DitherMethod::None => (0..len)
+				.map(|idx| match pixel(idx) {
+					None => transparent,
+					Some(color) => nearest.index(color),
+				})
+				.collect(),
+			DitherMethod::Bayer2 => self.ordered(len, width, &mut nearest, transparent, pixel, 2),
+			DitherMethod::Bayer4 => self.ordered(len, width, &mut nearest, transparent, pixel, 4),
+			DitherMethod::Bayer8 => self.ordered(len, width, &mut nearest, transparent, pixel, 8),
+			DitherMethod::FloydSteinberg => self.diffuse(
+				len,
+				width,
+				&mut nearest,
+				transparent,
+				pixel,
+				FLOYD_STEINBERG,
+			),
+			DitherMethod::Atkinson => {
+				self.diffuse(len, width, &mut nearest, transparent, pixel, ATKINSON)
+			}
+			DitherMethod::Sierra => {
+				self.diffuse(len, width, &mut nearest, transparent, pixel, SIERRA)
+			}
+		}
+	}
+
+	fn ordered<F>(
+		&self,
+		len: usize,
+		width: usize,
+		nearest: &mut Nearest,
+		transparent: u8,
+		pixel: F,
+		size: usize,
+	) -> Vec<u8>
+	where
+		F: Fn(usize) -> Option<Color>,
+	{
+		let matrix = bayer(size);
+
+		// Roughly the distance between palette colors, if they were spread
+		// evenly, so the pattern can reach the next color over
+		let spread = 255.0 / (nearest.palette.len() as f32).cbrt() * self.strength;
+
+		(0..len)
+			.map(|idx| {
+				let Some(color) = pixel(idx) else {
+					return transparent;
+				};
+
+				let (x, y) = (idx % width, idx / width);
+				let threshold = (matrix[(y % size) * size + x % size] as f32 + 0.5)
+					/ (size * size) as f32
+					- 0.5;
+				let offset = threshold * spread;
+
+				nearest.index(Color::new(
+					clamp(color.r as f32 + offset),
+					clamp(color.g as f32 + offset),
+					clamp(color.b as f32 + offset),
+				))
+			})
+			.collect()
+	}
+
+	fn diffuse<F>(
+		&self,
+		len: usize,
+		width: usize,
+		nearest: &mut Nearest,
+		transparent: u8,
+		pixel: F,
+		kernel: Kernel,
+	) -> Vec<u8>
+	where
+		F: Fn(usize) -> Option<Color>,
+	{
+		let height = len.div_ceil(width);
+		let mut errors = vec![[0f32; 3]; len];
+		let mut indicies = vec![transparent; len];
+
+		for y in 0..height {
+			let reverse = self.serpentine && y % 2 == 1;
+
+			for step in 0..width {
+				let x = if reverse { width - 1 - step } else { step };
+				let idx = y * width + x;
+				if idx >= len {
+					continue;
+				}
+
+				let Some(color) = pixel(idx) else {
+					continue;
+				};
+
+				let err = errors[idx];
+				let wanted = [
+					color.r as f32 + err[0],
+					color.g as f32 + err[1],
+					color.b as f32 + err[2],
+				];
+
+				let index = nearest.index(Color::new(
+					clamp(wanted[0]),
+					clamp(wanted[1]),
+					clamp(wanted[2]),
+				));
+				indicies[idx] = index;
+
+				let got = nearest.palette.get(index).unwrap();
+				let diff = [
+					(wanted[0] - got.r as f32) * self.strength,
+					(wanted[1] - got.g as f32) * self.strength,
+					(wanted[2] - got.b as f32) * self.strength,
+				];
+
+				for &(dx, dy, weight) in kernel.weights {
+					// Going backwards mirrors the kernel
+					let dx = if reverse { -dx } else { dx };
+					let nx = x as isize + dx;
+					let ny = y + dy;
+
+					if nx < 0 || nx >= width as isize {
+						continue;
+					}
+
+					let nidx = ny * width + nx as usize;
+					if nidx >= len {
+						continue;
+					}
+
+					let share = weight as f32 / kernel.divisor as f32;
+					for channel in 0..3 {
+						errors[nidx][channel] += diff[channel] * share;
+					}
+				}
+			}
+		}
+
+		indicies
+	}
+}
+
+impl Default for Dither {
+	fn default() -> Self {
+		Self::none()
+	}
+}
+
+/// An error diffusion kernel. The weights are (x, y, weight) from the
+/// current pixel and each weight is divided by `divisor`.
+#[derive(Clone, Copy)]
+struct Kernel {
+	weights: &'static [(isize, usize, u8)],
+	divisor: u8,
+}
+
+#[rustfmt::skip]
+const FLOYD_STEINBERG: Kernel = Kernel {
+	weights: &[
+		                       (1, 0, 7),
+		(-1, 1, 3), (0, 1, 5), (1, 1, 1),
+	],
+	divisor: 16,
+};
+
+#[rustfmt::skip]
+const ATKINSON: Kernel = Kernel {
+	weights: &[
+		                       (1, 0, 1), (2, 0, 1),
+		(-1, 1, 1), (0, 1, 1), (1, 1, 1),
+		            (0, 2, 1),
+	],
+	divisor: 8,
+};
+
+#[rustfmt::skip]
+const SIERRA: Kernel = Kernel {
+	weights: &[
+		                                   (1, 0, 5), (2, 0, 3),
+		(-2, 1, 2), (-1, 1, 4), (0, 1, 5), (1, 1, 4), (2, 1, 2),
+		            (-1, 2, 2), (0, 2, 3), (1, 2, 2),
+	],
+	divisor: 32,
+};
+
+/// A `size` by `size` Bayer matrix, row by row. `size` must be a power of two.
+fn bayer(size: usize) -> Vec<u8> {
+	let mut matrix = vec![0u8];
+	let mut current = 1;
+
+	while current < size {
+		let next = current * 2;
+		let mut bigger = vec![0; next * next];
+
+		for y in 0..current {
+			for x in 0..current {
+				let value = matrix[y * current + x] * 4;
+				bigger[y * next + x] = value;
+				bigger[y * next + x + current] = value + 2;
+				bigger[(y + current) * next + x] = value + 3;
+				bigger[(y + current) * next + x + current] = value + 1;
+			}
+		}
+
+		matrix = bigger;
+		current = next;
+	}
+
+	matrix
+}
+
+fn clamp(value: f32) -> u8 {
+	value.round().clamp(0.0, 255.0) as u8
+}
+
+/// Finds the closest palette color, other than the transparent one,
+/// remembering the colors it's seen.
+struct Nearest<'a> {
+	palette: &'a Palette,
+	transparent: Option<u8>,
+	cache: HashMap<u32, u8>,
+}
+
+impl<'a> Nearest<'a> {
+	fn new(palette: &'a Palette, transparent: Option<u8>) -> Self {
+		Self {
+			palette,
+			transparent,
+			cache: HashMap::new(),
+		}
+	}
+
+	fn index(&mut self, color: Color) -> u8 {
+		let Self {
+			palette,
+			transparent,
+			cache,
+		} = self;
+
+		*cache.entry(pack(color)).or_insert_with(|| {
+			palette
+				.iter()
+				.enumerate()
+				.filter(|(idx, _)| Some(*idx as u8) != *transparent)
+				.min_by_key(|(_, clr)| distance(color, **clr))
+				.map(|(idx, _)| idx as u8)
+				// If the transparent color is all there is, we have to use it
+				.or(*transparent)
+				.expect("the palette is empty")
+		})
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn black_and_white() -> Palette {
+		vec![(0, 0, 0), (255, 255, 255)].try_into().unwrap()
+	}
+
+	/// Mid gray, which should dither to half black and half white
+	fn gray(len: usize) -> Vec<Color> {
+		vec![Color::new(128, 128, 128); len]
+	}
+
+	#[test]
+	fn bayer_matrices() {
+		assert_eq!(bayer(2), vec![0, 2, 3, 1]);
+
+		let mut eight = bayer(8);
+		eight.sort();
+		assert_eq!(eight, (0..64).collect::<Vec<u8>>());
+	}
+
+	#[test]
+	fn gray_is_half_white() {
+		let palette = black_and_white();
+		let pixels = gray(16 * 16);
+
+		for method in [
+			DitherMethod::FloydSteinberg,
+			DitherMethod::Sierra,
+			DitherMethod::Bayer2,
+			DitherMethod::Bayer4,
+			DitherMethod::Bayer8,
+		] {
+			let indicies = Dither::new(method).remap(&pixels, 16, &palette);
+			let white = indicies.iter().filter(|&&idx| idx == 1).count();
+
+			assert!((112..=144).contains(&white), "{method:?} had {white} white");
+		}
+
+		// Without dithering it's all one color
+		let indicies = Dither::none().remap(&pixels, 16, &palette);
+		assert!(indicies.iter().all(|&idx| idx == indicies[0]));
+	}
+
+	#[test]
+	fn zero_strength_is_no_dither() {
+		let palette = black_and_white();
+		let pixels = gray(64);
+
+		let none = Dither::none().remap(&pixels, 8, &palette);
+		for method in [DitherMethod::Atkinson, DitherMethod::Bayer4] {
+			let weak = Dither::new(method)
+				.strength(0.0)
+				.remap(&pixels, 8, &palette);
+			assert_eq!(weak, none);
+		}
+	}
+
+	#[test]
+	fn rgba_transparent_pixels() {
+		// The transparent color is gray, but no opaque pixel should use it
+		let mut palette = black_and_white();
+		palette.push(Color::new(128, 128, 128));
+
+		let rgba = [128, 128, 128, 255, 128, 128, 128, 0].repeat(8);
+		let indicies =
+			Dither::new(DitherMethod::FloydSteinberg).remap_rgba(&rgba, 4, &palette, Some(2));
+
+		for (idx, index) in indicies.iter().enumerate() {
+			if idx % 2 == 1 {
+				assert_eq!(*index, 2);
+			} else {
+				assert_ne!(*index, 2);
+			}
+		}
+	}
+}
@@ -0,0 +1,81 @@
+use crate::{block::Palette, Color};
+
+use super::{average, distance, histogram, mediancut::median_cut, Quantizer};
+
+/// K-means quantization.
+///
+/// Starts with the palette [MedianCut](super::MedianCut) would pick, then
+/// repeatedly moves each color to the average of the pixels closest to it.
+/// This stops after [KMeans::iterations] rounds or when nothing changes.
+#[derive(Clone, Copy, Debug)]
+pub struct KMeans {
+	iterations: usize,
+}
+
+impl KMeans {
+	pub fn new() -> Self {
+		Self { iterations: 8 }
+	}
+
+	/// The most rounds of refinement to do. Defaults to 8.
+	pub fn iterations(mut self, iterations: usize) -> Self {
+		self.iterations = iterations;
+		self
+	}
+}
+
+impl Default for KMeans {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Quantizer for KMeans {
+	fn palette(&self, pixels: &[Color], max_colors: usize) -> Palette {
+		let histogram = histogram(pixels);
+
+		let mut centers: Vec<Color> = median_cut(histogram.clone(), max_colors)
+			.iter()
+			.map(average)
+			.collect();
+
+		let mut clusters: Vec<Vec<(Color, u32)>> = vec![vec![]; centers.len()];
+		for _ in 0..self.iterations {
+			for cluster in clusters.iter_mut() {
+				cluster.clear();
+			}
+
+			for &(color, count) in &histogram {
+				let nearest = (0..centers.len())
+					.min_by_key(|&idx| distance(color, centers[idx]))
+					.unwrap();
+				clusters[nearest].push((color, count));
+			}
+
+			let mut moved = false;
+			for (center, cluster) in centers.iter_mut().zip(&clusters) {
+				// A center nobody is closest to stays where it is
+				if cluster.is_empty() {
+					continue;
+				}
+
+				let mean = average(cluster);
+				if mean != *center {
+					*center = mean;
+					moved = true;
+				}
+			}
+
+			if !moved {
+				break;
+			}
+		}
+
+		let mut palette = Palette::new();
+		for center in centers {
+			palette.push(center);
+		}
+
+		palette
+	}
+}
@@ -0,0 +1,117 @@
+use crate::{block::Palette, Color};
+
+use super::{average, histogram, Quantizer};
+
+/// Median cut quantization.
+///
+/// All the colors start in one box. The box with the widest spread of
+/// colors is split in two at the median of its widest channel, and that's
+/// repeated until there are as many boxes as colors we want. Each box then
+/// becomes the average of the colors in it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MedianCut;
+
+impl Quantizer for MedianCut {
+	fn palette(&self, pixels: &[Color], max_colors: usize) -> Palette {
+		let mut palette = Palette::new();
+		for colors in median_cut(histogram(pixels), max_colors) {
+			palette.push(average(&colors));
+		}
+
+		palette
+	}
+}
+
+/// Split the histogram in to at most `max_boxes` boxes.
+pub(crate) fn median_cut(histogram: Vec<(Color, u32)>, max_boxes: usize) -> Vec<Vec<(Color, u32)>> {
+	if histogram.is_empty() {
+		return vec![];
+	}
+
+	let mut boxes = vec![ColorBox::new(histogram)];
+
+	while boxes.len() < max_boxes {
+		// Boxes of a single color can't be split
+		let widest = boxes
+			.iter()
+			.enumerate()
+			.filter(|(_, cbox)| cbox.colors.len() > 1)
+			.max_by_key(|(_, cbox)| cbox.score())
+			.map(|(idx, _)| idx);
+
+		let Some(widest) = widest else {
+			break;
+		};
+
+		let (low, high) = boxes.swap_remove(widest).split();
+		boxes.push(low);
+		boxes.push(high);
+	}
+
+	boxes.into_iter().map(|cbox| cbox.colors).collect()
+}
+
+struct ColorBox {
+	colors: Vec<(Color, u32)>,
+	/// The number of pixels in this box
+	pixels: u64,
+	/// The channel with the largest range, and that range
+	channel: usize,
+	range: u8,
+}
+
+impl ColorBox {
+	fn new(colors: Vec<(Color, u32)>) -> Self {
+		let pixels = colors.iter().map(|(_, count)| *count as u64).sum();
+
+		let mut min = [255u8; 3];
+		let mut max = [0u8; 3];
+		for (color, _) in &colors {
+			for (channel, value) in [color.r, color.g, color.b].into_iter().enumerate() {
+				min[channel] = min[channel].min(value);
+				max[channel] = max[channel].max(value);
+			}
+		}
+
+		let (channel, range) = (0..3)
+			.map(|channel| (channel, max[channel] - min[channel]))
+			.max_by_key(|(_, range)| *range)
+			.unwrap();
+
+		Self {
+			colors,
+			pixels,
+			channel,
+			range,
+		}
+	}
+
+	/// How much we want to split this box. Big boxes with lots of pixels in
+	/// them go first.
+	fn score(&self) -> u64 {
+		self.range as u64 * self.pixels
+	}
+
+	/// Split at the median pixel of the widest channel. Both halves always
+	/// have at least one color.
+	fn split(mut self) -> (ColorBox, ColorBox) {
+		let channel = self.channel;
+		self.colors
+			.sort_unstable_by_key(|(color, _)| [color.r, color.g, color.b][channel]);
+
+		let half = self.pixels / 2;
+		let mut seen = 0;
+		let mut at = 1;
+		for (idx, (_, count)) in self.colors.iter().enumerate() {
+			seen += *count as u64;
+			if seen >= half {
+				at = idx + 1;
+				break;
+			}
+		}
+
+		let at = at.clamp(1, self.colors.len() - 1);
+		let high = self.colors.split_off(at);
+		(ColorBox::new(self.colors), ColorBox::new(high))
+	}
+}
@@ -0,0 +1,477 @@
+//! Reduce truecolor images to the 256 colors, or fewer, that a GIF can hold.
+//!
+//! A [Quantizer] picks the colors and [Quantizer::quantize] maps every pixel
+//! to the closest of them. There are three built in:
+//! - [MedianCut] splits the colors into boxes. It's quick and a good default.
+//! - [Octree] groups colors that are close in a tree. It's quick and keeps
+//!   colors that only cover a few pixels better than median cut.
+//! - [KMeans] refines a median cut palette. It's the slowest but usually
+//!   looks the best.
+//!
+//! Pixels can be [dithered](Dither) on to the palette to hide banding.
+
+mod dither;
+mod kmeans;
+mod mediancut;
+mod octree;
+
+use std::collections::HashMap;
+
+pub use dither::{Dither, DitherMethod};
+pub use kmeans::KMeans;
+pub use mediancut::MedianCut;
+pub use octree::Octree;
+
+use crate::{block::Palette, Color};
+
+pub trait Quantizer {
+	/// Choose at most `max_colors` colors that represent `pixels` well.
+	fn palette(&self, pixels: &[Color], max_colors: usize) -> Palette;
+
+	/// Choose a palette for `pixels` and map every pixel to it, dithering if
+	/// the options say to. `width` is the width of the image in pixels. If
+	/// the pixels have no more colors than are allowed, they're used exactly.
+	fn quantize(&self, pixels: &[Color], width: usize, options: QuantizeOptions) -> Quantized {
+		let mut palette = choose_palette(self, pixels, options.image_colors());
+		let indicies = options.dither.remap(pixels, width, &palette);
+
+		let transparent_index = if options.reserve_transparent {
+			palette.push(Color::new(0, 0, 0));
+			Some((palette.len() - 1) as u8)
+		} else {
+			None
+		};
+
+		Quantized {
+			palette,
+			indicies,
+			transparent_index,
+		}
+	}
+
+	/// Like [Quantizer::quantize] for a buffer of RGBA data, four bytes a
+	/// pixel. Pixels with an alpha below the options'
+	/// [alpha threshold](QuantizeOptions::alpha_threshold) are transparent.
+	/// If there are any, a transparent color is reserved even if the options
+	/// don't ask for one.
+	fn quantize_rgba(&self, rgba: &[u8], width: usize, options: QuantizeOptions) -> Quantized {
+		let threshold = options.alpha_threshold;
+		let opaque: Vec<Color> = rgba
+			.chunks_exact(4)
+			.filter(|px| px[3] >= threshold)
+			.map(|px| Color::new(px[0], px[1], px[2]))
+			.collect();
+
+		let options = options
+			.reserve_transparent(options.reserve_transparent || opaque.len() < rgba.len() / 4);
+		let mut palette = choose_palette(self, &opaque, options.image_colors());
+
+		let transparent_index = if options.reserve_transparent {
+			palette.push(Color::new(0, 0, 0));
+			Some((palette.len() - 1) as u8)
+		} else {
+			None
+		};
+
+		let indicies =
+			options
+				.dither
+				.remap_alpha(rgba, width, &palette, transparent_index, threshold);
+
+		Quantized {
+			palette,
+			indicies,
+			transparent_index,
+		}
+	}
+}
+
+/// Choose one palette for several images, like the frames of an animation,
+/// from a sample of their pixels. Every `step`th pixel of each image is
+/// looked at.
+///
+/// The palette has room for the transparent color if the options reserve
+/// one, but it isn't added. The dither option isn't used.
+pub fn sample_palette<Q: Quantizer + ?Sized>(
+	quantizer: &Q,
+	images: &[&[Color]],
+	step: usize,
+	options: QuantizeOptions,
+) -> Palette {
+	let sample: Vec<Color> = images
+		.iter()
+		.flat_map(|image| image.iter().step_by(step.max(1)))
+		.copied()
+		.collect();
+
+	choose_palette(quantizer, &sample, options.image_colors())
+}
+
+/// Use the colors as they are if there's few enough, otherwise have the
+/// quantizer pick.
+fn choose_palette<Q: Quantizer + ?Sized>(
+	quantizer: &Q,
+	pixels: &[Color],
+	max_colors: usize,
+) -> Palette {
+	let histogram = histogram(pixels);
+
+	let mut palette = if histogram.len() <= max_colors {
+		let mut palette = Palette::new();
+		for (color, _) in &histogram {
+			palette.push(*color);
+		}
+		palette
+	} else {
+		quantizer.palette(pixels, max_colors)
+	};
+
+	// A GIF palette can't be empty
+	if palette.is_empty() {
+		palette.push(Color::new(0, 0, 0));
+	}
+
+	palette
+}
+
+/// How to [quantize](Quantizer::quantize) an image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QuantizeOptions {
+	/// The most colors the palette can have, including the transparent
+	/// color if there is one. Between 1 and 256.
+	pub max_colors: usize,
+	/// Keep the last entry of the palette free so it can be used as the
+	/// transparent index.
+	pub reserve_transparent: bool,
+	/// How to map the pixels on to the palette.
+	pub dither: Dither,
+	/// Pixels with an alpha below this are transparent when quantizing RGBA
+	/// data. Zero makes every pixel opaque.
+	pub alpha_threshold: u8,
+}
+
+impl QuantizeOptions {
+	pub fn new() -> Self {
+		Self {
+			max_colors: 256,
+			reserve_transparent: false,
+			dither: Dither::none(),
+			alpha_threshold: 128,
+		}
+	}
+
+	/// The most colors the palette can have. It's clamped to between 1 and
+	/// 256, or 2 and 256 if a transparent color is reserved.
+	pub fn max_colors(mut self, max_colors: usize) -> Self {
+		self.max_colors = max_colors;
+		self
+	}
+
+	pub fn reserve_transparent(mut self, reserve: bool) -> Self {
+		self.reserve_transparent = reserve;
+		self
+	}
+
+	pub fn dither(mut self, dither: Dither) -> Self {
+		self.dither = dither;
+		self
+	}
+
+	/// Pixels with an alpha below `threshold` are transparent. It's 128 by
+	/// default.
+	pub fn alpha_threshold(mut self, threshold: u8) -> Self {
+		self.alpha_threshold = threshold;
+		self
+	}
+
+	/// How many colors are left for the image itself.
+	pub(crate) fn image_colors(&self) -> usize {
+		let max_colors = self.max_colors.clamp(1, 256);
+
+		if self.reserve_transparent {
+			max_colors.saturating_sub(1).max(1)
+		} else {
+			max_colors
+		}
+	}
+}
+
+impl Default for QuantizeOptions {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// A quantized image.
+#[derive(Clone, Debug)]
+pub struct Quantized {
+	pub palette: Palette,
+	/// An index into the palette for every pixel.
+	pub indicies: Vec<u8>,
+	/// The reserved transparent index, if one was asked for. None of the
+	/// indicies will be this.
+	pub transparent_index: Option<u8>,
+}
+
+/// Map every pixel to the closest color in the palette.
+///
+/// # Panics
+/// If the palette is empty.
+pub fn map_to_palette(pixels: &[Color], palette: &Palette) -> Vec<u8> {
+	// Images tend to repeat colors a lot so we remember what we've found
+	let mut cache = HashMap::new();
+
+	pixels
+		.iter()
+		.map(|&color| {
+			*cache
+				.entry(pack(color))
+				.or_insert_with(|| palette.nearest(color).unwrap())
+		})
+		.collect()
+}
+
+/// The unique colors in the pixels and how many times each appears.
+pub(crate) fn histogram(pixels: &[Color]) -> Vec<(Color, u32)> {
+	let mut counts: HashMap<u32, u32> = HashMap::new();
+	for &color in pixels {
+		*counts.entry(pack(color)).or_default() += 1;
+	}
+
+	let mut histogram: Vec<(Color, u32)> = counts
+		.into_iter()
+		.map(|(packed, count)| (unpack(packed), count))
+		.collect();
+
+	// HashMap order is random. Sorting keeps the output the same every time
+	histogram.sort_unstable_by_key(|(color, _)| pack(*color));
+	histogram
+}
+
+/// The squared distance between two colors.
+pub(crate) fn distance(a: Color, b: Color) -> u32 {
+	let dr = a.r as i32 - b.r as i32;
+	let dg = a.g as i32 - b.g as i32;
+	let db = a.b as i32 - b.b as i32;
+
+	(dr * dr + dg * dg + db * db) as u32
+}
+
+pub(crate) fn pack(color: Color) -> u32 {
+	((color.r as u32) << 16) | ((color.g as u32) << 8) | color.b as u32
+}
+
+fn unpack(packed: u32) -> Color {
+	Color::new((packed >> 16) as u8, (packed >> 8) as u8, packed as u8)
+}
+
+/// The average of some colors, weighted by their counts.
+pub(crate) fn average<'a, I>(colors: I) -> Color
+where
+	I: IntoIterator<Item = &'a (Color, u32)>,
+{
+	let mut sum = [0u64; 3];
+	let mut total = 0u64;
+
+	for (color, count) in colors {
+		let count = *count as u64;
+		sum[0] += color.r as u64 * count;
+		sum[1] += color.g as u64 * count;
+		sum[2] += color.b as u64 * count;
+		total += count;
+	}
+
+	if total == 0 {
+		return Color::new(0, 0, 0);
+	}
+
+	// Add half the total so we round instead of truncate
+	let channel = |sum: u64| ((sum + total / 2) / total) as u8;
+	Color::new(channel(sum[0]), channel(sum[1]), channel(sum[2]))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// A gradient with many more colors than fit in a palette
+	fn gradient() -> Vec<Color> {
+		(0..64 * 64u32)
+			.map(|n| Color::new((n % 64 * 4) as u8, (n / 64 * 4) as u8, (n % 7 * 30) as u8))
+			.collect()
+	}
+
+	fn quantizers() -> Vec<Box<dyn Quantizer>> {
+		vec![
+			Box::new(MedianCut),
+			Box::new(Octree),
+			Box::new(KMeans::new()),
+		]
+	}
+
+	#[test]
+	fn respects_max_colors() {
+		let pixels = gradient();
+
+		for quantizer in quantizers() {
+			for max_colors in [2, 16, 256] {
+				let options = QuantizeOptions::new().max_colors(max_colors);
+				let quantized = quantizer.quantize(&pixels, 64, options);
+
+				assert!(quantized.palette.len() <= max_colors);
+				assert_eq!(quantized.indicies.len(), pixels.len());
+				assert!(quantized
+					.indicies
+					.iter()
+					.all(|&idx| (idx as usize) < quantized.palette.len()));
+			}
+		}
+	}
+
+	#[test]
+	fn few_colors_are_exact() {
+		let colors = [
+			Color::new(255, 0, 0),
+			Color::new(0, 255, 0),
+			Color::new(0, 0, 255),
+		];
+		let pixels: Vec<Color> = (0..30).map(|n| colors[n % 3]).collect();
+
+		for quantizer in quantizers() {
+			let quantized = quantizer.quantize(&pixels, 30, QuantizeOptions::new());
+
+			for (pixel, idx) in pixels.iter().zip(&quantized.indicies) {
+				assert_eq!(quantized.palette.get(*idx), Some(*pixel));
+			}
+		}
+	}
+
+	#[test]
+	fn palettes_are_close() {
+		let pixels = gradient();
+
+		for quantizer in quantizers() {
+			let quantized = quantizer.quantize(&pixels, 64, QuantizeOptions::new().max_colors(64));
+
+			let error: u64 = pixels
+				.iter()
+				.zip(&quantized.indicies)
+				.map(|(&px, &idx)| distance(px, quantized.palette.get(idx).unwrap()) as u64)
+				.sum();
+
+			// 64 colors can't cover this gradient exactly, but each channel
+			// should be off by less than 20 on average
+			assert!(error / (pixels.len() as u64) < 20 * 20 * 3);
+		}
+	}
+
+	#[test]
+	fn reserves_transparent() {
+		let pixels = gradient();
+
+		// 256 so the transparent color is the last one a palette can have
+		for max_colors in [16, 256] {
+			let options = QuantizeOptions::new()
+				.max_colors(max_colors)
+				.reserve_transparent(true);
+
+			for quantizer in quantizers() {
+				let quantized = quantizer.quantize(&pixels, 64, options);
+
+				assert!(quantized.palette.len() <= max_colors);
+				assert_eq!(
+					quantized.transparent_index,
+					Some((quantized.palette.len() - 1) as u8)
+				);
+				assert!(!quantized
+					.indicies
+					.contains(&quantized.transparent_index.unwrap()));
+			}
+		}
+	}
+
+	#[test]
+	fn gif_from_rgb() {
+		let rgb: Vec<u8> = gradient()
+			.into_iter()
+			.flat_map(|clr| [clr.r, clr.g, clr.b])
+			.collect();
+
+		assert!(matches!(
+			crate::Gif::from_rgb(64, 64, rgb.clone()),
+			Err(crate::EncodeError::TooManyColors)
+		));
+
+		let gif =
+			crate::Gif::from_rgb_quantized(64, 64, &rgb, &Octree, QuantizeOptions::new()).unwrap();
+		let image = gif.images().next().unwrap();
+		assert!(image.palette().len() <= 256);
+		assert_eq!(image.decompress().indicies.len(), 64 * 64);
+	}
+
+	#[test]
+	fn rgba_reserves_transparent() {
+		let opaque: Vec<u8> = gradient()
+			.into_iter()
+			.flat_map(|clr| [clr.r, clr.g, clr.b, 200])
+			.collect();
+		let options = QuantizeOptions::new().max_colors(16);
+
+		let quantized = MedianCut.quantize_rgba(&opaque, 64, options);
+		assert_eq!(quantized.transparent_index, None);
+		assert_eq!(quantized.palette.len(), 16);
+
+		// Alpha of 200 is below this threshold, so everything's transparent
+		let quantized = MedianCut.quantize_rgba(&opaque, 64, options.alpha_threshold(201));
+		let transparent = quantized.transparent_index.unwrap();
+		assert!(quantized.indicies.iter().all(|idx| *idx == transparent));
+
+		let mut half = opaque.clone();
+		for px in half.chunks_mut(4).step_by(2) {
+			px[3] = 0;
+		}
+
+		let quantized = MedianCut.quantize_rgba(&half, 64, options);
+		let transparent = quantized.transparent_index.unwrap();
+		assert!(quantized.palette.len() <= 16);
+		for (idx, px) in quantized.indicies.iter().zip(half.chunks(4)) {
+			assert_eq!(*idx == transparent, px[3] == 0);
+		}
+	}
+
+	#[test]
+	fn dithered() {
+		let pixels = gradient();
+		let options = QuantizeOptions::new()
+			.max_colors(8)
+			.dither(Dither::new(DitherMethod::FloydSteinberg));
+
+		let plain = MedianCut.quantize(&pixels, 64, options.dither(Dither::none()));
+		let dithered = MedianCut.quantize(&pixels, 64, options);
+
+		assert_eq!(plain.palette, dithered.palette);
+		assert_ne!(plain.indicies, dithered.indicies);
+	}
+
+	#[test]
+	fn sampled_palette() {
+		let red = vec![Color::new(255, 0, 0); 100];
+		let blue = vec![Color::new(0, 0, 255); 100];
+
+		let palette = sample_palette(&MedianCut, &[&red, &blue], 10, QuantizeOptions::new());
+		assert_eq!(palette.len(), 2);
+
+		let options = QuantizeOptions::new()
+			.max_colors(2)
+			.reserve_transparent(true);
+		let palette = sample_palette(&MedianCut, &[&red, &blue], 10, options);
+		assert_eq!(palette.len(), 1);
+	}
+
+	#[test]
+	fn empty_image() {
+		let quantized = MedianCut.quantize(&[], 0, QuantizeOptions::new());
+		assert_eq!(quantized.palette.len(), 1);
+		assert!(quantized.indicies.is_empty());
+	}
+}
@@ -0,0 +1,184 @@
+use crate::{block::Palette, Color};
+
+use super::{histogram, Quantizer};
+
+/// Octree quantization.
+///
+/// Every color is put in a tree eight levels deep, where each level picks a
+/// child with one bit from each of the red, green, and blue channels. Leaves
+/// are then folded in to their parents, starting with the deepest and
+/// least used, until there are few enough of them. Each leaf becomes the
+/// average of the colors that ended up in it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Octree;
+
+impl Quantizer for Octree {
+	fn palette(&self, pixels: &[Color], max_colors: usize) -> Palette {
+		let mut tree = Tree::new();
+		for (color, count) in histogram(pixels) {
+			tree.insert(color, count);
+		}
+
+		tree.reduce(max_colors.max(1));
+
+		let mut palette = Palette::new();
+		for node in tree.nodes.iter().filter(|node| node.leaf) {
+			palette.push(node.average());
+		}
+
+		palette
+	}
+}
+
+/// Nodes refer to their children by index in to [Tree::nodes]. Zero is the
+/// root so it's never a child and can mean there's no child.
+#[derive(Default)]
+struct Node {
+	children: [usize; 8],
+	leaf: bool,
+	/// How many pixels are in this node and the sum of their channels
+	count: u64,
+	sum: [u64; 3],
+}
+
+impl Node {
+	fn average(&self) -> Color {
+		let channel = |sum: u64| ((sum + self.count / 2) / self.count.max(1)) as u8;
+		Color::new(
+			channel(self.sum[0]),
+			channel(self.sum[1]),
+			channel(self.sum[2]),
+		)
+	}
+}
+
+struct Tree {
+	nodes: Vec<Node>,
+	/// The nodes with children at each level
+	levels: [Vec<usize>; 8],
+	leaves: usize,
+}
+
+impl Tree {
+	fn new() -> Self {
+		Self {
+			nodes: vec![Node::default()],
+			levels: Default::default(),
+			leaves: 0,
+		}
+	}
+
+	fn insert(&mut self, color: Color, count: u32) {
+		let mut node = 0;
+
+		for level in 0..8 {
+			let shift = 7 - level;
+			let child = (((color.r >> shift) & 1) << 2
+				| ((color.g >> shift) & 1) << 1
+				| ((color.b >> shift) & 1)) as usize;
+
+			if self.nodes[node].children[child] == 0 {
+				if self.nodes[node].children == [0; 8] {
+					self.levels[level].push(node);
+				}
+
+				self.nodes.push(Node::default());
+				self.nodes[node].children[child] = self.nodes.len() - 1;
+			}
+
+			node = self.nodes[node].children[child];
+		}
+
+		let leaf = &mut self.nodes[node];
+		if !leaf.leaf {
+			leaf.leaf = true;
+			self.leaves += 1;
+		}
+
+		leaf.count += count as u64;
+		leaf.sum[0] += color.r as u64 * count as u64;
+		leaf.sum[1] += color.g as u64 * count as u64;
+		leaf.sum[2] += color.b as u64 * count as u64;
+	}
+
+	/// Fold leaves in to their parents until there are at most `max_leaves`.
+	fn reduce(&mut self, max_leaves: usize) {
+		for level in (0..8).rev() {
+			// Fold the nodes with the fewest pixels first
+			let mut parents = std::mem::take(&mut self.levels[level]);
+			for &parent in &parents {
+				self.total(parent);
+			}
+			parents.sort_unstable_by_key(|&parent| std::cmp::Reverse(self.nodes[parent].count));
+
+			while self.leaves > max_leaves {
+				let Some(parent) = parents.pop() else {
+					break;
+				};
+
+				self.fold(parent);
+			}
+
+			if self.leaves <= max_leaves {
+				return;
+			}
+		}
+	}
+
+	/// Work out the count of a node from its children. Nodes deeper than
+	/// this have all been folded so its children are leaves.
+	fn total(&mut self, node: usize) {
+		let mut count = 0;
+		let mut sum = [0; 3];
+
+		for child in self.nodes[node].children {
+			if child != 0 {
+				let child = &self.nodes[child];
+				count += child.count;
+				sum = [
+					sum[0] + child.sum[0],
+					sum[1] + child.sum[1],
+					sum[2] + child.sum[2],
+				];
+			}
+		}
+
+		self.nodes[node].count = count;
+		self.nodes[node].sum = sum;
+	}
+
+	/// Make a node in to a leaf holding the colors of all its children.
+	fn fold(&mut self, node: usize) {
+		let children = std::mem::take(&mut self.nodes[node].children);
+
+		for child in children {
+			if child != 0 {
+				self.nodes[child].leaf = false;
+				self.leaves -= 1;
+			}
+		}
+
+		self.nodes[node].leaf = true;
+		self.leaves += 1;
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn folds_least_used_first() {
+		let mut pixels = vec![Color::new(0, 0, 0); 100];
+		pixels.extend([Color::new(0, 0, 1); 100]);
+		pixels.push(Color::new(255, 255, 255));
+		pixels.push(Color::new(255, 255, 254));
+
+		// The two whites have fewer pixels so they're folded together, but
+		// the blacks are kept apart.
+		let palette = Octree.palette(&pixels, 3);
+		assert_eq!(palette.len(), 3);
+		assert!(palette.contains(&Color::new(0, 0, 0)));
+		assert!(palette.contains(&Color::new(0, 0, 1)));
+	}
+}
@@ -0,0 +1,450 @@
+//! Reading from a tokio [AsyncRead]. Bytes are read in to a buffer until a
+//! whole block is there and then it's parsed by the same [Reader] that
+//! [Decoder] makes, so nothing about parsing is different.
+
+use std::io::Cursor;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{
+	block::{Palette, ScreenDescriptor, Version},
+	Gif,
+};
+
+use super::{
+	limits::ImageCount, DecodeError, DecodeLimits, DecodeWarning, Decoder, ReadBlock, Reader,
+};
+
+/// How much we try to read at once, at least. If we're part way through a
+/// larger block we try to read as much as we already have of it, so it
+/// doesn't take too many tries.
+const READ_SIZE: usize = 8 * 1024;
+
+/// Like [Decoder], but for an [AsyncRead].
+pub struct AsyncDecoder<R: AsyncRead + Unpin> {
+	reader: R,
+	limits: DecodeLimits,
+	lenient: bool,
+}
+
+impl<R: AsyncRead + Unpin> AsyncDecoder<R> {
+	pub fn new(reader: R) -> Self {
+		Self {
+			reader,
+			limits: DecodeLimits::default(),
+			lenient: false,
+		}
+	}
+
+	/// Set the limits to enforce while decoding. See [Decoder::limits].
+	pub fn limits(mut self, limits: DecodeLimits) -> Self {
+		self.limits = limits;
+		self
+	}
+
+	/// Recover from broken files instead of failing. See [Decoder::lenient].
+	pub fn lenient(mut self, lenient: bool) -> Self {
+		self.lenient = lenient;
+		self
+	}
+
+	pub async fn read(mut self) -> Result<AsyncReader<R>, DecodeError> {
+		let mut buffer = vec![];
+		let mut eof = false;
+
+		let reader = loop {
+			// The header is tiny so copying it for each try is fine
+			let decoder = Decoder::new(Cursor::new(buffer.clone()))
+				.limits(self.limits)
+				.lenient(self.lenient);
+
+			match decoder.read() {
+				Err(e) if !eof && matches!(e.root(), DecodeError::UnexpectedEof) => (),
+				result => break result?,
+			}
+
+			eof = fill(&mut self.reader, &mut buffer).await?;
+		};
+
+		let mut reader = AsyncReader {
+			reader,
+			source: self.reader,
+			eof,
+			resume: None,
+			retry_at: 0,
+		};
+		reader.reader.partial = !eof;
+
+		Ok(reader)
+	}
+
+	pub async fn read_all(self) -> Result<Gif, DecodeError> {
+		self.read_all_with_warnings().await.map(|(gif, _)| gif)
+	}
+
+	/// Like [Decoder::read_all_with_warnings].
+	pub async fn read_all_with_warnings(self) -> Result<(Gif, Vec<DecodeWarning>), DecodeError> {
+		let mut reader = self.read().await?;
+
+		let mut blocks = vec![];
+		while let Some(block) = reader.block().await? {
+			blocks.push(block.block)
+		}
+
+		let Reader {
+			version,
+			screen_descriptor,
+			palette,
+			warnings,
+			..
+		} = reader.reader;
+
+		let gif = Gif {
+			version,
+			descriptor: screen_descriptor,
+			palette,
+			blocks,
+		};
+
+		Ok((gif, warnings))
+	}
+}
+
+/// Like [Reader], but for an [AsyncRead]. Made with [AsyncDecoder::read].
+pub struct AsyncReader<R: AsyncRead + Unpin> {
+	/// Parses what's been read so far
+	reader: Reader<Cursor<Vec<u8>>>,
+	source: R,
+	/// Whether `source` has ended
+	eof: bool,
+	/// Where [scan] got to in the block we're waiting on
+	resume: Option<usize>,
+	/// How much of the block we need before parsing it again, even if [scan]
+	/// says it isn't all there. This is so limits are still checked on a
+	/// block that's still coming in, without reparsing it every read.
+	retry_at: usize,
+}
+
+impl<R: AsyncRead + Unpin> AsyncReader<R> {
+	pub fn version(&self) -> Version {
+		self.reader.version
+	}
+
+	pub fn screen_descriptor(&self) -> &ScreenDescriptor {
+		&self.reader.screen_descriptor
+	}
+
+	pub fn palette(&self) -> Option<&Palette> {
+		self.reader.palette.as_ref()
+	}
+
+	pub fn width(&self) -> u16 {
+		self.reader.width()
+	}
+
+	pub fn height(&self) -> u16 {
+		self.reader.height()
+	}
+
+	/// See [Reader::warnings].
+	pub fn warnings(&self) -> &[DecodeWarning] {
+		self.reader.warnings()
+	}
+
+	/// Read the next block. See [Reader::block].
+	pub async fn block(&mut self) -> Result<Option<ReadBlock>, DecodeError> {
+		loop {
+			let checkpoint = self.checkpoint();
+
+			if !self.eof && !self.ready() {
+				self.fill().await?;
+				continue;
+			}
+
+			match self.reader.block() {
+				Err(e) if !self.eof && matches!(e.root(), DecodeError::UnexpectedEof) => {
+					// We don't have all of the block yet. Go back to the
+					// start of it and try again with more.
+					self.restore(checkpoint);
+					self.retry_at = self.buffered() + self.buffered().max(READ_SIZE);
+					self.fill().await?;
+				}
+				result => {
+					self.resume = None;
+					self.retry_at = 0;
+					return result;
+				}
+			}
+		}
+	}
+
+	/// Whether it's worth trying to parse the next block yet, which is when
+	/// it looks like it's all there or a lot more of it came in since we
+	/// last tried.
+	fn ready(&mut self) -> bool {
+		let cursor = &self.reader.reader.inner;
+		let block = &cursor.get_ref()[cursor.position() as usize..];
+
+		match scan(block, self.resume) {
+			Scan::Complete => true,
+			Scan::Incomplete { needs, resume } => {
+				self.resume = resume;
+				block.len() >= needs || block.len() >= self.retry_at
+			}
+		}
+	}
+
+	/// How much of the block we're on has been read in.
+	fn buffered(&self) -> usize {
+		let cursor = &self.reader.reader.inner;
+		cursor.get_ref().len() - cursor.position() as usize
+	}
+
+	fn checkpoint(&self) -> Checkpoint {
+		Checkpoint {
+			position: self.reader.reader.inner.position(),
+			bytes_read: self.reader.reader.bytes_read,
+			images: self.reader.images,
+		}
+	}
+
+	fn restore(&mut self, checkpoint: Checkpoint) {
+		self.reader.reader.inner.set_position(checkpoint.position);
+		self.reader.reader.bytes_read = checkpoint.bytes_read;
+		self.reader.images = checkpoint.images;
+	}
+
+	/// Drop what's been parsed and read more on to the end of the buffer.
+	async fn fill(&mut self) -> Result<(), DecodeError> {
+		let cursor = &mut self.reader.reader.inner;
+		let parsed = cursor.position() as usize;
+		cursor.get_mut().drain(..parsed);
+		cursor.set_position(0);
+
+		self.eof = fill(&mut self.source, cursor.get_mut()).await?;
+		self.reader.partial = !self.eof;
+
+		Ok(())
+	}
+}
+
+/// Where a [Reader] was before it tried to read a block.
+struct Checkpoint {
+	position: u64,
+	bytes_read: usize,
+	images: ImageCount,
+}
+
+/// Read more on to the end of `buffer`. Returns true if the reader has ended.
+async fn fill<R: AsyncRead + Unpin>(
+	reader: &mut R,
+	buffer: &mut Vec<u8>,
+) -> Result<bool, DecodeError> {
+	let start = buffer.len();
+	buffer.resize(start + start.max(READ_SIZE), 0);
+
+	let read = reader.read(&mut buffer[start..]).await?;
+	buffer.truncate(start + read);
+
+	Ok(read == 0)
+}
+
+/// How much of the block at the start of a buffer is there, found by
+/// following the sub-block lengths rather than parsing it.
+#[derive(Debug, PartialEq)]
+enum Scan {
+	/// The whole block is there. Or it isn't a block we know, and parsing it
+	/// will say what's wrong.
+	Complete,
+	/// The block is at least `needs` bytes long. `resume` is where the
+	/// length of the next sub-block is, to pick up from once there's more.
+	Incomplete { needs: usize, resume: Option<usize> },
+}
+
+/// Scan the block at the start of `block`. Pass the `resume` from the last
+/// scan of it so the sub-blocks that were already there aren't walked again.
+fn scan(block: &[u8], resume: Option<usize>) -> Scan {
+	let mut at = match resume {
+		Some(at) => at,
+		None => match block.first() {
+			None => {
+				return Scan::Incomplete {
+					needs: 1,
+					resume: None,
+				}
+			}
+			// Extension introducer and label, then it's all sub-blocks
+			Some(0x21) => 2,
+			Some(0x2C) => {
+				// Image descriptor, maybe a local palette, the LZW code size
+				let Some(packed) = block.get(9) else {
+					return Scan::Incomplete {
+						needs: 10,
+						resume: None,
+					};
+				};
+
+				let palette = if packed & 0x80 > 0 {
+					3 * (1 << ((packed & 0b111) + 1))
+				} else {
+					0
+				};
+				10 + palette + 1
+			}
+			_ => return Scan::Complete,
+		},
+	};
+
+	loop {
+		let Some(&len) = block.get(at) else {
+			return Scan::Incomplete {
+				needs: at + 1,
+				resume: Some(at),
+			};
+		};
+
+		if len == 0 {
+			return Scan::Complete;
+		}
+		at += len as usize + 1;
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use tokio::io::AsyncWriteExt;
+
+	use super::*;
+	use crate::{block::Block, writer::ImageBuilder};
+
+	fn gif() -> Gif {
+		let mut gif = Gif::new(32, 32);
+		gif.set_palette(Some(vec![(0, 0, 0), (255, 255, 255)].try_into().unwrap()));
+		gif.push(Block::CommentExtension(vec![b'c'; 1000]));
+
+		for frame in 0..4u32 {
+			let indicies = (0..32 * 32u32)
+				.map(|n| ((n * 7 + frame) % 5 == 0) as u8)
+				.collect();
+			gif.push(ImageBuilder::new(32, 32).delay(5).build(indicies).unwrap());
+		}
+
+		gif
+	}
+
+	#[tokio::test]
+	async fn reads_duplex() {
+		let gif = gif();
+		let bytes = gif.as_bytes();
+		let (mut tx, rx) = tokio::io::duplex(64);
+
+		let write = async {
+			// Dribble it in so blocks land across lots of reads
+			for chunk in bytes.chunks(37) {
+				tx.write_all(chunk).await.unwrap();
+			}
+			drop(tx);
+		};
+		let read = AsyncDecoder::new(rx).read_all();
+
+		let ((), read) = tokio::join!(write, read);
+		assert_eq!(read.unwrap().as_bytes(), bytes);
+	}
+
+	#[tokio::test]
+	async fn open_stream_returns_what_is_there() {
+		let bytes = gif().as_bytes();
+		let (mut tx, rx) = tokio::io::duplex(bytes.len());
+
+		// Everything but the trailer, and the stream is left open
+		tx.write_all(&bytes[..bytes.len() - 1]).await.unwrap();
+
+		// Everything here is already buffered, so none of it should wait
+		// on more. If it does the other branch wins and we fail
+		let waited = async {
+			for _ in 0..100 {
+				tokio::task::yield_now().await;
+			}
+		};
+		let read = async {
+			let mut reader = AsyncDecoder::new(rx).read().await.unwrap();
+			let mut sync = Decoder::new(bytes.as_slice()).read().unwrap();
+
+			while let Some(block) = sync.block().unwrap() {
+				let read = reader.block().await.unwrap().unwrap();
+				assert_eq!(read.offset, block.offset);
+			}
+		};
+
+		tokio::select! {
+			biased;
+			() = read => (),
+			() = waited => panic!("waited for more on an open stream"),
+		}
+		drop(tx);
+	}
+
+	#[test]
+	fn scans_sub_blocks() {
+		let bytes = gif().as_bytes();
+		// The comment extension, after the header, screen, and palette
+		let comment = &bytes[13 + 6..];
+		let len = 2 + 1000 + 1000 / 255 + 1 + 1;
+
+		assert_eq!(scan(&comment[..len], None), Scan::Complete);
+		assert_eq!(
+			scan(&comment[..300], None),
+			Scan::Incomplete {
+				needs: 2 + 256 + 256 + 1,
+				resume: Some(2 + 256 + 256),
+			}
+		);
+		assert_eq!(scan(&comment[..len], Some(2 + 256 + 256)), Scan::Complete);
+		assert_eq!(
+			scan(&[0x2C, 0, 0, 0, 0], None),
+			Scan::Incomplete {
+				needs: 10,
+				resume: None
+			}
+		);
+	}
+
+	#[tokio::test]
+	async fn reads_blocks() {
+		let bytes = gif().as_bytes();
+		let mut reader = AsyncDecoder::new(bytes.as_slice()).read().await.unwrap();
+		assert_eq!(reader.width(), 32);
+
+		let mut sync = Decoder::new(bytes.as_slice()).read().unwrap();
+		while let Some(block) = sync.block().unwrap() {
+			let read = reader.block().await.unwrap().unwrap();
+			assert_eq!(read.offset, block.offset);
+		}
+		assert!(reader.block().await.unwrap().is_none());
+	}
+
+	#[tokio::test]
+	async fn lenient_when_it_ends() {
+		let mut bytes = gif().as_bytes();
+		bytes.truncate(bytes.len() - 50);
+		let (mut tx, rx) = tokio::io::duplex(64);
+
+		let write = async {
+			tx.write_all(&bytes).await.unwrap();
+			drop(tx);
+		};
+		let read = AsyncDecoder::new(rx).lenient(true).read_all_with_warnings();
+
+		let ((), read) = tokio::join!(write, read);
+		let (gif, warnings) = read.unwrap();
+		assert_eq!(gif.images().count(), 4);
+		assert!(matches!(warnings[0], DecodeWarning::TruncatedImage { .. }));
+
+		// It's an error when not lenient, not something to wait on forever
+		let error = AsyncDecoder::new(bytes.as_slice())
+			.read_all()
+			.await
+			.unwrap_err();
+		assert!(matches!(error.root(), DecodeError::UnexpectedEof));
+	}
+}
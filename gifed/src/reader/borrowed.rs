@@ -0,0 +1,601 @@
+//! Reading a GIF that's already in memory without copying it. Blocks point in
+//! to the input and can be turned in to owned [Block]s when you need them.
+
+use std::{convert::TryFrom, ops::Range};
+
+use crate::{
+	block::{
+		extension::{Application, GraphicControl, PlainText},
+		AnimationApplication, Block, CompressedImage, ImageDescriptor, LoopCount, Palette,
+		ScreenDescriptor, Version,
+	},
+	Gif,
+};
+
+use super::{
+	limits::{exceeds, ImageCount},
+	netscape_extension, BlockKind, DecodeError, DecodeLimits,
+};
+
+/// Like [Decoder](super::Decoder), but for a slice. The blocks borrow from
+/// it instead of being copied out. There's no lenient mode.
+pub struct SliceDecoder<'a> {
+	data: &'a [u8],
+	limits: DecodeLimits,
+}
+
+impl<'a> SliceDecoder<'a> {
+	pub fn new(data: &'a [u8]) -> Self {
+		Self {
+			data,
+			limits: DecodeLimits::default(),
+		}
+	}
+
+	/// Set the limits to enforce while decoding. See [DecodeLimits].
+	pub fn limits(mut self, limits: DecodeLimits) -> Self {
+		self.limits = limits;
+		self
+	}
+
+	pub fn read(self) -> Result<SliceReader<'a>, DecodeError> {
+		let mut input = Input {
+			data: self.data,
+			position: 0,
+			max_bytes: self.limits.max_input_bytes,
+		};
+
+		let version = input
+			.array::<6>()
+			.and_then(|header| match &header {
+				b"GIF87a" => Ok(Version::Gif87a),
+				b"GIF89a" => Ok(Version::Gif89a),
+				_ => Err(DecodeError::InvalidVersion { header }),
+			})
+			.map_err(|e| e.located(0, BlockKind::Header, None))?;
+
+		let screen_descriptor = input
+			.array::<7>()
+			.map(ScreenDescriptor::from)
+			.and_then(|descriptor| {
+				self.limits
+					.check_canvas(descriptor.width, descriptor.height)?;
+				Ok(descriptor)
+			})
+			.map_err(|e| e.located(6, BlockKind::ScreenDescriptor, None))?;
+
+		let palette = if screen_descriptor.has_color_table() {
+			Some(
+				input
+					.palette(screen_descriptor.color_table_len())
+					.map_err(|e| e.located(13, BlockKind::GlobalPalette, None))?,
+			)
+		} else {
+			None
+		};
+
+		Ok(SliceReader {
+			version,
+			screen_descriptor,
+			palette,
+			input,
+			saw_trailer: false,
+			limits: self.limits,
+			images: ImageCount::default(),
+		})
+	}
+
+	/// Read everything and copy it in to a [Gif].
+	pub fn read_all(self) -> Result<Gif, DecodeError> {
+		let mut reader = self.read()?;
+
+		let mut blocks = vec![];
+		while let Some(block) = reader.block()? {
+			blocks.push(block.block.to_block())
+		}
+
+		Ok(Gif {
+			version: reader.version,
+			descriptor: reader.screen_descriptor,
+			palette: reader.palette,
+			blocks,
+		})
+	}
+}
+
+/// Like [Reader](super::Reader), but the blocks borrow from the input. Made
+/// with [SliceDecoder::read].
+pub struct SliceReader<'a> {
+	pub version: Version,
+	pub screen_descriptor: ScreenDescriptor,
+	/// Palettes are small so they're copied out.
+	pub palette: Option<Palette>,
+
+	input: Input<'a>,
+	saw_trailer: bool,
+
+	limits: DecodeLimits,
+	images: ImageCount,
+}
+
+/// A block and where it is in the input.
+#[derive(Clone, Debug)]
+pub struct ReadBlockRef<'a> {
+	pub offset: Range<usize>,
+	/// The whole block exactly as it was in the input. Good for copying it
+	/// somewhere else untouched.
+	pub bytes: &'a [u8],
+	pub block: BlockRef<'a>,
+}
+
+/// A [Block] that borrows its data from the input. The small, fixed size
+/// parts are copied.
+#[derive(Clone, Debug)]
+pub enum BlockRef<'a> {
+	CompressedImage(CompressedImageRef<'a>),
+	GraphicControlExtension(GraphicControl),
+	CommentExtension(SubBlocks<'a>),
+	PlainTextExtension {
+		/// Everything but the text. [PlainText::text] is empty.
+		header: PlainText,
+		text: SubBlocks<'a>,
+	},
+	ApplicationExtension {
+		identifier: [u8; 8],
+		authentication_code: [u8; 3],
+		data: SubBlocks<'a>,
+	},
+	LoopingExtension(LoopCount, AnimationApplication),
+	BufferingExtension(u32, AnimationApplication),
+	UnknownExtension {
+		label: u8,
+		data: SubBlocks<'a>,
+	},
+}
+
+/// A [CompressedImage] that borrows its LZW data from the input.
+#[derive(Clone, Debug)]
+pub struct CompressedImageRef<'a> {
+	pub image_descriptor: ImageDescriptor,
+	/// Palettes are small so they're copied out.
+	pub local_color_table: Option<Palette>,
+	pub lzw_code_size: u8,
+	pub data: SubBlocks<'a>,
+}
+
+/// Data sub-blocks as they are in the input, lengths and all, without the
+/// block terminator.
+#[derive(Clone, Copy, Debug)]
+pub struct SubBlocks<'a> {
+	raw: &'a [u8],
+}
+
+impl<'a> SliceReader<'a> {
+	pub fn width(&self) -> u16 {
+		self.screen_descriptor.width
+	}
+
+	pub fn height(&self) -> u16 {
+		self.screen_descriptor.height
+	}
+
+	/// Read the next block. None once the trailer has been read.
+	pub fn block(&mut self) -> Result<Option<ReadBlockRef<'a>>, DecodeError> {
+		if self.saw_trailer {
+			return Ok(None);
+		}
+
+		let before = self.input.position;
+		let frame = self.images.frames();
+		let Some(block) = self.read_block().map_err(|e| {
+			let kind = BlockKind::identify(&self.input.data[before..]);
+			e.located(before, kind, Some(frame))
+		})?
+		else {
+			return Ok(None);
+		};
+
+		let offset = before..self.input.position;
+		Ok(Some(ReadBlockRef {
+			bytes: &self.input.data[offset.clone()],
+			offset,
+			block,
+		}))
+	}
+
+	fn read_block(&mut self) -> Result<Option<BlockRef<'a>>, DecodeError> {
+		match self.input.u8()? {
+			0x2C => self.read_image().map(Some),
+			0x21 => self.read_extension().map(Some),
+			0x3B => {
+				self.saw_trailer = true;
+				Ok(None)
+			}
+			byte => Err(DecodeError::UnknownBlock { byte }),
+		}
+	}
+
+	fn read_image(&mut self) -> Result<BlockRef<'a>, DecodeError> {
+		let image_descriptor = ImageDescriptor::from(self.input.array::<9>()?);
+		self.limits.check_image(
+			&mut self.images,
+			image_descriptor.width,
+			image_descriptor.height,
+		)?;
+
+		let local_color_table = if image_descriptor.has_color_table() {
+			Some(self.input.palette(image_descriptor.color_table_size())?)
+		} else {
+			None
+		};
+
+		let lzw_code_size = self.input.u8()?;
+		let data = self.input.sub_blocks(None)?;
+
+		Ok(BlockRef::CompressedImage(CompressedImageRef {
+			image_descriptor,
+			local_color_table,
+			lzw_code_size,
+			data,
+		}))
+	}
+
+	fn read_extension(&mut self) -> Result<BlockRef<'a>, DecodeError> {
+		let label = self.input.u8()?;
+		let max_size = self.limits.max_extension_size;
+
+		match label {
+			0xF9 => {
+				// Graphics Control Extension
+				let _len = self.input.u8()?;
+				let gce = GraphicControl::from(self.input.array::<4>()?);
+				let _ = self.input.u8()?;
+
+				Ok(BlockRef::GraphicControlExtension(gce))
+			}
+			0x01 => {
+				// Plain Text Extension. If the header isn't 12 bytes we don't
+				// know what this is, so keep it as it is.
+				let start = self.input.position;
+				if self.input.u8()? != 12 {
+					self.input.position = start;
+					let data = self.input.sub_blocks(max_size)?;
+					return Ok(BlockRef::UnknownExtension { label, data });
+				}
+
+				let header = PlainText::from(self.input.array::<12>()?);
+				let text = self.input.sub_blocks(max_size)?;
+
+				Ok(BlockRef::PlainTextExtension { header, text })
+			}
+			0xFE => {
+				// Comment Extension
+				let data = self.input.sub_blocks(max_size)?;
+				Ok(BlockRef::CommentExtension(data))
+			}
+			0xFF => {
+				let _len = self.input.u8()?;
+				let identifier = self.input.array::<8>()?;
+				let authentication_code = self.input.array::<3>()?;
+				let data = self.input.sub_blocks(max_size)?;
+
+				if let Some(app) =
+					AnimationApplication::from_identifier(&identifier, &authentication_code)
+				{
+					let sub: Vec<&[u8]> = data.iter().collect();
+					match netscape_extension(&sub, app) {
+						Some(Block::LoopingExtension(count, app)) => {
+							return Ok(BlockRef::LoopingExtension(count, app))
+						}
+						Some(Block::BufferingExtension(size, app)) => {
+							return Ok(BlockRef::BufferingExtension(size, app))
+						}
+						_ => (),
+					}
+				}
+
+				Ok(BlockRef::ApplicationExtension {
+					identifier,
+					authentication_code,
+					data,
+				})
+			}
+			_ => {
+				let data = self.input.sub_blocks(max_size)?;
+				Ok(BlockRef::UnknownExtension { label, data })
+			}
+		}
+	}
+}
+
+impl<'a> BlockRef<'a> {
+	/// Copy the data out and make an owned [Block].
+	pub fn to_block(&self) -> Block {
+		match self {
+			BlockRef::CompressedImage(img) => Block::CompressedImage(img.to_image()),
+			BlockRef::GraphicControlExtension(gce) => Block::GraphicControlExtension(gce.clone()),
+			BlockRef::CommentExtension(data) => Block::CommentExtension(data.to_vec()),
+			BlockRef::PlainTextExtension { header, text } => {
+				let mut plain = header.clone();
+				plain.text = text.to_vec();
+				Block::PlainTextExtension(plain)
+			}
+			BlockRef::ApplicationExtension {
+				identifier,
+				authentication_code,
+				data,
+			} => Block::ApplicationExtension(Application {
+				identifier: *identifier,
+				authentication_code: *authentication_code,
+				data: data.to_vec(),
+			}),
+			BlockRef::LoopingExtension(count, app) => Block::LoopingExtension(*count, *app),
+			BlockRef::BufferingExtension(size, app) => Block::BufferingExtension(*size, *app),
+			BlockRef::UnknownExtension { label, data } => Block::UnknownExtension {
+				label: *label,
+				data: data.iter().map(<[u8]>::to_vec).collect(),
+			},
+		}
+	}
+}
+
+impl<'a> From<BlockRef<'a>> for Block {
+	fn from(block: BlockRef<'a>) -> Self {
+		block.to_block()
+	}
+}
+
+impl<'a> CompressedImageRef<'a> {
+	pub fn width(&self) -> u16 {
+		self.image_descriptor.width
+	}
+
+	pub fn height(&self) -> u16 {
+		self.image_descriptor.height
+	}
+
+	/// Copy the data out and make an owned [CompressedImage].
+	pub fn to_image(&self) -> CompressedImage {
+		CompressedImage {
+			image_descriptor: self.image_descriptor.clone(),
+			local_color_table: self.local_color_table.clone(),
+			lzw_code_size: self.lzw_code_size,
+			blocks: self.data.iter().map(<[u8]>::to_vec).collect(),
+		}
+	}
+}
+
+impl<'a> SubBlocks<'a> {
+	/// Each sub-block's data, without the length.
+	pub fn iter(&self) -> SubBlockIter<'a> {
+		SubBlockIter { raw: self.raw }
+	}
+
+	/// The sub-blocks as they were in the input, with their lengths.
+	pub fn raw(&self) -> &'a [u8] {
+		self.raw
+	}
+
+	/// How much data there is in all of the sub-blocks together.
+	pub fn len(&self) -> usize {
+		self.iter().map(<[u8]>::len).sum()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.raw.is_empty()
+	}
+
+	/// Copy the data of every sub-block in to one Vec.
+	pub fn to_vec(&self) -> Vec<u8> {
+		let mut data = Vec::with_capacity(self.len());
+		for sub in self.iter() {
+			data.extend_from_slice(sub);
+		}
+
+		data
+	}
+}
+
+impl<'a> IntoIterator for SubBlocks<'a> {
+	type Item = &'a [u8];
+	type IntoIter = SubBlockIter<'a>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
+/// The data of each sub-block in a [SubBlocks].
+pub struct SubBlockIter<'a> {
+	raw: &'a [u8],
+}
+
+impl<'a> Iterator for SubBlockIter<'a> {
+	type Item = &'a [u8];
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let (&len, rest) = self.raw.split_first()?;
+		let (sub, rest) = rest.split_at(len as usize);
+		self.raw = rest;
+
+		Some(sub)
+	}
+}
+
+/// The input and how far in to it we are.
+struct Input<'a> {
+	data: &'a [u8],
+	position: usize,
+	/// The most bytes we're allowed to read. See [DecodeLimits::max_input_bytes]
+	max_bytes: Option<usize>,
+}
+
+impl<'a> Input<'a> {
+	fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+		if exceeds(self.position + len, self.max_bytes) {
+			return Err(DecodeError::InputTooLarge);
+		}
+
+		let taken = self
+			.data
+			.get(self.position..self.position + len)
+			.ok_or(DecodeError::UnexpectedEof)?;
+		self.position += len;
+
+		Ok(taken)
+	}
+
+	fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+		Ok(<[u8; N]>::try_from(self.take(N)?).unwrap())
+	}
+
+	fn u8(&mut self) -> Result<u8, DecodeError> {
+		Ok(self.take(1)?[0])
+	}
+
+	fn palette(&mut self, count: usize) -> Result<Palette, DecodeError> {
+		Ok(Palette::try_from(self.take(count * 3)?).unwrap())
+	}
+
+	/// Walk the data sub-blocks up to the block terminator. If there's more
+	/// than `max_size` data in them, error with
+	/// [DecodeError::ExtensionTooLarge].
+	fn sub_blocks(&mut self, max_size: Option<usize>) -> Result<SubBlocks<'a>, DecodeError> {
+		let start = self.position;
+		let mut size = 0;
+
+		loop {
+			let end = self.position;
+			let len = self.u8()? as usize;
+
+			if len == 0 {
+				return Ok(SubBlocks {
+					raw: &self.data[start..end],
+				});
+			}
+
+			size += len;
+			if exceeds(size, max_size) {
+				return Err(DecodeError::ExtensionTooLarge);
+			}
+
+			self.take(len)?;
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::{reader::Decoder, writer::ImageBuilder};
+
+	fn gif() -> Gif {
+		let mut gif = Gif::new(8, 8);
+		gif.set_palette(Some(vec![(0, 0, 0), (255, 255, 255)].try_into().unwrap()));
+		gif.push(LoopCount::Number(3));
+		gif.push(Block::BufferingExtension(
+			1024,
+			AnimationApplication::AnimExts,
+		));
+		gif.push(Block::ApplicationExtension(Application {
+			identifier: *b"GIFEDTST",
+			authentication_code: *b"1.0",
+			data: vec![7; 300],
+		}));
+		gif.push(Block::CommentExtension(b"hello".repeat(100)));
+		gif.push(PlainText {
+			left: 0,
+			top: 0,
+			width: 8,
+			height: 8,
+			cell_width: 8,
+			cell_height: 8,
+			foreground_color_index: 1,
+			background_color_index: 0,
+			text: b"hi".to_vec(),
+		});
+		gif.push(Block::UnknownExtension {
+			label: 0x42,
+			data: vec![vec![1, 2], vec![3]],
+		});
+
+		let indicies: Vec<u8> = (0..64u8).map(|n| n % 2).collect();
+		gif.push(ImageBuilder::new(8, 8).delay(4).build(indicies).unwrap());
+
+		gif
+	}
+
+	#[test]
+	fn matches_decoder() {
+		let bytes = gif().as_bytes();
+
+		let mut borrowed = SliceDecoder::new(&bytes).read().unwrap();
+		let mut owned = Decoder::new(bytes.as_slice()).read().unwrap();
+
+		while let Some(block) = owned.block().unwrap() {
+			let borrowed = borrowed.block().unwrap().unwrap();
+			assert_eq!(borrowed.offset, block.offset);
+			assert_eq!(borrowed.bytes, &bytes[block.offset]);
+
+			let mut from_borrowed = Gif::new(8, 8);
+			from_borrowed.push(borrowed.block.to_block());
+			let mut from_owned = Gif::new(8, 8);
+			from_owned.push(block.block);
+			assert_eq!(from_borrowed.as_bytes(), from_owned.as_bytes());
+		}
+		assert!(borrowed.block().unwrap().is_none());
+
+		assert_eq!(
+			SliceDecoder::new(&bytes).read_all().unwrap().as_bytes(),
+			bytes
+		);
+	}
+
+	#[test]
+	fn borrows_input() {
+		let bytes = gif().as_bytes();
+		let input = bytes.as_ptr_range();
+		let mut reader = SliceDecoder::new(&bytes).read().unwrap();
+
+		let mut images = 0;
+		while let Some(block) = reader.block().unwrap() {
+			let data = match block.block {
+				BlockRef::CompressedImage(img) => {
+					images += 1;
+					img.data
+				}
+				BlockRef::CommentExtension(data) => data,
+				_ => continue,
+			};
+
+			for sub in data {
+				assert!(input.contains(&sub.as_ptr()));
+			}
+		}
+		assert_eq!(images, 1);
+	}
+
+	#[test]
+	fn errors() {
+		let bytes = gif().as_bytes();
+
+		let error = SliceDecoder::new(&bytes[..bytes.len() - 20])
+			.read_all()
+			.unwrap_err();
+		assert!(matches!(error.root(), DecodeError::UnexpectedEof));
+
+		// Errors say where they were the same as when reading with a Decoder
+		let expected = Decoder::new(&bytes[..bytes.len() - 20])
+			.read_all()
+			.unwrap_err();
+		assert_eq!(error.offset(), expected.offset());
+		assert_eq!(error.block(), Some(BlockKind::Image));
+		assert_eq!(error.frame(), Some(0));
+
+		let limited =
+			SliceDecoder::new(&bytes).limits(DecodeLimits::none().max_extension_size(200));
+		let error = limited.read_all().unwrap_err();
+		assert!(matches!(error.root(), DecodeError::ExtensionTooLarge));
+		assert_eq!(error.block(), Some(BlockKind::ApplicationExtension));
+	}
+}
@@ -0,0 +1,380 @@
+//! Find where every image in a file is without decompressing any of them, so
+//! that a single frame can be read, or rendered, later on.
+
+use std::{
+	io::{Read, Seek, SeekFrom},
+	ops::Range,
+};
+
+use crate::{
+	block::{
+		extension::{DisposalMethod, GraphicControl},
+		Block, CompressedImage, ImageDescriptor, Palette, ScreenDescriptor, Version,
+	},
+	render::Frame,
+	Gif,
+};
+
+use super::{BlockKind, DecodeError, DecodeWarning, Decoder, ReadBlock, Reader};
+
+/// Where an image is in the file and what we know about it without
+/// decompressing it.
+#[derive(Clone, Debug)]
+pub struct IndexedFrame {
+	/// From the image separator to just after the block terminator.
+	pub offset: Range<usize>,
+	pub descriptor: ImageDescriptor,
+	/// The Graphic Control Extension that came before the image, if any.
+	pub gce: Option<GraphicControl>,
+	/// Whether the image covers the whole canvas and doesn't show anything
+	/// under it, so that rendering can start here. The first image is always
+	/// a keyframe.
+	pub keyframe: bool,
+}
+
+/// Random access to the images of a GIF. Made with [Decoder::index].
+pub struct FrameIndex<R: Read + Seek> {
+	reader: Reader<R>,
+	/// Where in the stream the GIF starts. Offsets are from here.
+	start: u64,
+	frames: Vec<IndexedFrame>,
+}
+
+impl<R: Read + Seek> Decoder<R> {
+	/// Read through the whole file, recording where each image is and its
+	/// Graphic Control Extension, but skip over the image data and every
+	/// other extension. Any image can then be read with [FrameIndex::image].
+	pub fn index(mut self) -> Result<FrameIndex<R>, DecodeError> {
+		let start = self.reader.inner.stream_position()?;
+		let mut reader = self.read()?;
+
+		let mut frames = vec![];
+		let mut gce = None;
+		loop {
+			if reader.saw_trailer {
+				break;
+			}
+
+			let before = reader.reader.bytes_read;
+			let frame = frames.len();
+			reader.reading = BlockKind::Unknown;
+
+			let result = index_block(&mut reader, before, &mut gce);
+			let result = reader
+				.recover(before, result)
+				.map_err(|e| e.located(before, reader.reading, Some(frame)))?;
+			match result {
+				None => break,
+				Some(None) => (),
+				Some(Some(mut frame)) => {
+					frame.keyframe =
+						frames.is_empty() || frame.covers(reader.width(), reader.height());
+					frames.push(frame);
+				}
+			}
+		}
+
+		Ok(FrameIndex {
+			reader,
+			start,
+			frames,
+		})
+	}
+}
+
+/// Skip a block, returning the image if it was one. The outer Option is
+/// None at the trailer, like [Reader::block]. `gce` holds the last Graphic
+/// Control Extension until something uses it.
+fn index_block<R: Read>(
+	reader: &mut Reader<R>,
+	before: usize,
+	gce: &mut Option<GraphicControl>,
+) -> Result<Option<Option<IndexedFrame>>, DecodeError> {
+	let introducer = reader.reader.u8()?;
+	reader.reading = BlockKind::from_introducer(introducer);
+
+	match introducer {
+		0x2C => {
+			let descriptor = reader.read_image_descriptor()?;
+			reader.check_image_limits(&descriptor)?;
+
+			if descriptor.has_color_table() {
+				reader.reader.skip(descriptor.color_table_size() * 3)?;
+			}
+			let _lzw_code_size = reader.reader.u8()?;
+
+			match reader.reader.skip_data_subblocks() {
+				Ok(()) => (),
+				Err(DecodeError::UnexpectedEof) if reader.lenient => {
+					// Keep what we have of the image, but there's nothing after it
+					reader
+						.warnings
+						.push(DecodeWarning::TruncatedImage { offset: before });
+					reader.saw_trailer = true;
+				}
+				Err(e) => return Err(e),
+			}
+
+			Ok(Some(Some(IndexedFrame {
+				offset: before..reader.reader.bytes_read,
+				descriptor,
+				gce: gce.take(),
+				keyframe: false,
+			})))
+		}
+		0x21 => {
+			let label = reader.reader.u8()?;
+			reader.reading = BlockKind::from_label(label);
+
+			match label {
+				0xF9 => {
+					let _len = reader.reader.u8()?;
+					let mut buf = [0; 4];
+					reader.reader.read_exact(&mut buf)?;
+					let _ = reader.reader.u8()?;
+					*gce = Some(GraphicControl::from(buf));
+				}
+				0x01 => {
+					// Plain text uses up the GCE just like an image would. The
+					// header is shaped like a sub-block so it's skipped too.
+					*gce = None;
+					reader.reader.skip_data_subblocks()?;
+				}
+				_ => reader.reader.skip_data_subblocks()?,
+			}
+
+			Ok(Some(None))
+		}
+		0x3B => {
+			reader.saw_trailer = true;
+			Ok(None)
+		}
+		byte => Err(DecodeError::UnknownBlock { byte }),
+	}
+}
+
+impl IndexedFrame {
+	/// Whether drawing this image replaces the whole canvas, leaving nothing
+	/// from before it for later images to see.
+	fn covers(&self, width: u16, height: u16) -> bool {
+		let desc = &self.descriptor;
+		let full = desc.left == 0 && desc.top == 0 && desc.width >= width && desc.height >= height;
+
+		let (transparent, previous) = match &self.gce {
+			None => (false, false),
+			Some(gce) => (
+				gce.transparent_index().is_some(),
+				gce.disposal_method() == Some(DisposalMethod::RestorePrevious),
+			),
+		};
+
+		full && !transparent && !previous
+	}
+}
+
+impl<R: Read + Seek> FrameIndex<R> {
+	pub fn version(&self) -> Version {
+		self.reader.version
+	}
+
+	pub fn screen_descriptor(&self) -> &ScreenDescriptor {
+		&self.reader.screen_descriptor
+	}
+
+	pub fn palette(&self) -> Option<&Palette> {
+		self.reader.palette.as_ref()
+	}
+
+	pub fn width(&self) -> u16 {
+		self.reader.width()
+	}
+
+	pub fn height(&self) -> u16 {
+		self.reader.height()
+	}
+
+	/// Every image that was found, in order.
+	pub fn frames(&self) -> &[IndexedFrame] {
+		&self.frames
+	}
+
+	pub fn len(&self) -> usize {
+		self.frames.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.frames.is_empty()
+	}
+
+	/// The problems that were recovered from while indexing and reading
+	/// images. Only a [lenient](Decoder::lenient) decoder recovers from
+	/// anything.
+	pub fn warnings(&self) -> &[DecodeWarning] {
+		self.reader.warnings()
+	}
+
+	/// The last keyframe at or before image `nth`. Rendering `nth` only
+	/// needs the images from there on. See [IndexedFrame::keyframe].
+	pub fn keyframe(&self, nth: usize) -> Option<usize> {
+		self.frames
+			.get(..=nth)?
+			.iter()
+			.rposition(|frame| frame.keyframe)
+	}
+
+	/// Seek to image `nth` and read it. It isn't decompressed, use
+	/// [CompressedImage::decompress] for that. Returns None if there's no
+	/// image `nth`.
+	pub fn image(&mut self, nth: usize) -> Result<Option<CompressedImage>, DecodeError> {
+		let Some(frame) = self.frames.get(nth) else {
+			return Ok(None);
+		};
+
+		let offset = frame.offset.start;
+		let descriptor = frame.descriptor.clone();
+		let located = |e: DecodeError| e.located(offset, BlockKind::Image, Some(nth));
+
+		// The descriptor was already read, skip it and the separator
+		let data = offset + 10;
+		self.reader
+			.reader
+			.inner
+			.seek(SeekFrom::Start(self.start + data as u64))
+			.map_err(|e| located(e.into()))?;
+		self.reader.reader.bytes_read = data;
+
+		match self
+			.reader
+			.read_image(offset, descriptor)
+			.map_err(located)?
+		{
+			ReadBlock {
+				block: Block::CompressedImage(image),
+				..
+			} => Ok(Some(image)),
+			_ => unreachable!(),
+		}
+	}
+
+	/// Render image `nth` onto the canvas like [Gif::render] would, but
+	/// only read the images from the nearest [keyframe](FrameIndex::keyframe).
+	/// Returns None if there's no image `nth`.
+	pub fn render(&mut self, nth: usize) -> Result<Option<Frame>, DecodeError> {
+		let Some(keyframe) = self.keyframe(nth) else {
+			return Ok(None);
+		};
+
+		let mut gif = Gif {
+			version: self.version(),
+			descriptor: self.screen_descriptor().clone(),
+			palette: self.palette().cloned(),
+			blocks: vec![],
+		};
+
+		for idx in keyframe..=nth {
+			if let Some(gce) = self.frames[idx].gce.clone() {
+				gif.blocks.push(Block::GraphicControlExtension(gce));
+			}
+
+			// Only None if idx is out of bounds, and keyframe checked that
+			let image = self.image(idx)?.unwrap();
+			gif.blocks.push(Block::CompressedImage(image));
+		}
+
+		gif.render().last().transpose()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::io::Cursor;
+
+	use super::*;
+	use crate::writer::ImageBuilder;
+
+	/// Four 2x2 images. The second is full and opaque, so a keyframe, the
+	/// third only covers one pixel and the last has transparency.
+	fn animation() -> Gif {
+		let mut gif = Gif::new(2, 2);
+		gif.set_palette(Some(
+			vec![(0, 0, 0), (255, 0, 0), (0, 255, 0), (0, 0, 255)]
+				.try_into()
+				.unwrap(),
+		));
+
+		gif.push(ImageBuilder::new(2, 2).delay(1).build(vec![0; 4]).unwrap());
+		gif.push(Block::CommentExtension(b"skip me".to_vec()));
+		gif.push(
+			ImageBuilder::new(2, 2)
+				.delay(2)
+				.build(vec![1, 2, 3, 1])
+				.unwrap(),
+		);
+		gif.push(
+			ImageBuilder::new(1, 1)
+				.offset(1, 1)
+				.delay(3)
+				.build(vec![2])
+				.unwrap(),
+		);
+		gif.push(
+			ImageBuilder::new(2, 2)
+				.delay(4)
+				.transparent_index(Some(0))
+				.build(vec![0, 0, 0, 3])
+				.unwrap(),
+		);
+
+		gif
+	}
+
+	#[test]
+	fn indexes_frames() {
+		let gif = animation();
+		let bytes = gif.as_bytes();
+
+		// Something before the gif, so offsets aren't from the stream start
+		let mut padded = b"junk".to_vec();
+		padded.extend_from_slice(&bytes);
+		let mut cursor = Cursor::new(padded);
+		cursor.set_position(4);
+
+		let mut index = Decoder::new(cursor).index().unwrap();
+		assert_eq!(index.len(), 4);
+
+		let keyframes: Vec<bool> = index.frames().iter().map(|f| f.keyframe).collect();
+		assert_eq!(keyframes, [true, true, false, false]);
+
+		let delays: Vec<u16> = index
+			.frames()
+			.iter()
+			.map(|f| f.gce.as_ref().unwrap().delay())
+			.collect();
+		assert_eq!(delays, [1, 2, 3, 4]);
+
+		// Out of order on purpose
+		let images: Vec<CompressedImage> = gif.images().map(|img| img.compressed.clone()).collect();
+		for nth in [2, 0, 3, 1] {
+			let image = index.image(nth).unwrap().unwrap();
+			assert_eq!(image.as_bytes(), images[nth].as_bytes());
+			assert_eq!(&bytes[index.frames()[nth].offset.clone()], image.as_bytes());
+		}
+		assert!(index.image(4).unwrap().is_none());
+	}
+
+	#[test]
+	fn renders_from_keyframe() {
+		let gif = animation();
+		let mut index = Decoder::new(Cursor::new(gif.as_bytes())).index().unwrap();
+
+		assert_eq!(index.keyframe(3), Some(1));
+		assert_eq!(index.keyframe(0), Some(0));
+		assert_eq!(index.keyframe(4), None);
+
+		let frames: Vec<Vec<u8>> = gif.render().map(|f| f.unwrap().rgba).collect();
+		for nth in (0..4).rev() {
+			assert_eq!(index.render(nth).unwrap().unwrap().rgba, frames[nth]);
+		}
+		assert!(index.render(4).unwrap().is_none());
+	}
+}
@@ -0,0 +1,110 @@
+use super::DecodeError;
+
+/// Limits on how much a [Decoder](super::Decoder) is willing to read and
+/// allocate. Useful when decoding GIFs you don't trust.
+///
+/// Every limit is `None`, unlimited, by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DecodeLimits {
+	/// The largest width * height allowed for the logical screen or for any
+	/// single image.
+	pub max_canvas_pixels: Option<usize>,
+	/// The most images a GIF may contain.
+	pub max_frames: Option<usize>,
+	/// The most pixels all of the images may have, in total. Decompressing
+	/// an image never gives more indicies than it has pixels, so this is also
+	/// the most bytes the indicies will take up once decompressed. The
+	/// compressed data is only bounded by
+	/// [max_input_bytes](DecodeLimits::max_input_bytes).
+	pub max_decompressed_bytes: Option<usize>,
+	/// The largest amount of data a single comment, application, plain text,
+	/// or unknown extension may contain.
+	pub max_extension_size: Option<usize>,
+	/// The most bytes that may be read from the input.
+	pub max_input_bytes: Option<usize>,
+}
+
+impl DecodeLimits {
+	/// No limits at all. This is the same as [DecodeLimits::default].
+	pub fn none() -> Self {
+		Self::default()
+	}
+
+	pub fn max_canvas_pixels(mut self, pixels: usize) -> Self {
+		self.max_canvas_pixels = Some(pixels);
+		self
+	}
+
+	pub fn max_frames(mut self, frames: usize) -> Self {
+		self.max_frames = Some(frames);
+		self
+	}
+
+	pub fn max_decompressed_bytes(mut self, bytes: usize) -> Self {
+		self.max_decompressed_bytes = Some(bytes);
+		self
+	}
+
+	pub fn max_extension_size(mut self, bytes: usize) -> Self {
+		self.max_extension_size = Some(bytes);
+		self
+	}
+
+	pub fn max_input_bytes(mut self, bytes: usize) -> Self {
+		self.max_input_bytes = Some(bytes);
+		self
+	}
+}
+
+/// How many images, and how many pixels in them, have been read so far.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ImageCount {
+	frames: usize,
+	pixels: usize,
+}
+
+impl ImageCount {
+	/// How many images have been counted.
+	pub(crate) fn frames(&self) -> usize {
+		self.frames
+	}
+}
+
+impl DecodeLimits {
+	/// Check the size of the logical screen, or of a single image.
+	pub(crate) fn check_canvas(&self, width: u16, height: u16) -> Result<(), DecodeError> {
+		let area = width as usize * height as usize;
+		if exceeds(area, self.max_canvas_pixels) {
+			Err(DecodeError::CanvasTooLarge { width, height })
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Count another image in to `count`, checking it and the new totals.
+	pub(crate) fn check_image(
+		&self,
+		count: &mut ImageCount,
+		width: u16,
+		height: u16,
+	) -> Result<(), DecodeError> {
+		self.check_canvas(width, height)?;
+
+		count.frames += 1;
+		if exceeds(count.frames, self.max_frames) {
+			return Err(DecodeError::TooManyFrames);
+		}
+
+		count.pixels += width as usize * height as usize;
+		if exceeds(count.pixels, self.max_decompressed_bytes) {
+			return Err(DecodeError::DecompressedTooLarge);
+		}
+
+		Ok(())
+	}
+}
+
+/// Returns true if `value` is over `limit`, if there is one.
+pub(crate) fn exceeds(value: usize, limit: Option<usize>) -> bool {
+	limit.map(|limit| value > limit).unwrap_or(false)
+}
@@ -1,23 +1,44 @@
+#[cfg(feature = "tokio")]
+mod asynchronous;
+mod borrowed;
+mod index;
+mod limits;
+mod stream;
+
+#[cfg(feature = "tokio")]
+pub use asynchronous::{AsyncDecoder, AsyncReader};
+pub use borrowed::{
+	BlockRef, CompressedImageRef, ReadBlockRef, SliceDecoder, SliceReader, SubBlockIter, SubBlocks,
+};
+pub use index::{FrameIndex, IndexedFrame};
+pub use limits::DecodeLimits;
+pub use stream::{Event, StreamDecoder};
+
 use std::{
 	convert::TryFrom,
 	error::Error,
 	fmt,
 	fs::File,
-	io::{BufReader, ErrorKind, Read},
+	io::{self, BufReader, ErrorKind, Read},
 	ops::Range,
 	path::Path,
 };
 
 use crate::{
 	block::{
-		extension::{Application, GraphicControl},
-		Block, CompressedImage, ImageDescriptor, Palette, ScreenDescriptor, Version,
+		extension::{Application, GraphicControl, PlainText},
+		AnimationApplication, Block, CompressedImage, ImageDescriptor, LoopCount, Palette,
+		ScreenDescriptor, Version,
 	},
 	Gif,
 };
 
+use self::limits::{exceeds, ImageCount};
+
 pub struct Decoder<R: Read> {
 	reader: SmartReader<R>,
+	limits: DecodeLimits,
+	lenient: bool,
 }
 
 impl Decoder<BufReader<File>> {
@@ -32,17 +53,50 @@ impl<R: Read> Decoder<R> {
 	pub fn new(reader: R) -> Self {
 		Self {
 			reader: SmartReader::new(reader),
+			limits: DecodeLimits::default(),
+			lenient: false,
 		}
 	}
 
+	/// Set the limits to enforce while decoding. See [DecodeLimits].
+	pub fn limits(mut self, limits: DecodeLimits) -> Self {
+		self.reader.max_bytes = limits.max_input_bytes;
+		self.limits = limits;
+		self
+	}
+
+	/// Recover from broken files instead of failing. Reading stops at the
+	/// first problem with the block structure, like the file ending early or
+	/// garbage where a block should start, and everything read up to there is
+	/// kept. Image data is decompressed as it's read so that bad LZW data can
+	/// be salvaged and short images padded out.
+	///
+	/// Everything that had to be fixed is recorded as a [DecodeWarning]. See
+	/// [Reader::warnings] and [Decoder::read_all_with_warnings]. Errors from
+	/// [DecodeLimits] are still errors.
+	pub fn lenient(mut self, lenient: bool) -> Self {
+		self.lenient = lenient;
+		self
+	}
+
 	pub fn read(mut self) -> Result<Reader<R>, DecodeError> {
-		let version = self.read_version()?;
-		let screen_descriptor = self.read_screen_descriptor()?;
+		let version = self
+			.read_version()
+			.map_err(|e| e.located(0, BlockKind::Header, None))?;
+		let screen_descriptor = self
+			.read_screen_descriptor()
+			.and_then(|descriptor| {
+				self.limits
+					.check_canvas(descriptor.width, descriptor.height)?;
+				Ok(descriptor)
+			})
+			.map_err(|e| e.located(6, BlockKind::ScreenDescriptor, None))?;
 
 		let palette = if screen_descriptor.has_color_table() {
 			Some(
 				self.reader
-					.read_palette(screen_descriptor.color_table_len())?,
+					.read_palette(screen_descriptor.color_table_len())
+					.map_err(|e| e.located(13, BlockKind::GlobalPalette, None))?,
 			)
 		} else {
 			None
@@ -54,10 +108,23 @@ impl<R: Read> Decoder<R> {
 			palette,
 			reader: self.reader,
 			saw_trailer: false,
+			reading: BlockKind::Unknown,
+			limits: self.limits,
+			images: ImageCount::default(),
+			lenient: self.lenient,
+			partial: false,
+			warnings: vec![],
 		})
 	}
 
 	pub fn read_all(self) -> Result<Gif, DecodeError> {
+		self.read_all_with_warnings().map(|(gif, _)| gif)
+	}
+
+	/// Read the whole file like [Decoder::read_all], also returning anything
+	/// that was recovered from. The warnings are always empty unless the
+	/// decoder is [lenient](Decoder::lenient).
+	pub fn read_all_with_warnings(self) -> Result<(Gif, Vec<DecodeWarning>), DecodeError> {
 		let mut decoder = self.read()?;
 
 		let mut blocks = vec![];
@@ -65,22 +132,24 @@ impl<R: Read> Decoder<R> {
 			blocks.push(block.block)
 		}
 
-		Ok(Gif {
+		let gif = Gif {
 			version: decoder.version,
 			descriptor: decoder.screen_descriptor,
 			palette: decoder.palette,
 			blocks,
-		})
+		};
+
+		Ok((gif, decoder.warnings))
 	}
 
 	fn read_version(&mut self) -> Result<Version, DecodeError> {
 		let mut buf = [0; 6];
 		self.reader.read_exact(&mut buf)?;
 
-		match buf.as_slice() {
+		match &buf {
 			b"GIF87a" => Ok(Version::Gif87a),
 			b"GIF89a" => Ok(Version::Gif89a),
-			_ => Err(DecodeError::InvalidVersion),
+			_ => Err(DecodeError::InvalidVersion { header: buf }),
 		}
 	}
 
@@ -103,6 +172,20 @@ pub struct Reader<R: Read> {
 
 	reader: SmartReader<R>,
 	saw_trailer: bool,
+	/// What the block we're in the middle of is, as far as we know yet. For
+	/// saying where errors happened.
+	reading: BlockKind,
+
+	limits: DecodeLimits,
+	/// How many images, and pixels, we've read
+	images: ImageCount,
+
+	lenient: bool,
+	/// Set while the rest of the input might just not have arrived yet, so
+	/// running out of data is an error to wait on rather than something to
+	/// recover from. Only reading asynchronously does this.
+	partial: bool,
+	warnings: Vec<DecodeWarning>,
 }
 
 impl<R: Read> Reader<R> {
@@ -114,39 +197,68 @@ impl<R: Read> Reader<R> {
 		self.screen_descriptor.height
 	}
 
+	/// The problems that have been recovered from so far. Only a
+	/// [lenient](Decoder::lenient) reader recovers from anything.
+	pub fn warnings(&self) -> &[DecodeWarning] {
+		&self.warnings
+	}
+
 	pub fn block(&mut self) -> Result<Option<ReadBlock>, DecodeError> {
 		if self.saw_trailer {
 			return Ok(None);
 		}
 
 		let before = self.reader.bytes_read;
-		let introducer = self.reader.u8()?;
+		let frame = self.images.frames();
+		self.reading = BlockKind::Unknown;
 
-		match introducer {
-			0x2C => {
-				let mut buf = [0; 9];
-				self.reader.read_exact(&mut buf)?;
-				let descriptor: ImageDescriptor = buf.into();
+		let result = self.read_block(before);
+		self.recover(before, result)
+			.map_err(|e| e.located(before, self.reading, Some(frame)))
+	}
 
-				let palette = if descriptor.has_color_table() {
-					Some(self.reader.read_palette(descriptor.color_table_size())?)
+	/// If we're lenient, turn the errors we can stop reading at in to
+	/// warnings.
+	fn recover<T>(
+		&mut self,
+		before: usize,
+		result: Result<Option<T>, DecodeError>,
+	) -> Result<Option<T>, DecodeError> {
+		match result {
+			Err(DecodeError::UnexpectedEof) if self.lenient && !self.partial => {
+				if self.reader.bytes_read == before {
+					self.stop(DecodeWarning::MissingTrailer)
 				} else {
-					None
-				};
+					self.stop(DecodeWarning::TruncatedBlock { offset: before })
+				}
+			}
+			Err(DecodeError::UnknownBlock { byte }) if self.lenient => {
+				self.stop(DecodeWarning::UnknownBlock {
+					offset: before,
+					byte,
+				})
+			}
+			result => result,
+		}
+	}
+
+	/// Record a problem we can't read past and act as if we saw the trailer.
+	fn stop<T>(&mut self, warning: DecodeWarning) -> Result<Option<T>, DecodeError> {
+		self.warnings.push(warning);
+		self.saw_trailer = true;
+		Ok(None)
+	}
 
-				let lzw_code_size = self.reader.u8()?;
-				let data = self.reader.take_data_subblocks()?;
-				let after = self.reader.bytes_read;
+	fn read_block(&mut self, before: usize) -> Result<Option<ReadBlock>, DecodeError> {
+		let introducer = self.reader.u8()?;
+		self.reading = BlockKind::from_introducer(introducer);
 
-				Ok(Some(ReadBlock {
-					offset: before..after,
-					block: Block::CompressedImage(CompressedImage {
-						image_descriptor: descriptor,
-						local_color_table: palette,
-						lzw_code_size,
-						blocks: data,
-					}),
-				}))
+		match introducer {
+			0x2C => {
+				let descriptor = self.read_image_descriptor()?;
+				self.check_image_limits(&descriptor)?;
+
+				self.read_image(before, descriptor).map(Some)
 			}
 			0x21 => {
 				let block = self.read_extension()?;
@@ -165,8 +277,84 @@ impl<R: Read> Reader<R> {
 		}
 	}
 
+	fn read_image_descriptor(&mut self) -> Result<ImageDescriptor, DecodeError> {
+		let mut buf = [0; 9];
+		self.reader.read_exact(&mut buf)?;
+		Ok(buf.into())
+	}
+
+	/// Read the rest of an image, everything after the descriptor. `before`
+	/// is the offset of the image separator.
+	fn read_image(
+		&mut self,
+		before: usize,
+		descriptor: ImageDescriptor,
+	) -> Result<ReadBlock, DecodeError> {
+		let palette = if descriptor.has_color_table() {
+			Some(self.reader.read_palette(descriptor.color_table_size())?)
+		} else {
+			None
+		};
+
+		let lzw_code_size = self.reader.u8()?;
+
+		let mut data = vec![];
+		match self.reader.take_data_subblocks_into(&mut data, None) {
+			Ok(()) => (),
+			Err(DecodeError::UnexpectedEof) if self.lenient && !self.partial => {
+				// Keep what we have of the image, but there's nothing after it
+				self.warnings
+					.push(DecodeWarning::TruncatedImage { offset: before });
+				self.saw_trailer = true;
+			}
+			Err(e) => return Err(e),
+		}
+		let after = self.reader.bytes_read;
+
+		let mut image = CompressedImage {
+			image_descriptor: descriptor,
+			local_color_table: palette,
+			lzw_code_size,
+			blocks: data,
+		};
+
+		if self.lenient {
+			image = self.repair_image(image);
+		}
+
+		Ok(ReadBlock {
+			offset: before..after,
+			block: Block::CompressedImage(image),
+		})
+	}
+
+	/// Decompress the image to check it. If anything was wrong with the data,
+	/// it's compressed again from what could be salvaged.
+	///
+	/// Decompressing stops at about as many indicies as the image has pixels,
+	/// which [Reader::check_image_limits] has already counted against
+	/// [DecodeLimits::max_decompressed_bytes].
+	fn repair_image(&mut self, image: CompressedImage) -> CompressedImage {
+		let lzw_code_size = image.lzw_code_size;
+		let (indexed, warnings) = image.clone().decompress_lenient();
+
+		if warnings.is_empty() {
+			return image;
+		}
+
+		self.warnings.extend(warnings);
+		indexed.compress_with_code_size(lzw_code_size.clamp(2, 8))
+	}
+
+	fn check_image_limits(&mut self, descriptor: &ImageDescriptor) -> Result<(), DecodeError> {
+		self.limits
+			.check_image(&mut self.images, descriptor.width, descriptor.height)
+	}
+
 	fn read_extension(&mut self) -> Result<Block, DecodeError> {
 		let label = self.reader.u8()?;
+		self.reading = BlockKind::from_label(label);
+		let max_size = self.limits.max_extension_size;
 
 		match label {
 			0xF9 => {
@@ -179,9 +367,40 @@ impl<R: Read> Reader<R> {
 
 				Ok(Block::GraphicControlExtension(gce))
 			}
+			0x01 => {
+				// Plain Text Extension
+				let len = self.reader.u8()?;
+				if len != 12 {
+					// The header is always 12 bytes. If it isn't we don't
+					// know what this is, so keep it as it is.
+					let mut data = vec![];
+					if len > 0 {
+						let len = len as usize;
+						if exceeds(len, max_size) {
+							return Err(DecodeError::ExtensionTooLarge);
+						}
+
+						let mut header = vec![0; len];
+						self.reader.read_exact(&mut header)?;
+						data.push(header);
+
+						let max_size = max_size.map(|max| max - len);
+						self.reader.take_data_subblocks_into(&mut data, max_size)?;
+					}
+
+					return Ok(Block::UnknownExtension { label, data });
+				}
+
+				let mut buf = [0; 12];
+				self.reader.read_exact(&mut buf)?;
+				let mut text = PlainText::from(buf);
+				text.text = self.reader.take_and_collapse_subblocks(max_size)?;
+
+				Ok(Block::PlainTextExtension(text))
+			}
 			0xFE => {
 				// Comment Extension
-				let data = self.reader.take_and_collapse_subblocks()?;
+				let data = self.reader.take_and_collapse_subblocks(max_size)?;
 				Ok(Block::CommentExtension(data))
 			}
 			0xFF => {
@@ -191,23 +410,66 @@ impl<R: Read> Reader<R> {
 				let mut auth = [0; 3];
 				self.reader.read_exact(&mut app_id)?;
 				self.reader.read_exact(&mut auth)?;
-				let data = self.reader.take_and_collapse_subblocks()?;
-				let app = Application {
-					identifier: app_id,
-					authentication_code: auth,
-					data,
-				};
+				let data = self.reader.take_data_subblocks(max_size)?;
 
-				Ok(Block::ApplicationExtension(app))
+				Ok(application_extension(app_id, auth, &data))
+			}
+			_ => {
+				let data = self.reader.take_data_subblocks(max_size)?;
+				Ok(Block::UnknownExtension { label, data })
 			}
-			_ => Err(DecodeError::UnknownExtension),
 		}
 	}
 }
 
+/// Make a [Block] from an application extension's header and data
+/// sub-blocks. NETSCAPE2.0 and ANIMEXTS1.0 extensions we understand become a
+/// [Block::LoopingExtension] or [Block::BufferingExtension], anything else is
+/// a [Block::ApplicationExtension].
+fn application_extension<B: AsRef<[u8]>>(
+	identifier: [u8; 8],
+	authentication_code: [u8; 3],
+	data: &[B],
+) -> Block {
+	if let Some(app) = AnimationApplication::from_identifier(&identifier, &authentication_code) {
+		if let Some(block) = netscape_extension(data, app) {
+			return block;
+		}
+	}
+
+	Block::ApplicationExtension(Application {
+		identifier,
+		authentication_code,
+		data: data.iter().flat_map(AsRef::as_ref).copied().collect(),
+	})
+}
+
+/// Turn the data of a NETSCAPE2.0 extension into a [Block::LoopingExtension]
+/// or [Block::BufferingExtension]. Returns None if the data isn't a single
+/// sub-block we understand, in which case it should be kept as an application
+/// extension.
+fn netscape_extension<B: AsRef<[u8]>>(data: &[B], app: AnimationApplication) -> Option<Block> {
+	match data {
+		[sub] => match sub.as_ref() {
+			[0x01, lo, hi] => match u16::from_le_bytes([*lo, *hi]) {
+				0 => Some(Block::LoopingExtension(LoopCount::Forever, app)),
+				count => Some(Block::LoopingExtension(LoopCount::Number(count), app)),
+			},
+			[0x02, a, b, c, d] => Some(Block::BufferingExtension(
+				u32::from_le_bytes([*a, *b, *c, *d]),
+				app,
+			)),
+			_ => None,
+		},
+		_ => None,
+	}
+}
+
 struct SmartReader<R: Read> {
 	inner: R,
 	bytes_read: usize,
+	/// The most bytes we're allowed to read. See [DecodeLimits::max_input_bytes]
+	max_bytes: Option<usize>,
 }
 
 impl<R: Read> SmartReader<R> {
@@ -215,19 +477,22 @@ impl<R: Read> SmartReader<R> {
 		Self {
 			inner: reader,
 			bytes_read: 0,
+			max_bytes: None,
+		}
+	}
+
+	fn check_input(&self, len: usize) -> Result<(), DecodeError> {
+		if exceeds(self.bytes_read + len, self.max_bytes) {
+			Err(DecodeError::InputTooLarge)
+		} else {
+			Ok(())
 		}
 	}
 
 	pub fn u8(&mut self) -> Result<u8, DecodeError> {
 		let mut buffer = [0];
-
-		match self.inner.read(&mut buffer) {
-			Ok(read) => {
-				self.bytes_read += read;
-				Ok(buffer[0])
-			}
-			Err(e) => Err(DecodeError::IoError(e)),
-		}
+		self.read_exact(&mut buffer)?;
+		Ok(buffer[0])
 	}
 
 	#[allow(dead_code)]
@@ -242,6 +507,8 @@ impl<R: Read> SmartReader<R> {
 
 	//TODO: Result not Option when buffer len
 	pub fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), DecodeError> {
+		self.check_input(buf.len())?;
+
 		match self.inner.read_exact(buf) {
 			Ok(_) => {
 				self.bytes_read += buf.len();
@@ -252,25 +519,89 @@ impl<R: Read> SmartReader<R> {
 		}
 	}
 
-	pub fn take_data_subblocks(&mut self) -> Result<Vec<Vec<u8>>, DecodeError> {
+	/// Read data sub-blocks until the block terminator. If the total length
+	/// of the data would be greater than `max_size`, error with
+	/// [DecodeError::ExtensionTooLarge].
+	pub fn take_data_subblocks(
+		&mut self,
+		max_size: Option<usize>,
+	) -> Result<Vec<Vec<u8>>, DecodeError> {
 		let mut blocks = vec![];
+		self.take_data_subblocks_into(&mut blocks, max_size)?;
+		Ok(blocks)
+	}
+
+	/// Like [SmartReader::take_data_subblocks], but the sub-blocks are pushed
+	/// on to `blocks` so that the ones read before an error are kept. If the
+	/// data ends part way through a sub-block, what there was of it is kept.
+	pub fn take_data_subblocks_into(
+		&mut self,
+		blocks: &mut Vec<Vec<u8>>,
+		max_size: Option<usize>,
+	) -> Result<(), DecodeError> {
+		let mut size = 0;
 
 		loop {
 			let block_size = self.u8()?;
 
 			if block_size == 0 {
-				return Ok(blocks);
+				return Ok(());
+			}
+
+			size += block_size as usize;
+			if exceeds(size, max_size) {
+				return Err(DecodeError::ExtensionTooLarge);
 			}
 
-			let mut block = vec![0; block_size as usize];
-			self.read_exact(&mut block)?;
+			self.check_input(block_size as usize)?;
+			let mut block = Vec::with_capacity(block_size as usize);
+			let read = (&mut self.inner)
+				.take(block_size as u64)
+				.read_to_end(&mut block)?;
+			self.bytes_read += read;
 
+			// Keep a partial block too, it's still data
+			let short = read < block_size as usize;
 			blocks.push(block);
+
+			if short {
+				return Err(DecodeError::UnexpectedEof);
+			}
+		}
+	}
+
+	/// Read past data sub-blocks, up to and including the block terminator,
+	/// without keeping any of them.
+	pub fn skip_data_subblocks(&mut self) -> Result<(), DecodeError> {
+		loop {
+			let block_size = self.u8()?;
+
+			if block_size == 0 {
+				return Ok(());
+			}
+
+			self.skip(block_size as usize)?;
+		}
+	}
+
+	pub fn skip(&mut self, len: usize) -> Result<(), DecodeError> {
+		self.check_input(len)?;
+
+		let skipped = io::copy(&mut (&mut self.inner).take(len as u64), &mut io::sink())?;
+		self.bytes_read += skipped as usize;
+
+		if (skipped as usize) < len {
+			Err(DecodeError::UnexpectedEof)
+		} else {
+			Ok(())
 		}
 	}
 
-	pub fn take_and_collapse_subblocks(&mut self) -> Result<Vec<u8>, DecodeError> {
-		let blocks = self.take_data_subblocks()?;
+	pub fn take_and_collapse_subblocks(
+		&mut self,
+		max_size: Option<usize>,
+	) -> Result<Vec<u8>, DecodeError> {
+		let blocks = self.take_data_subblocks(max_size)?;
 		let mut ret = vec![];
 		for block in blocks {
 			ret.extend_from_slice(&block)
@@ -290,28 +621,129 @@ impl<R: Read> SmartReader<R> {
 #[derive(Debug)]
 pub enum DecodeError {
 	IoError(std::io::Error),
-	UnknownVersionString,
 	UnexpectedEof,
-	LzwInvalidCode,
+	/// The LZW data had a `code` that isn't in the table yet. `next_code` is
+	/// the code the table would have added next, so the largest that was
+	/// allowed.
+	LzwInvalidCode {
+		code: u16,
+		next_code: u16,
+	},
+	InvalidCodeSize {
+		lzw_code_size: u8,
+	},
 	ColorIndexOutOfBounds,
-	InvalidVersion,
-	UnknownBlock { byte: u8 },
-	UnknownExtension,
+	/// The file didn't start with `GIF87a` or `GIF89a`. `header` is what it
+	/// started with instead.
+	InvalidVersion {
+		header: [u8; 6],
+	},
+	UnknownBlock {
+		byte: u8,
+	},
+	/// The logical screen or an image is larger than
+	/// [DecodeLimits::max_canvas_pixels]
+	CanvasTooLarge {
+		width: u16,
+		height: u16,
+	},
+	/// There are more images than [DecodeLimits::max_frames]
+	TooManyFrames,
+	/// The images, decompressed, would be larger than
+	/// [DecodeLimits::max_decompressed_bytes]
+	DecompressedTooLarge,
+	/// An extension had more data than [DecodeLimits::max_extension_size]
+	ExtensionTooLarge,
+	/// The input is longer than [DecodeLimits::max_input_bytes]
+	InputTooLarge,
+	/// Another error and where in the input it happened. Errors from reading
+	/// come wrapped in this, use [DecodeError::root] to get at what went
+	/// wrong.
+	Located {
+		/// Where the block that was being read starts, from the start of the
+		/// input.
+		offset: usize,
+		block: BlockKind,
+		/// How many images came before the block. For an image this is its
+		/// index. None for the header, screen descriptor, and global palette.
+		frame: Option<usize>,
+		error: Box<DecodeError>,
+	},
 }
 
-impl Error for DecodeError {}
+impl DecodeError {
+	/// The error without where it happened.
+	pub fn root(&self) -> &DecodeError {
+		match self {
+			DecodeError::Located { error, .. } => error.root(),
+			error => error,
+		}
+	}
+
+	/// Where the block that was being read starts, if we know.
+	pub fn offset(&self) -> Option<usize> {
+		match self {
+			DecodeError::Located { offset, .. } => Some(*offset),
+			_ => None,
+		}
+	}
+
+	/// What was being read, if we know.
+	pub fn block(&self) -> Option<BlockKind> {
+		match self {
+			DecodeError::Located { block, .. } => Some(*block),
+			_ => None,
+		}
+	}
+
+	/// The index of the image the block was, or came before. See
+	/// [DecodeError::Located].
+	pub fn frame(&self) -> Option<usize> {
+		match self {
+			DecodeError::Located { frame, .. } => *frame,
+			_ => None,
+		}
+	}
+
+	/// Say where this happened, unless that's already known.
+	pub(crate) fn located(self, offset: usize, block: BlockKind, frame: Option<usize>) -> Self {
+		match self {
+			DecodeError::Located { .. } => self,
+			error => DecodeError::Located {
+				offset,
+				block,
+				frame,
+				error: Box::new(error),
+			},
+		}
+	}
+}
+
+impl Error for DecodeError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			DecodeError::IoError(error) => Some(error),
+			// The wrapped error is already part of our message
+			DecodeError::Located { error, .. } => error.source(),
+			_ => None,
+		}
+	}
+}
 impl fmt::Display for DecodeError {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		match self {
 			DecodeError::IoError(error) => write!(f, "{}", error),
-			DecodeError::UnknownVersionString => {
-				write!(f, "File did not start with a valid header")
-			}
 			DecodeError::UnexpectedEof => {
 				write!(f, "Found the end of the data at a weird spot")
 			}
-			DecodeError::LzwInvalidCode => {
-				write!(f, "the LZW stream contained invalid data")
+			DecodeError::LzwInvalidCode { code, next_code } => {
+				write!(
+					f,
+					"LZW code {code} is invalid, the next code in the table was {next_code}"
+				)
+			}
+			DecodeError::InvalidCodeSize { lzw_code_size } => {
+				write!(f, "LZW minimum code size of {lzw_code_size} is invalid")
 			}
 			DecodeError::ColorIndexOutOfBounds => {
 				write!(
@@ -319,16 +751,176 @@ impl fmt::Display for DecodeError {
 					"The image contained an index not found in the color table"
 				)
 			}
-			DecodeError::InvalidVersion => {
-				write!(f, "GIF header was incorrect")
+			DecodeError::InvalidVersion { header } => {
+				write!(
+					f,
+					"GIF header was incorrect, expected GIF87a or GIF89a but found \"{}\"",
+					header.escape_ascii()
+				)
 			}
 			DecodeError::UnknownBlock { byte } => {
-				//TODO: gen- Better error message
-				write!(f, "No block with introducer {byte:02X}")
+				write!(
+					f,
+					"Expected an image, extension, or trailer but found the byte {byte:02X}"
+				)
+			}
+			DecodeError::CanvasTooLarge { width, height } => {
+				write!(f, "An image of {width}x{height} is larger than the limit")
+			}
+			DecodeError::TooManyFrames => {
+				write!(f, "There are more images than the limit")
 			}
-			DecodeError::UnknownExtension => {
-				//TODO: gen- Better error message
-				write!(f, "Unknown extension")
+			DecodeError::DecompressedTooLarge => {
+				write!(f, "The decompressed images would be larger than the limit")
+			}
+			DecodeError::ExtensionTooLarge => {
+				write!(f, "An extension has more data than the limit")
+			}
+			DecodeError::InputTooLarge => {
+				write!(f, "The input is longer than the limit")
+			}
+			DecodeError::Located {
+				offset,
+				block,
+				frame,
+				error,
+			} => {
+				write!(f, "{error} [{block} at byte {offset}")?;
+				if let Some(frame) = frame {
+					write!(f, ", frame {frame}")?;
+				}
+				write!(f, "]")
+			}
+		}
+	}
+}
+
+/// What was being read when a [DecodeError] happened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockKind {
+	Header,
+	ScreenDescriptor,
+	GlobalPalette,
+	/// Where a block should start. Either the data ended there or the
+	/// introducer wasn't one we know.
+	Unknown,
+	Image,
+	/// An extension we hadn't read the label of yet.
+	Extension,
+	GraphicControlExtension,
+	PlainTextExtension,
+	CommentExtension,
+	ApplicationExtension,
+	UnknownExtension {
+		label: u8,
+	},
+}
+
+impl BlockKind {
+	pub(crate) fn from_introducer(introducer: u8) -> Self {
+		match introducer {
+			0x2C => BlockKind::Image,
+			0x21 => BlockKind::Extension,
+			_ => BlockKind::Unknown,
+		}
+	}
+
+	pub(crate) fn from_label(label: u8) -> Self {
+		match label {
+			0xF9 => BlockKind::GraphicControlExtension,
+			0x01 => BlockKind::PlainTextExtension,
+			0xFE => BlockKind::CommentExtension,
+			0xFF => BlockKind::ApplicationExtension,
+			label => BlockKind::UnknownExtension { label },
+		}
+	}
+
+	/// What the block at the start of `bytes` is, from its introducer and
+	/// label.
+	pub(crate) fn identify(bytes: &[u8]) -> Self {
+		match bytes {
+			[0x21, label, ..] => BlockKind::from_label(*label),
+			[introducer, ..] => BlockKind::from_introducer(*introducer),
+			[] => BlockKind::Unknown,
+		}
+	}
+}
+
+impl fmt::Display for BlockKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			BlockKind::Header => write!(f, "header"),
+			BlockKind::ScreenDescriptor => write!(f, "logical screen descriptor"),
+			BlockKind::GlobalPalette => write!(f, "global color table"),
+			BlockKind::Unknown => write!(f, "block"),
+			BlockKind::Image => write!(f, "image"),
+			BlockKind::Extension => write!(f, "extension"),
+			BlockKind::GraphicControlExtension => write!(f, "graphic control extension"),
+			BlockKind::PlainTextExtension => write!(f, "plain text extension"),
+			BlockKind::CommentExtension => write!(f, "comment extension"),
+			BlockKind::ApplicationExtension => write!(f, "application extension"),
+			BlockKind::UnknownExtension { label } => {
+				write!(f, "unknown extension {label:02X}")
+			}
+		}
+	}
+}
+
+/// A problem with the file that a [lenient](Decoder::lenient) decoder
+/// recovered from. Offsets are from the start of the input.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodeWarning {
+	/// The data ended where a block should have started. Everything before
+	/// was fine, there just wasn't a trailer.
+	MissingTrailer,
+	/// The data ended part way through the block at `offset`. The block was
+	/// dropped.
+	TruncatedBlock { offset: usize },
+	/// The data ended part way through the image data of the image at
+	/// `offset`. What was there was kept.
+	TruncatedImage { offset: usize },
+	/// There was a byte that isn't a block introducer where a block should
+	/// have started. Nothing after it was read.
+	UnknownBlock { offset: usize, byte: u8 },
+	/// The LZW data of an image had an invalid code. The indicies decoded
+	/// before it were kept. See [DecodeError::LzwInvalidCode] for the fields.
+	LzwInvalidCode { code: u16, next_code: u16 },
+	/// An image had an LZW minimum code size outside of 1 to 8, so none of
+	/// its data could be decoded.
+	InvalidCodeSize { lzw_code_size: u8 },
+	/// An image decoded to the wrong number of indicies. It was padded with
+	/// zeros, or cut short, to `expected`. Decoding stops one past
+	/// `expected`, so `got` is never more than that.
+	ImageDataLength { expected: usize, got: usize },
+}
+
+impl fmt::Display for DecodeWarning {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			DecodeWarning::MissingTrailer => write!(f, "The trailer is missing"),
+			DecodeWarning::TruncatedBlock { offset } => {
+				write!(f, "The block at {offset} was cut short and dropped")
+			}
+			DecodeWarning::TruncatedImage { offset } => {
+				write!(f, "The image data at {offset} was cut short")
+			}
+			DecodeWarning::UnknownBlock { offset, byte } => {
+				write!(
+					f,
+					"No block with introducer {byte:02X} at {offset}, stopped reading"
+				)
+			}
+			DecodeWarning::LzwInvalidCode { code, next_code } => {
+				write!(
+					f,
+					"LZW code {code} is invalid, the next code in the table was {next_code}. Kept what came before it"
+				)
+			}
+			DecodeWarning::InvalidCodeSize { lzw_code_size } => {
+				write!(f, "LZW minimum code size of {lzw_code_size} is invalid")
+			}
+			DecodeWarning::ImageDataLength { expected, got } => {
+				write!(f, "Image should have {expected} indicies but had {got}")
 			}
 		}
 	}
@@ -339,3 +931,423 @@ impl From<std::io::Error> for DecodeError {
 		DecodeError::IoError(ioerror)
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use std::io::Cursor;
+
+	use super::*;
+	use crate::writer::ImageBuilder;
+
+	#[test]
+	fn plain_text_round_trip() {
+		let mut gif = Gif::new(4, 4);
+		gif.push(PlainText {
+			left: 1,
+			top: 2,
+			width: 16,
+			height: 8,
+			cell_width: 4,
+			cell_height: 8,
+			foreground_color_index: 1,
+			background_color_index: 0,
+			text: b"gif!".repeat(100),
+		});
+		gif.push(
+			ImageBuilder::new(4, 4)
+				.palette(vec![(0, 0, 0), (255, 255, 255)].try_into().unwrap())
+				.build(vec![0; 16])
+				.unwrap(),
+		);
+
+		let read = Decoder::new(Cursor::new(gif.as_bytes()))
+			.read_all()
+			.unwrap();
+		let image = read.images().next().unwrap();
+		let text = image.plain_text().next().unwrap();
+
+		assert_eq!((text.left, text.top), (1, 2));
+		assert_eq!((text.width, text.height), (16, 8));
+		assert_eq!((text.columns(), text.rows()), (4, 1));
+		assert_eq!(text.foreground_color_index, 1);
+		assert_eq!(text.text(), b"gif!".repeat(100).as_slice());
+		assert_eq!(read.as_bytes(), gif.as_bytes());
+	}
+
+	#[test]
+	fn plain_text_wrong_header() {
+		// A plain text extension with a 5 byte header instead of 12
+		let mut bytes = b"GIF89a".to_vec();
+		bytes.extend_from_slice(&[1, 0, 1, 0, 0, 0, 0]);
+		bytes.extend_from_slice(&[0x21, 0x01, 5, 1, 2, 3, 4, 5, 2, b'h', b'i', 0x00]);
+		bytes.push(0x3B);
+
+		let read = Decoder::new(Cursor::new(&bytes)).read_all().unwrap();
+		match &read.blocks[0] {
+			Block::UnknownExtension { label, data } => {
+				assert_eq!(*label, 0x01);
+				assert_eq!(data, &[vec![1, 2, 3, 4, 5], b"hi".to_vec()]);
+			}
+			_ => panic!("expected an unknown extension"),
+		}
+		assert_eq!(read.as_bytes(), bytes);
+
+		let read = SliceDecoder::new(&bytes).read_all().unwrap();
+		assert_eq!(read.as_bytes(), bytes);
+	}
+
+	#[test]
+	fn unknown_extension_round_trip() {
+		let mut gif = Gif::new(1, 1);
+		gif.push(Block::UnknownExtension {
+			label: 0xF8,
+			data: vec![vec![0x80, 0x00], vec![1; 255], vec![2, 3]],
+		});
+		gif.push(
+			ImageBuilder::new(1, 1)
+				.palette(vec![(0, 0, 0)].try_into().unwrap())
+				.build(vec![0])
+				.unwrap(),
+		);
+
+		let bytes = gif.as_bytes();
+		let read = Decoder::new(Cursor::new(&bytes)).read_all().unwrap();
+
+		match &read.blocks[0] {
+			Block::UnknownExtension { label, data } => {
+				assert_eq!(*label, 0xF8);
+				assert_eq!(data.len(), 3);
+				assert_eq!(data[1], vec![1; 255]);
+			}
+			_ => panic!("expected an unknown extension"),
+		}
+		assert_eq!(read.as_bytes(), bytes);
+	}
+
+	fn limits_gif() -> Vec<u8> {
+		let mut gif = Gif::new(8, 8);
+		gif.set_palette(Some(vec![(0, 0, 0), (255, 255, 255)].try_into().unwrap()));
+		gif.push(Block::CommentExtension(vec![b'a'; 300]));
+		for _ in 0..3 {
+			gif.push(ImageBuilder::new(8, 8).build(vec![1; 64]).unwrap());
+		}
+
+		gif.as_bytes()
+	}
+
+	fn read_limited(limits: DecodeLimits) -> Result<Gif, DecodeError> {
+		Decoder::new(Cursor::new(limits_gif()))
+			.limits(limits)
+			.read_all()
+	}
+
+	#[test]
+	fn limits_allow_when_within() {
+		let bytes = limits_gif();
+		let limits = DecodeLimits::none()
+			.max_canvas_pixels(64)
+			.max_frames(3)
+			.max_decompressed_bytes(64 * 3)
+			.max_extension_size(300)
+			.max_input_bytes(bytes.len());
+
+		assert!(read_limited(limits).is_ok());
+	}
+
+	#[test]
+	fn limits_error_when_exceeded() {
+		let bytes = limits_gif();
+
+		assert!(matches!(
+			read_limited(DecodeLimits::none().max_canvas_pixels(63))
+				.unwrap_err()
+				.root(),
+			DecodeError::CanvasTooLarge {
+				width: 8,
+				height: 8
+			}
+		));
+		assert!(matches!(
+			read_limited(DecodeLimits::none().max_frames(2))
+				.unwrap_err()
+				.root(),
+			DecodeError::TooManyFrames
+		));
+		assert!(matches!(
+			read_limited(DecodeLimits::none().max_decompressed_bytes(64 * 2 + 63))
+				.unwrap_err()
+				.root(),
+			DecodeError::DecompressedTooLarge
+		));
+		assert!(matches!(
+			read_limited(DecodeLimits::none().max_extension_size(299))
+				.unwrap_err()
+				.root(),
+			DecodeError::ExtensionTooLarge
+		));
+		assert!(matches!(
+			read_limited(DecodeLimits::none().max_input_bytes(bytes.len() - 1))
+				.unwrap_err()
+				.root(),
+			DecodeError::InputTooLarge
+		));
+	}
+
+	fn read_lenient(bytes: &[u8]) -> (Gif, Vec<DecodeWarning>) {
+		Decoder::new(Cursor::new(bytes))
+			.lenient(true)
+			.read_all_with_warnings()
+			.unwrap()
+	}
+
+	#[test]
+	fn lenient_missing_trailer() {
+		let mut bytes = limits_gif();
+		bytes.pop();
+
+		assert!(matches!(
+			Decoder::new(Cursor::new(&bytes))
+				.read_all()
+				.unwrap_err()
+				.root(),
+			DecodeError::UnexpectedEof
+		));
+
+		let (gif, warnings) = read_lenient(&bytes);
+		assert_eq!(gif.images().count(), 3);
+		assert_eq!(warnings, vec![DecodeWarning::MissingTrailer]);
+	}
+
+	#[test]
+	fn lenient_garbage_block() {
+		let mut bytes = limits_gif();
+		let trailer = bytes.len() - 1;
+		bytes[trailer] = 0x00;
+		bytes.extend_from_slice(b"garbage");
+
+		assert!(matches!(
+			Decoder::new(Cursor::new(&bytes))
+				.read_all()
+				.unwrap_err()
+				.root(),
+			DecodeError::UnknownBlock { byte: 0x00 }
+		));
+
+		let (gif, warnings) = read_lenient(&bytes);
+		assert_eq!(gif.images().count(), 3);
+		assert_eq!(
+			warnings,
+			vec![DecodeWarning::UnknownBlock {
+				offset: trailer,
+				byte: 0x00
+			}]
+		);
+	}
+
+	#[test]
+	fn errors_say_where() {
+		let mut bytes = limits_gif();
+		let trailer = bytes.len() - 1;
+		bytes[trailer] = 0x00;
+
+		let error = Decoder::new(Cursor::new(&bytes)).read_all().unwrap_err();
+		assert_eq!(error.offset(), Some(trailer));
+		assert_eq!(error.block(), Some(BlockKind::Unknown));
+		assert_eq!(error.frame(), Some(3));
+
+		// Cut off part way through the comment, which is after the palette
+		let mut bytes = limits_gif();
+		bytes.truncate(19 + 100);
+
+		let error = Decoder::new(Cursor::new(&bytes)).read_all().unwrap_err();
+		assert!(matches!(error.root(), DecodeError::UnexpectedEof));
+		assert_eq!(error.offset(), Some(19));
+		assert_eq!(error.block(), Some(BlockKind::CommentExtension));
+		assert_eq!(error.frame(), Some(0));
+		assert_eq!(
+			error.to_string(),
+			"Found the end of the data at a weird spot [comment extension at byte 19, frame 0]"
+		);
+
+		let mut bytes = limits_gif();
+		bytes[3..6].copy_from_slice(b"90a");
+
+		let error = Decoder::new(Cursor::new(&bytes)).read().err().unwrap();
+		assert!(matches!(
+			error.root(),
+			DecodeError::InvalidVersion { header } if header == b"GIF90a"
+		));
+		assert_eq!(error.block(), Some(BlockKind::Header));
+		assert_eq!(error.frame(), None);
+	}
+
+	#[test]
+	fn error_source() {
+		struct Broken;
+		impl Read for Broken {
+			fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+				Err(io::Error::other("broken"))
+			}
+		}
+
+		let error = Decoder::new(Broken).read().err().unwrap();
+		assert!(matches!(error.root(), DecodeError::IoError(_)));
+
+		let source = error.source().unwrap();
+		assert_eq!(source.to_string(), "broken");
+		assert!(source.downcast_ref::<io::Error>().is_some());
+
+		assert!(DecodeError::UnexpectedEof.source().is_none());
+	}
+
+	#[test]
+	fn lenient_truncated_extension() {
+		let mut gif = Gif::new(1, 1);
+		gif.push(Block::CommentExtension(vec![b'a'; 300]));
+		let mut bytes = gif.as_bytes();
+		bytes.truncate(bytes.len() - 10);
+
+		let (gif, warnings) = read_lenient(&bytes);
+		assert!(gif.blocks.is_empty());
+		assert_eq!(warnings, vec![DecodeWarning::TruncatedBlock { offset: 13 }]);
+	}
+
+	#[test]
+	fn lenient_truncated_image() {
+		let indicies: Vec<u8> = (0..32 * 32u32).map(|n| (n * 7 % 13) as u8).collect();
+		let mut gif = Gif::new(32, 32);
+		gif.push(
+			ImageBuilder::new(32, 32)
+				.palette(vec![(0, 0, 0); 16].try_into().unwrap())
+				.build(indicies.clone())
+				.unwrap(),
+		);
+
+		// Cut the image data off part way through
+		let mut bytes = gif.as_bytes();
+		bytes.truncate(bytes.len() - 40);
+
+		let (gif, warnings) = read_lenient(&bytes);
+		assert_eq!(warnings[0], DecodeWarning::TruncatedImage { offset: 13 });
+		assert!(matches!(
+			warnings[1],
+			DecodeWarning::ImageDataLength { expected: 1024, .. }
+		));
+
+		let image = gif.images().next().unwrap();
+		let decoded = image.compressed.clone().decompress().unwrap().indicies;
+		assert_eq!(decoded.len(), 1024);
+		assert_eq!(decoded[..64], indicies[..64]);
+	}
+
+	#[test]
+	fn lenient_decompresses_no_more_than_the_image() {
+		let indicies: Vec<u8> = (0..200 * 200u32).map(|n| (n % 3) as u8).collect();
+		let image = ImageBuilder::new(200, 200)
+			.palette(vec![(0, 0, 0); 4].try_into().unwrap())
+			.build(indicies)
+			.unwrap();
+
+		// A 2x2 image with the data of a 200x200 one
+		let mut compressed = image.image.compress(Some(2)).unwrap();
+		compressed.image_descriptor.width = 2;
+		compressed.image_descriptor.height = 2;
+
+		let mut gif = Gif::new(2, 2);
+		gif.push(Block::CompressedImage(compressed));
+
+		let (gif, warnings) = Decoder::new(Cursor::new(gif.as_bytes()))
+			.lenient(true)
+			.limits(DecodeLimits::none().max_decompressed_bytes(4))
+			.read_all_with_warnings()
+			.unwrap();
+
+		assert_eq!(
+			warnings,
+			vec![DecodeWarning::ImageDataLength {
+				expected: 4,
+				got: 5
+			}]
+		);
+		let image = gif.images().next().unwrap();
+		assert_eq!(
+			image.compressed.clone().decompress().unwrap().indicies,
+			[0, 1, 2, 0]
+		);
+	}
+
+	fn netscape(ident: &[u8; 11], data: &[u8]) -> Vec<u8> {
+		let mut bytes = b"GIF89a".to_vec();
+		bytes.extend_from_slice(&[1, 0, 1, 0, 0, 0, 0]);
+		bytes.extend_from_slice(&[0x21, 0xFF, 0x0B]);
+		bytes.extend_from_slice(ident);
+		bytes.push(data.len() as u8);
+		bytes.extend_from_slice(data);
+		bytes.extend_from_slice(&[0x00, 0x3B]);
+		bytes
+	}
+
+	fn first_block(bytes: Vec<u8>) -> Block {
+		let mut read = Decoder::new(Cursor::new(bytes)).read_all().unwrap();
+		read.blocks.remove(0)
+	}
+
+	#[test]
+	fn netscape_looping() {
+		let bytes = netscape(b"NETSCAPE2.0", &[0x01, 0x00, 0x00]);
+		assert!(matches!(
+			first_block(bytes.clone()),
+			Block::LoopingExtension(LoopCount::Forever, AnimationApplication::Netscape)
+		));
+
+		let read = Decoder::new(Cursor::new(&bytes)).read_all().unwrap();
+		assert_eq!(read.as_bytes(), bytes);
+
+		let bytes = netscape(b"NETSCAPE2.0", &[0x01, 0x05, 0x01]);
+		assert!(matches!(
+			first_block(bytes),
+			Block::LoopingExtension(LoopCount::Number(261), AnimationApplication::Netscape)
+		));
+
+		// The same thing under another name, written back out under that name
+		let bytes = netscape(b"ANIMEXTS1.0", &[0x01, 0x05, 0x01]);
+		assert!(matches!(
+			first_block(bytes.clone()),
+			Block::LoopingExtension(LoopCount::Number(261), AnimationApplication::AnimExts)
+		));
+
+		let read = Decoder::new(Cursor::new(&bytes)).read_all().unwrap();
+		assert_eq!(read.as_bytes(), bytes);
+	}
+
+	#[test]
+	fn netscape_buffering() {
+		let bytes = netscape(b"NETSCAPE2.0", &[0x02, 0x00, 0x10, 0x00, 0x00]);
+		assert!(matches!(
+			first_block(bytes.clone()),
+			Block::BufferingExtension(4096, AnimationApplication::Netscape)
+		));
+
+		let read = Decoder::new(Cursor::new(&bytes)).read_all().unwrap();
+		assert_eq!(read.as_bytes(), bytes);
+
+		let bytes = netscape(b"ANIMEXTS1.0", &[0x02, 0x00, 0x10, 0x00, 0x00]);
+		assert!(matches!(
+			first_block(bytes.clone()),
+			Block::BufferingExtension(4096, AnimationApplication::AnimExts)
+		));
+
+		let read = Decoder::new(Cursor::new(&bytes)).read_all().unwrap();
+		assert_eq!(read.as_bytes(), bytes);
+	}
+
+	#[test]
+	fn netscape_malformed() {
+		// Too short to be a loop count, so it stays an application extension
+		let bytes = netscape(b"NETSCAPE2.0", &[0x01, 0x00]);
+		match first_block(bytes) {
+			Block::ApplicationExtension(app) => assert_eq!(app.data(), &[0x01, 0x00]),
+			_ => panic!("expected an application extension"),
+		}
+	}
+}
@@ -0,0 +1,677 @@
+//! A decoder that's given the file a piece at a time, for when the bytes
+//! arrive in chunks and blocking on a [Read](std::io::Read) isn't an option.
+
+use std::{collections::VecDeque, convert::TryFrom, mem};
+
+use crate::{
+	block::{
+		extension::{GraphicControl, PlainText},
+		interlaced_rows, Block, ImageDescriptor, Palette, ScreenDescriptor, Version,
+	},
+	lzw::Decompressor,
+};
+
+use super::{
+	application_extension,
+	limits::{exceeds, ImageCount},
+	BlockKind, DecodeError, DecodeLimits,
+};
+
+/// Something [StreamDecoder] found. They come in the order they are in the
+/// file.
+#[derive(Clone, Debug)]
+pub enum Event {
+	Header(Version),
+	ScreenDescriptor(ScreenDescriptor),
+	/// The global color table. Only there if the screen descriptor says so.
+	GlobalPalette(Palette),
+	/// A whole extension. They're small, so we wait for all of it.
+	Extension(Block),
+	ImageDescriptor(ImageDescriptor),
+	/// The local color table of the image that was just described.
+	LocalPalette(Palette),
+	LzwCodeSize(u8),
+	/// One sub-block of the image's LZW data. Not given when decoding rows.
+	ImageData(Vec<u8>),
+	/// One row of the image, when decoding rows. `pass` and `y` are like
+	/// they are for [CompressedImage::decompress_progressive](crate::block::CompressedImage::decompress_progressive).
+	/// Rows are given as soon as there's enough data for them.
+	Row {
+		pass: u8,
+		y: u16,
+		indicies: Vec<u8>,
+	},
+	/// The end of the image's data.
+	ImageEnd,
+	/// The end of the file. Nothing comes after this.
+	Trailer,
+}
+
+enum State {
+	Header,
+	ScreenDescriptor,
+	GlobalPalette(usize),
+	Block,
+	ExtensionLabel,
+	Extension {
+		label: u8,
+		data: Vec<Vec<u8>>,
+		size: usize,
+	},
+	ImageDescriptor,
+	LocalPalette(usize),
+	LzwCodeSize,
+	ImageData,
+	/// After the trailer, or an error.
+	Finished,
+}
+
+impl State {
+	/// What's being read in this state, for saying where errors happened.
+	fn kind(&self) -> BlockKind {
+		match self {
+			State::Header => BlockKind::Header,
+			State::ScreenDescriptor => BlockKind::ScreenDescriptor,
+			State::GlobalPalette(_) => BlockKind::GlobalPalette,
+			State::Block | State::Finished => BlockKind::Unknown,
+			State::ExtensionLabel => BlockKind::Extension,
+			State::Extension { label, .. } => BlockKind::from_label(*label),
+			State::ImageDescriptor
+			| State::LocalPalette(_)
+			| State::LzwCodeSize
+			| State::ImageData => BlockKind::Image,
+		}
+	}
+}
+
+/// A push-style decoder. Give it bytes with [StreamDecoder::push] whenever
+/// they arrive and take [Event]s out with [StreamDecoder::next_event] until
+/// it needs more data.
+pub struct StreamDecoder {
+	buffer: Vec<u8>,
+	/// How much of `buffer` has been decoded
+	position: usize,
+	/// How many bytes have been decoded in total
+	consumed: usize,
+	/// Where the block we're in started, and how many images came before it
+	block_start: usize,
+	frame: usize,
+
+	state: State,
+	events: VecDeque<Event>,
+
+	limits: DecodeLimits,
+	images: ImageCount,
+
+	/// The descriptor of the image we're in
+	image: Option<ImageDescriptor>,
+	/// Set if we're decoding rows and are in an image
+	rows: Option<Rows>,
+	decode_rows: bool,
+}
+
+impl StreamDecoder {
+	pub fn new() -> Self {
+		Self {
+			buffer: vec![],
+			position: 0,
+			consumed: 0,
+			block_start: 0,
+			frame: 0,
+			state: State::Header,
+			events: VecDeque::new(),
+			limits: DecodeLimits::default(),
+			images: ImageCount::default(),
+			image: None,
+			rows: None,
+			decode_rows: false,
+		}
+	}
+
+	/// Set the limits to enforce while decoding. See [DecodeLimits].
+	pub fn limits(mut self, limits: DecodeLimits) -> Self {
+		self.limits = limits;
+		self
+	}
+
+	/// Decompress images as they arrive and give [Event::Row]s instead of
+	/// [Event::ImageData].
+	pub fn decode_rows(mut self, rows: bool) -> Self {
+		self.decode_rows = rows;
+		self
+	}
+
+	/// Add the next piece of the file.
+	pub fn push(&mut self, data: &[u8]) {
+		self.buffer.drain(..self.position);
+		self.position = 0;
+		self.buffer.extend_from_slice(data);
+	}
+
+	/// The next event, or None if there isn't enough data for one yet. Push
+	/// more and try again. After [Event::Trailer], or an error, this is
+	/// always None.
+	pub fn next_event(&mut self) -> Result<Option<Event>, DecodeError> {
+		loop {
+			if let Some(event) = self.events.pop_front() {
+				return Ok(Some(event));
+			}
+
+			let kind = self.state.kind();
+			match self.step() {
+				Ok(true) => continue,
+				Ok(false) => return Ok(None),
+				Err(e) => {
+					self.state = State::Finished;
+					self.events.clear();
+
+					let frame = match kind {
+						BlockKind::Header
+						| BlockKind::ScreenDescriptor
+						| BlockKind::GlobalPalette => None,
+						_ => Some(self.frame),
+					};
+					return Err(e.located(self.block_start, kind, frame));
+				}
+			}
+		}
+	}
+
+	/// Whether the trailer, or an error, has been reached.
+	pub fn is_finished(&self) -> bool {
+		matches!(self.state, State::Finished)
+	}
+
+	/// Decode what we can. Returns false if we need more data.
+	fn step(&mut self) -> Result<bool, DecodeError> {
+		let state = mem::replace(&mut self.state, State::Finished);
+
+		// Something new starts here
+		if matches!(
+			state,
+			State::Header | State::ScreenDescriptor | State::GlobalPalette(_) | State::Block
+		) {
+			self.block_start = self.consumed;
+			self.frame = self.images.frames();
+		}
+
+		self.state = match state {
+			State::Header => {
+				let Some(bytes) = self.take(6)? else {
+					return self.wait(State::Header);
+				};
+
+				let header = <[u8; 6]>::try_from(bytes).unwrap();
+				let version = match &header {
+					b"GIF87a" => Version::Gif87a,
+					b"GIF89a" => Version::Gif89a,
+					_ => return Err(DecodeError::InvalidVersion { header }),
+				};
+
+				self.events.push_back(Event::Header(version));
+				State::ScreenDescriptor
+			}
+			State::ScreenDescriptor => {
+				let Some(bytes) = self.take(7)? else {
+					return self.wait(State::ScreenDescriptor);
+				};
+
+				let descriptor = ScreenDescriptor::from(<[u8; 7]>::try_from(bytes).unwrap());
+				self.limits
+					.check_canvas(descriptor.width, descriptor.height)?;
+
+				let next = if descriptor.has_color_table() {
+					State::GlobalPalette(descriptor.color_table_len())
+				} else {
+					State::Block
+				};
+
+				self.events.push_back(Event::ScreenDescriptor(descriptor));
+				next
+			}
+			State::GlobalPalette(colors) => {
+				let Some(bytes) = self.take(colors * 3)? else {
+					return self.wait(State::GlobalPalette(colors));
+				};
+
+				let palette = Palette::try_from(bytes.as_slice()).unwrap();
+				self.events.push_back(Event::GlobalPalette(palette));
+				State::Block
+			}
+			State::Block => {
+				let Some(bytes) = self.take(1)? else {
+					return self.wait(State::Block);
+				};
+
+				match bytes[0] {
+					0x2C => State::ImageDescriptor,
+					0x21 => State::ExtensionLabel,
+					0x3B => {
+						self.events.push_back(Event::Trailer);
+						State::Finished
+					}
+					byte => return Err(DecodeError::UnknownBlock { byte }),
+				}
+			}
+			State::ExtensionLabel => {
+				let Some(bytes) = self.take(1)? else {
+					return self.wait(State::ExtensionLabel);
+				};
+
+				State::Extension {
+					label: bytes[0],
+					data: vec![],
+					size: 0,
+				}
+			}
+			State::Extension {
+				label,
+				mut data,
+				mut size,
+			} => {
+				let Some(block) = self.take_sub_block()? else {
+					return self.wait(State::Extension { label, data, size });
+				};
+
+				if block.is_empty() {
+					self.events
+						.push_back(Event::Extension(extension(label, data)));
+					State::Block
+				} else {
+					// The fixed size part of these isn't counted, like when
+					// reading with a Decoder
+					let header = data.is_empty() && matches!(label, 0xF9 | 0x01 | 0xFF);
+					if !header {
+						size += block.len();
+						if exceeds(size, self.limits.max_extension_size) {
+							return Err(DecodeError::ExtensionTooLarge);
+						}
+					}
+
+					data.push(block);
+					State::Extension { label, data, size }
+				}
+			}
+			State::ImageDescriptor => {
+				let Some(bytes) = self.take(9)? else {
+					return self.wait(State::ImageDescriptor);
+				};
+
+				let descriptor = ImageDescriptor::from(<[u8; 9]>::try_from(bytes).unwrap());
+				self.limits
+					.check_image(&mut self.images, descriptor.width, descriptor.height)?;
+
+				let next = if descriptor.has_color_table() {
+					State::LocalPalette(descriptor.color_table_size())
+				} else {
+					State::LzwCodeSize
+				};
+
+				self.image = Some(descriptor.clone());
+				self.events.push_back(Event::ImageDescriptor(descriptor));
+				next
+			}
+			State::LocalPalette(colors) => {
+				let Some(bytes) = self.take(colors * 3)? else {
+					return self.wait(State::LocalPalette(colors));
+				};
+
+				let palette = Palette::try_from(bytes.as_slice()).unwrap();
+				self.events.push_back(Event::LocalPalette(palette));
+				State::LzwCodeSize
+			}
+			State::LzwCodeSize => {
+				let Some(bytes) = self.take(1)? else {
+					return self.wait(State::LzwCodeSize);
+				};
+				let lzw_code_size = bytes[0];
+
+				if self.decode_rows {
+					// Indicies are bytes, so the code size can't be larger than 8 bits
+					if !(1..=8).contains(&lzw_code_size) {
+						return Err(DecodeError::InvalidCodeSize { lzw_code_size });
+					}
+
+					// We can't be in an image without having read its descriptor
+					let descriptor = self.image.as_ref().unwrap();
+					self.rows = Some(Rows::new(descriptor, lzw_code_size));
+				}
+
+				self.events.push_back(Event::LzwCodeSize(lzw_code_size));
+				State::ImageData
+			}
+			State::ImageData => {
+				let Some(block) = self.take_sub_block()? else {
+					return self.wait(State::ImageData);
+				};
+
+				if block.is_empty() {
+					self.image = None;
+					self.rows = None;
+					self.events.push_back(Event::ImageEnd);
+					State::Block
+				} else {
+					match self.rows.as_mut() {
+						None => self.events.push_back(Event::ImageData(block)),
+						Some(rows) => rows.push(&block, &mut self.events)?,
+					}
+
+					State::ImageData
+				}
+			}
+			State::Finished => return self.wait(State::Finished),
+		};
+
+		Ok(true)
+	}
+
+	/// Put the state back because there isn't enough data to go on.
+	fn wait(&mut self, state: State) -> Result<bool, DecodeError> {
+		self.state = state;
+		Ok(false)
+	}
+
+	/// Take `len` bytes, if there are that many.
+	fn take(&mut self, len: usize) -> Result<Option<Vec<u8>>, DecodeError> {
+		if self.buffer.len() - self.position < len {
+			return Ok(None);
+		}
+
+		if exceeds(self.consumed + len, self.limits.max_input_bytes) {
+			return Err(DecodeError::InputTooLarge);
+		}
+
+		let bytes = self.buffer[self.position..self.position + len].to_vec();
+		self.position += len;
+		self.consumed += len;
+
+		Ok(Some(bytes))
+	}
+
+	/// Take a whole data sub-block, without its length, if all of it is here.
+	/// An empty one is the block terminator.
+	fn take_sub_block(&mut self) -> Result<Option<Vec<u8>>, DecodeError> {
+		let Some(&len) = self.buffer.get(self.position) else {
+			return Ok(None);
+		};
+
+		Ok(self.take(len as usize + 1)?.map(|mut block| {
+			block.remove(0);
+			block
+		}))
+	}
+}
+
+impl Default for StreamDecoder {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Decompresses an image as its data arrives and splits it into rows.
+struct Rows {
+	lzw: Decompressor,
+	width: usize,
+	/// The pass and y of each row, in the order they're stored
+	order: Vec<(u8, u16)>,
+	next: usize,
+	/// Decompressed indicies that haven't made a whole row yet
+	decompressed: Vec<u8>,
+}
+
+impl Rows {
+	fn new(descriptor: &ImageDescriptor, lzw_code_size: u8) -> Self {
+		let order = if descriptor.packed.interlaced() {
+			interlaced_rows(descriptor.height).collect()
+		} else {
+			(0..descriptor.height).map(|y| (0, y)).collect()
+		};
+
+		// Anything past the last row would be thrown away, so don't decode it
+		let pixels = descriptor.width as usize * descriptor.height as usize;
+
+		Self {
+			lzw: Decompressor::new(lzw_code_size).limit(pixels),
+			width: descriptor.width as usize,
+			order,
+			next: 0,
+			decompressed: vec![],
+		}
+	}
+
+	fn push(&mut self, data: &[u8], events: &mut VecDeque<Event>) -> Result<(), DecodeError> {
+		self.lzw.push(data, &mut self.decompressed)?;
+
+		if self.width == 0 {
+			self.decompressed.clear();
+			return Ok(());
+		}
+
+		let mut used = 0;
+		for row in self.decompressed.chunks_exact(self.width) {
+			// Anything past the last row is ignored
+			let Some(&(pass, y)) = self.order.get(self.next) else {
+				used = self.decompressed.len();
+				break;
+			};
+
+			events.push_back(Event::Row {
+				pass,
+				y,
+				indicies: row.to_vec(),
+			});
+			self.next += 1;
+			used += self.width;
+		}
+		self.decompressed.drain(..used);
+
+		Ok(())
+	}
+}
+
+/// Make a [Block] from the label and sub-blocks of an extension. Anything
+/// shaped wrong is kept as a [Block::UnknownExtension].
+fn extension(label: u8, mut data: Vec<Vec<u8>>) -> Block {
+	let header_len = data.first().map(Vec::len);
+
+	match (label, header_len) {
+		(0xF9, Some(4)) => {
+			let header = <[u8; 4]>::try_from(data[0].as_slice()).unwrap();
+			Block::GraphicControlExtension(GraphicControl::from(header))
+		}
+		(0x01, Some(12)) => {
+			let header = <[u8; 12]>::try_from(data[0].as_slice()).unwrap();
+			let mut text = PlainText::from(header);
+			text.text = data[1..].concat();
+
+			Block::PlainTextExtension(text)
+		}
+		(0xFE, _) => Block::CommentExtension(data.concat()),
+		(0xFF, Some(11)) => {
+			let rest = data.split_off(1);
+			let header = &data[0];
+
+			let mut identifier = [0; 8];
+			let mut authentication_code = [0; 3];
+			identifier.copy_from_slice(&header[..8]);
+			authentication_code.copy_from_slice(&header[8..]);
+
+			application_extension(identifier, authentication_code, &rest)
+		}
+		_ => Block::UnknownExtension { label, data },
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::{
+		block::{extension::DisposalMethod, LoopCount},
+		reader::Decoder,
+		writer::ImageBuilder,
+		Gif,
+	};
+
+	fn gif() -> Gif {
+		let mut gif = Gif::new(4, 9);
+		gif.set_palette(Some(vec![(0, 0, 0), (255, 255, 255)].try_into().unwrap()));
+		gif.push(LoopCount::Forever);
+		gif.push(Block::CommentExtension(vec![b'c'; 300]));
+
+		let indicies: Vec<u8> = (0..36u8).map(|n| n % 3).collect();
+		gif.push(
+			ImageBuilder::new(4, 9)
+				.palette(vec![(1, 1, 1), (2, 2, 2), (3, 3, 3)].try_into().unwrap())
+				.interlaced(true)
+				.delay(5)
+				.disposal_method(DisposalMethod::RestoreBackground)
+				.build(indicies)
+				.unwrap(),
+		);
+		gif.push(ImageBuilder::new(2, 2).build(vec![1, 0, 0, 1]).unwrap());
+
+		gif
+	}
+
+	fn events(decoder: &mut StreamDecoder, bytes: &[u8], chunk: usize) -> Vec<Event> {
+		let mut events = vec![];
+		for piece in bytes.chunks(chunk) {
+			decoder.push(piece);
+			while let Some(event) = decoder.next_event().unwrap() {
+				events.push(event);
+			}
+		}
+
+		assert!(decoder.is_finished());
+		events
+	}
+
+	#[test]
+	fn needs_more_data() {
+		let bytes = gif().as_bytes();
+		let mut decoder = StreamDecoder::new();
+
+		decoder.push(&bytes[..5]);
+		assert!(decoder.next_event().unwrap().is_none());
+
+		decoder.push(&bytes[5..8]);
+		assert!(matches!(
+			decoder.next_event().unwrap(),
+			Some(Event::Header(Version::Gif89a))
+		));
+		assert!(decoder.next_event().unwrap().is_none());
+		assert!(!decoder.is_finished());
+	}
+
+	#[test]
+	fn matches_decoder() {
+		let gif = gif();
+		let bytes = gif.as_bytes();
+
+		for chunk in [1, 3, 64, bytes.len()] {
+			let events = events(&mut StreamDecoder::new(), &bytes, chunk);
+
+			let mut blocks = vec![];
+			let mut data = vec![];
+			for event in events {
+				match event {
+					Event::Extension(block) => blocks.push(block),
+					Event::ImageData(block) => data.push(block),
+					Event::ImageEnd => {
+						blocks.push(Block::CommentExtension(mem::take(&mut data).concat()))
+					}
+					_ => (),
+				}
+			}
+
+			// Images are stood in for by their data
+			let expected: Vec<Block> = gif
+				.blocks
+				.iter()
+				.map(|block| match block {
+					Block::CompressedImage(img) => Block::CommentExtension(img.blocks.concat()),
+					block => block.clone(),
+				})
+				.collect();
+
+			let mut streamed = Gif::new(4, 9);
+			streamed.blocks = blocks;
+			let mut reference = Gif::new(4, 9);
+			reference.blocks = expected;
+			assert_eq!(streamed.as_bytes(), reference.as_bytes());
+		}
+	}
+
+	#[test]
+	fn decodes_rows() {
+		let gif = gif();
+		let bytes = gif.as_bytes();
+
+		let mut expected = vec![];
+		for image in gif.images() {
+			image
+				.compressed
+				.decompress_progressive(|pass, y, row| expected.push((pass, y, row.to_vec())))
+				.unwrap();
+		}
+
+		for chunk in [1, 5, bytes.len()] {
+			let mut decoder = StreamDecoder::new().decode_rows(true);
+			let rows: Vec<(u8, u16, Vec<u8>)> = events(&mut decoder, &bytes, chunk)
+				.into_iter()
+				.filter_map(|event| match event {
+					Event::Row { pass, y, indicies } => Some((pass, y, indicies)),
+					Event::ImageData(_) => panic!("data when decoding rows"),
+					_ => None,
+				})
+				.collect();
+
+			assert_eq!(rows, expected);
+		}
+	}
+
+	#[test]
+	fn errors() {
+		let mut bytes = gif().as_bytes();
+		let trailer = bytes.len() - 1;
+		bytes[trailer] = 0x99;
+
+		let mut decoder = StreamDecoder::new();
+		decoder.push(&bytes);
+		let found = error(&mut decoder);
+		assert!(matches!(
+			found.root(),
+			DecodeError::UnknownBlock { byte: 0x99 }
+		));
+		assert!(decoder.is_finished());
+
+		// Errors say where they were the same as when reading with a Decoder
+		let expected = Decoder::new(bytes.as_slice()).read_all().unwrap_err();
+		assert_eq!(found.offset(), Some(trailer));
+		assert_eq!(found.frame(), expected.frame());
+
+		let limits = DecodeLimits::none().max_extension_size(10);
+		let bytes = gif().as_bytes();
+		let mut decoder = StreamDecoder::new().limits(limits);
+		decoder.push(&bytes);
+		let found = error(&mut decoder);
+		assert!(matches!(found.root(), DecodeError::ExtensionTooLarge));
+
+		let expected = Decoder::new(bytes.as_slice())
+			.limits(limits)
+			.read_all()
+			.unwrap_err();
+		assert_eq!(found.offset(), expected.offset());
+		assert_eq!(found.block(), expected.block());
+	}
+
+	fn error(decoder: &mut StreamDecoder) -> DecodeError {
+		loop {
+			match decoder.next_event() {
+				Ok(Some(_)) => continue,
+				Ok(None) => panic!("ran out of data"),
+				Err(e) => return e,
+			}
+		}
+	}
+}
@@ -0,0 +1,295 @@
+//! Composite the images of a [Gif] onto a full-size canvas, the way a viewer
+//! would display them.
+
+use crate::{
+	block::extension::DisposalMethod,
+	gif::{Image, ImageIterator},
+	reader::DecodeError,
+	Gif,
+};
+
+/// A fully composited frame. This is what the whole canvas looks like once
+/// an image has been drawn onto it.
+#[derive(Clone, Debug)]
+pub struct Frame {
+	pub width: u16,
+	pub height: u16,
+	/// RGBA pixels, row-major. The length is always width * height * 4.
+	pub rgba: Vec<u8>,
+	/// Time to show this frame, in hundredths of a second. Zero if there was
+	/// no Graphic Control Extension.
+	pub delay: u16,
+	/// The disposal method of the image this frame was drawn from.
+	pub disposal_method: DisposalMethod,
+}
+
+/// An iterator that renders every image in a [Gif] onto the canvas described
+/// by the [ScreenDescriptor](crate::block::ScreenDescriptor).
+///
+/// The canvas starts filled with the background color, if there is a global
+/// color table, or transparent black if there isn't.
+pub struct Renderer<'a> {
+	images: ImageIterator<'a>,
+	width: u16,
+	height: u16,
+	background: [u8; 4],
+	canvas: Vec<u8>,
+	/// What needs to happen to the canvas before the next image is drawn.
+	dispose: Option<Dispose>,
+}
+
+enum Dispose {
+	/// Clear a rectangle, (left, top, width, height), to the background.
+	Background(u16, u16, u16, u16),
+	/// Put the canvas back how it was before the last image was drawn.
+	Previous(Vec<u8>),
+}
+
+impl<'a> Renderer<'a> {
+	pub fn new(gif: &'a Gif) -> Self {
+		let width = gif.width();
+		let height = gif.height();
+
+		let background = background(gif);
+		let canvas = background.repeat(width as usize * height as usize);
+
+		Self {
+			images: gif.images(),
+			width,
+			height,
+			background,
+			canvas,
+			dispose: None,
+		}
+	}
+
+	fn dispose(&mut self) {
+		match self.dispose.take() {
+			None => (),
+			Some(Dispose::Previous(previous)) => self.canvas = previous,
+			Some(Dispose::Background(left, top, width, height)) => {
+				let (xrange, yrange) = self.clip(left, top, width, height);
+
+				for y in yrange {
+					for x in xrange.clone() {
+						let idx = (y * self.width as usize + x) * 4;
+						self.canvas[idx..idx + 4].copy_from_slice(&self.background);
+					}
+				}
+			}
+		}
+	}
+
+	/// Clip a rectangle to the canvas, returning the x and y ranges of the
+	/// visible part.
+	fn clip(
+		&self,
+		left: u16,
+		top: u16,
+		width: u16,
+		height: u16,
+	) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+		let cwidth = self.width as usize;
+		let cheight = self.height as usize;
+
+		let x0 = (left as usize).min(cwidth);
+		let y0 = (top as usize).min(cheight);
+		let x1 = (left as usize + width as usize).min(cwidth);
+		let y1 = (top as usize + height as usize).min(cheight);
+
+		(x0..x1, y0..y1)
+	}
+
+	fn draw(&mut self, image: &Image, indicies: &[u8]) -> Result<(), DecodeError> {
+		let palette = image.compressed.palette().or(image.global_palette);
+		let transparent = image.transparent_index();
+
+		let img_width = image.width() as usize;
+		let (xrange, yrange) = self.clip(image.left(), image.top(), image.width(), image.height());
+
+		for y in yrange {
+			let img_y = y - image.top() as usize;
+
+			for x in xrange.clone() {
+				let img_x = x - image.left() as usize;
+
+				// Streams that end early leave the rest of the image alone
+				let color_idx = match indicies.get(img_y * img_width + img_x) {
+					Some(&idx) => idx,
+					None => return Ok(()),
+				};
+
+				if Some(color_idx) == transparent {
+					continue;
+				}
+
+				let clr = palette
+					.and_then(|plt| plt.get(color_idx))
+					.ok_or(DecodeError::ColorIndexOutOfBounds)?;
+
+				let idx = (y * self.width as usize + x) * 4;
+				self.canvas[idx..idx + 4].copy_from_slice(&[clr.r, clr.g, clr.b, 255]);
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// The color the canvas starts as and is cleared to by
+/// [DisposalMethod::RestoreBackground].
+pub(crate) fn background(gif: &Gif) -> [u8; 4] {
+	match (gif.palette.as_ref(), gif.background_color()) {
+		(Some(plt), Some(idx)) => match plt.get(idx) {
+			Some(clr) => [clr.r, clr.g, clr.b, 255],
+			None => [0; 4],
+		},
+		_ => [0; 4],
+	}
+}
+
+impl<'a> Iterator for Renderer<'a> {
+	type Item = Result<Frame, DecodeError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let image = self.images.next()?;
+		self.dispose();
+
+		let indexed = match image.compressed.clone().decompress() {
+			Ok(indexed) => indexed,
+			Err(e) => return Some(Err(e)),
+		};
+
+		let disposal_method = image.disposal_method();
+		if disposal_method == DisposalMethod::RestorePrevious {
+			self.dispose = Some(Dispose::Previous(self.canvas.clone()));
+		}
+
+		if let Err(e) = self.draw(&image, &indexed.indicies) {
+			return Some(Err(e));
+		}
+
+		if disposal_method == DisposalMethod::RestoreBackground {
+			self.dispose = Some(Dispose::Background(
+				image.left(),
+				image.top(),
+				image.width(),
+				image.height(),
+			));
+		}
+
+		Some(Ok(Frame {
+			width: self.width,
+			height: self.height,
+			rgba: self.canvas.clone(),
+			delay: image.graphic_control().map(|gce| gce.delay()).unwrap_or(0),
+			disposal_method,
+		}))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::{block::Palette, writer::ImageBuilder, Color};
+
+	const RED: [u8; 4] = [255, 0, 0, 255];
+	const GREEN: [u8; 4] = [0, 255, 0, 255];
+	const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+	fn palette() -> Palette {
+		let mut plt = Palette::new();
+		plt.push(Color::new(255, 0, 0));
+		plt.push(Color::new(0, 255, 0));
+		plt.push(Color::new(0, 0, 255));
+		plt
+	}
+
+	fn gif() -> Gif {
+		let mut gif = Gif::new(2, 2);
+		gif.set_palette(Some(palette()));
+		gif.set_background_color(0);
+		gif
+	}
+
+	fn pixel(frame: &Frame, x: usize, y: usize) -> [u8; 4] {
+		let idx = (y * frame.width as usize + x) * 4;
+		frame.rgba[idx..idx + 4].try_into().unwrap()
+	}
+
+	#[test]
+	fn offset_image_over_background() {
+		let mut gif = gif();
+		gif.push(ImageBuilder::new(1, 1).offset(1, 1).build(vec![2]).unwrap());
+
+		let frames: Vec<Frame> = gif.render().map(Result::unwrap).collect();
+		assert_eq!(frames.len(), 1);
+		assert_eq!(pixel(&frames[0], 0, 0), RED);
+		assert_eq!(pixel(&frames[0], 1, 1), BLUE);
+	}
+
+	#[test]
+	fn transparent_keeps_canvas() {
+		let mut gif = gif();
+		gif.push(ImageBuilder::new(2, 2).build(vec![1, 1, 1, 1]).unwrap());
+		gif.push(
+			ImageBuilder::new(2, 2)
+				.transparent_index(Some(1))
+				.build(vec![2, 1, 1, 2])
+				.unwrap(),
+		);
+
+		let frames: Vec<Frame> = gif.render().map(Result::unwrap).collect();
+		assert_eq!(pixel(&frames[1], 0, 0), BLUE);
+		assert_eq!(pixel(&frames[1], 1, 0), GREEN);
+		assert_eq!(pixel(&frames[1], 0, 1), GREEN);
+		assert_eq!(pixel(&frames[1], 1, 1), BLUE);
+	}
+
+	#[test]
+	fn restore_background() {
+		let mut gif = gif();
+		gif.push(
+			ImageBuilder::new(1, 2)
+				.disposal_method(DisposalMethod::RestoreBackground)
+				.build(vec![1, 1])
+				.unwrap(),
+		);
+		gif.push(ImageBuilder::new(1, 1).offset(1, 0).build(vec![2]).unwrap());
+
+		let frames: Vec<Frame> = gif.render().map(Result::unwrap).collect();
+		assert_eq!(pixel(&frames[0], 0, 0), GREEN);
+		assert_eq!(pixel(&frames[1], 0, 0), RED);
+		assert_eq!(pixel(&frames[1], 0, 1), RED);
+		assert_eq!(pixel(&frames[1], 1, 0), BLUE);
+	}
+
+	#[test]
+	fn restore_previous() {
+		let mut gif = gif();
+		gif.push(ImageBuilder::new(2, 2).build(vec![1, 1, 1, 1]).unwrap());
+		gif.push(
+			ImageBuilder::new(1, 1)
+				.disposal_method(DisposalMethod::RestorePrevious)
+				.build(vec![2])
+				.unwrap(),
+		);
+		gif.push(ImageBuilder::new(1, 1).offset(1, 1).build(vec![0]).unwrap());
+
+		let frames: Vec<Frame> = gif.render().map(Result::unwrap).collect();
+		assert_eq!(pixel(&frames[1], 0, 0), BLUE);
+		assert_eq!(pixel(&frames[2], 0, 0), GREEN);
+		assert_eq!(pixel(&frames[2], 1, 1), RED);
+	}
+
+	#[test]
+	fn index_outside_palette() {
+		let mut gif = gif();
+		gif.push(ImageBuilder::new(1, 1).build(vec![3]).unwrap());
+
+		assert!(matches!(
+			gif.render().next(),
+			Some(Err(DecodeError::ColorIndexOutOfBounds))
+		));
+	}
+}
@@ -1,5 +1,7 @@
 use crate::{
 	block::{LoopCount, Palette},
+	optimize::{optimize_frames, OptimizeError},
+	quantize::{self, distance, QuantizeOptions, Quantizer},
 	writer::ImageBuilder,
 	Color, EncodeError, Gif,
 };
@@ -18,6 +20,7 @@ pub struct VideoGif {
 	framerate: Option<u16>,
 	frames: Vec<Frame>,
 	looping: LoopCount,
+	global_palette: Option<GlobalPalette>,
 }
 
 impl VideoGif {
@@ -28,6 +31,7 @@ impl VideoGif {
 			framerate: None,
 			frames: vec![],
 			looping: LoopCount::Forever,
+			global_palette: None,
 		}
 	}
 
@@ -46,6 +50,13 @@ impl VideoGif {
 		self.looping = count;
 	}
 
+	/// Share one global palette between the frames instead of giving each
+	/// its own. This saves up to 768 bytes a frame and stops colors from
+	/// flickering between frames. See [GlobalPalette].
+	pub fn set_global_palette(&mut self, global: GlobalPalette) {
+		self.global_palette = Some(global);
+	}
+
 	/// Adds a frame to the gif.
 	///
 	/// # Panic
@@ -69,27 +80,43 @@ impl VideoGif {
 
 	#[rustfmt::skip] // it was doing things i did not like
 	pub fn build(self) -> Result<Gif, EncodeError> {
-		let Self { width, height, framerate, frames, looping } = self;
+		let Self { width, height, framerate, mut frames, looping, global_palette } = self;
 
 		let mut gif = Gif::new(width, height);
 
+		if let Some(global) = global_palette {
+			let palette = global.apply(width, &mut frames);
+			gif.set_palette(Some(palette));
+		}
+
 		gif.push(looping);
 
-		for Frame { image_indices, interval, palette } in frames {
+		for Frame { image_indices, interval, palette, transparent_index } in frames {
 			//TODO: return error instead of defaulting to 10? or print warning?
 			// printing in a library is bad but perhaps so is assuming 10 fps?
 			let delay = interval.or(framerate).unwrap_or(10);
 
-			gif.push(
-				ImageBuilder::new(width, height)
-					.delay(delay)
-					.palette(palette)
-					.build(image_indices)?,
-			)
+			let mut builder = ImageBuilder::new(width, height)
+				.delay(delay)
+				.transparent_index(transparent_index);
+
+			// Frames without a palette use the global one
+			if let Some(palette) = palette {
+				builder = builder.palette(palette);
+			}
+
+			gif.push(builder.build(image_indices)?)
 		}
 
 		Ok(gif)
 	}
+
+	/// Build the gif and then [optimize](optimize_frames) it, so that each
+	/// frame only covers the pixels that changed.
+	pub fn build_optimized(self) -> Result<Gif, OptimizeError> {
+		let gif = self.build()?;
+		optimize_frames(&gif)
+	}
 }
 
 pub struct Frame {
@@ -97,7 +124,9 @@ pub struct Frame {
 	image_indices: Vec<u8>,
 	/// in hundredths of a second
 	interval: Option<u16>,
-	palette: Palette,
+	/// None if the frame uses the global palette
+	palette: Option<Palette>,
+	transparent_index: Option<u8>,
 }
 
 impl From<Vec<Color>> for Frame {
@@ -122,7 +151,8 @@ impl From<&[Color]> for Frame {
 		Self {
 			image_indices: indices,
 			interval: None,
-			palette,
+			palette: Some(palette),
+			transparent_index: None,
 		}
 	}
 }
@@ -137,7 +167,194 @@ impl From<(&[Color], u16)> for Frame {
 }
 
 impl Frame {
+	/// Make a frame from truecolor pixels with the given [Quantizer]. Frames
+	/// made with `From` use NeuQuant. `width` is only used for dithering.
+	pub fn quantize<Q: Quantizer + ?Sized>(
+		pixels: &[Color],
+		width: u16,
+		quantizer: &Q,
+		options: QuantizeOptions,
+	) -> Self {
+		let quantized = quantizer.quantize(pixels, width as usize, options);
+
+		Self {
+			image_indices: quantized.indicies,
+			interval: None,
+			palette: Some(quantized.palette),
+			transparent_index: quantized.transparent_index,
+		}
+	}
+
 	pub fn set_interval(&mut self, interval_hundredths: u16) {
 		self.interval = Some(interval_hundredths);
 	}
+
+	/// The color of every pixel.
+	fn colors(&self) -> Vec<Color> {
+		let palette = match &self.palette {
+			Some(palette) => palette,
+			None => return vec![],
+		};
+
+		self.image_indices
+			.iter()
+			.map(|&idx| palette.get(idx).unwrap_or(Color::new(0, 0, 0)))
+			.collect()
+	}
+}
+
+/// How a [VideoGif] picks a palette to share between all of its frames.
+///
+/// The palette is chosen from a sample of the pixels of every frame, then
+/// each frame is remapped on to it. If a frame would be too far off from its
+/// own palette, it keeps that instead.
+pub struct GlobalPalette {
+	quantizer: Box<dyn Quantizer>,
+	options: QuantizeOptions,
+	step: usize,
+	max_error: Option<u32>,
+}
+
+impl GlobalPalette {
+	pub fn new<Q: Quantizer + 'static>(quantizer: Q) -> Self {
+		Self {
+			quantizer: Box::new(quantizer),
+			options: QuantizeOptions::new(),
+			step: 4,
+			max_error: None,
+		}
+	}
+
+	/// The options used to pick the palette and remap the frames. If a
+	/// transparent color is reserved, every frame using the global palette
+	/// gets it as their transparent index.
+	pub fn options(mut self, options: QuantizeOptions) -> Self {
+		self.options = options;
+		self
+	}
+
+	/// Only look at every `step`th pixel of each frame when picking the
+	/// palette. Defaults to 4.
+	pub fn sample(mut self, step: usize) -> Self {
+		self.step = step;
+		self
+	}
+
+	/// The largest average error a frame can have with the global palette
+	/// before it gets its own instead. The error is the squared distance
+	/// between a pixel's color in the frame's own palette and its color in
+	/// the global one. Without this every frame uses the global palette.
+	pub fn max_error(mut self, max_error: u32) -> Self {
+		self.max_error = Some(max_error);
+		self
+	}
+
+	/// Pick the palette and remap the frames that should use it.
+	fn apply(&self, width: u16, frames: &mut [Frame]) -> Palette {
+		// The frames have already been quantized, so their own palettes are
+		// the best idea we have of what they looked like
+		let colors: Vec<Vec<Color>> = frames.iter().map(Frame::colors).collect();
+		let images: Vec<&[Color]> = colors.iter().map(Vec::as_slice).collect();
+
+		let mut palette =
+			quantize::sample_palette(self.quantizer.as_ref(), &images, self.step, self.options);
+
+		let transparent_index = if self.options.reserve_transparent {
+			Some(palette.len() as u8)
+		} else {
+			None
+		};
+
+		for (frame, colors) in frames.iter_mut().zip(&colors) {
+			let indicies = self.options.dither.remap(colors, width as usize, &palette);
+
+			if let Some(max_error) = self.max_error {
+				let error: u64 = colors
+					.iter()
+					.zip(&indicies)
+					.map(|(&clr, &idx)| distance(clr, palette.get(idx).unwrap()) as u64)
+					.sum();
+
+				if error / (colors.len().max(1) as u64) > max_error as u64 {
+					continue;
+				}
+			}
+
+			frame.image_indices = indicies;
+			frame.palette = None;
+			frame.transparent_index = transparent_index;
+		}
+
+		if transparent_index.is_some() {
+			palette.push(Color::new(0, 0, 0));
+		}
+
+		palette
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::quantize::MedianCut;
+
+	fn solid(color: Color) -> Frame {
+		Frame::quantize(&[color; 16], 4, &MedianCut, QuantizeOptions::new())
+	}
+
+	#[test]
+	fn shared_palette() {
+		let mut video = VideoGif::new(4, 4);
+		video.set_global_palette(GlobalPalette::new(MedianCut));
+		video.add_frame(solid(Color::new(255, 0, 0)));
+		video.add_frame(solid(Color::new(0, 0, 255)));
+
+		let gif = video.build().unwrap();
+		assert_eq!(gif.palette.as_ref().map(|plt| plt.len()), Some(2));
+
+		for (image, color) in gif
+			.images()
+			.zip([Color::new(255, 0, 0), Color::new(0, 0, 255)])
+		{
+			assert!(image.compressed.palette().is_none());
+
+			let idx = image.decompress().indicies[0];
+			assert_eq!(image.palette().get(idx), Some(color));
+		}
+	}
+
+	#[test]
+	fn optimized() {
+		let mut video = VideoGif::new(4, 4);
+		video.set_framerate(10);
+		video.add_frame(solid(Color::new(255, 0, 0)));
+		video.add_frame(solid(Color::new(255, 0, 0)));
+
+		let optimized = video.build_optimized().unwrap();
+		let second = optimized.images().nth(1).unwrap();
+		assert_eq!((second.width(), second.height()), (1, 1));
+	}
+
+	#[test]
+	fn falls_back_to_local_palette() {
+		let mut video = VideoGif::new(4, 4);
+		video.set_global_palette(
+			GlobalPalette::new(MedianCut)
+				.options(QuantizeOptions::new().max_colors(2))
+				.max_error(1),
+		);
+		video.add_frame(solid(Color::new(0, 0, 0)));
+		video.add_frame(solid(Color::new(0, 0, 250)));
+		video.add_frame(solid(Color::new(0, 0, 255)));
+
+		let gif = video.build().unwrap();
+		let local: Vec<bool> = gif
+			.images()
+			.map(|image| image.compressed.palette().is_some())
+			.collect();
+
+		// Median cut puts the first two in the same box, so they share a
+		// color that's not close to either and keep their own palettes
+		assert_eq!(local, vec![true, true, false]);
+	}
 }
@@ -0,0 +1,131 @@
+//! Writing to a tokio [AsyncWrite]. Each block is encoded by a [Writer] in to
+//! a buffer and the buffer is then written out, so the bytes are exactly the
+//! same as they would be from the [Writer].
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::block::{LoopCount, Palette, ScreenDescriptor, Version};
+
+use super::{EncodeBlock, EncodeError, Strict87a, Writer};
+
+/// Like [Writer], but for an [AsyncWrite].
+pub struct AsyncWriter<W: AsyncWrite + Unpin> {
+	/// Encodes in to a buffer that's emptied after every write
+	encoder: Writer<Vec<u8>>,
+	writer: W,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWriter<W> {
+	/// See [Writer::new].
+	pub async fn new(
+		writer: W,
+		width: u16,
+		height: u16,
+		global_palette: Option<Palette>,
+	) -> Result<Self, EncodeError> {
+		let encoder = Writer::new(vec![], width, height, global_palette)?;
+		Self::start(encoder, writer).await
+	}
+
+	/// See [Writer::strict_87a].
+	pub async fn strict_87a(
+		writer: W,
+		width: u16,
+		height: u16,
+		global_palette: Option<Palette>,
+		strict: Strict87a,
+	) -> Result<Self, EncodeError> {
+		let encoder = Writer::strict_87a(vec![], width, height, global_palette, strict)?;
+		Self::start(encoder, writer).await
+	}
+
+	/// See [Writer::from_parts].
+	pub async fn from_parts(
+		writer: W,
+		version: Version,
+		screen_descriptor: ScreenDescriptor,
+		global_palette: Option<Palette>,
+	) -> Result<Self, EncodeError> {
+		let encoder = Writer::from_parts(vec![], version, screen_descriptor, global_palette)?;
+		Self::start(encoder, writer).await
+	}
+
+	async fn start(encoder: Writer<Vec<u8>>, writer: W) -> Result<Self, EncodeError> {
+		let mut this = Self { encoder, writer };
+		this.write_out().await?;
+		Ok(this)
+	}
+
+	pub async fn repeat(&mut self, count: LoopCount) -> Result<(), EncodeError> {
+		self.encoder.repeat(count)?;
+		self.write_out().await
+	}
+
+	pub async fn push<I: Into<EncodeBlock>>(&mut self, image: I) -> Result<(), EncodeError> {
+		self.encoder.push(image)?;
+		self.write_out().await
+	}
+
+	/// Write the trailer and flush the writer.
+	pub async fn done(mut self) -> Result<(), EncodeError> {
+		self.encoder.write_all(&[0x3B])?;
+		self.write_out().await?;
+		self.writer.flush().await?;
+
+		Ok(())
+	}
+
+	/// Write out everything that's been encoded.
+	async fn write_out(&mut self) -> Result<(), EncodeError> {
+		self.writer.write_all(&self.encoder.writer).await?;
+		self.encoder.writer.clear();
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use tokio::io::AsyncReadExt;
+
+	use super::*;
+	use crate::{block::Block, writer::ImageBuilder};
+
+	#[tokio::test]
+	async fn writes_duplex() {
+		let palette: Palette = vec![(0, 0, 0), (255, 255, 255)].try_into().unwrap();
+		let image = || {
+			let indicies = (0..64 * 64u32).map(|n| (n % 3 == 0) as u8).collect();
+			ImageBuilder::new(64, 64).delay(10).build(indicies).unwrap()
+		};
+
+		let mut expected = vec![];
+		let mut writer = Writer::new(&mut expected, 64, 64, Some(palette.clone())).unwrap();
+		writer.repeat(LoopCount::Forever).unwrap();
+		writer
+			.push(Block::CommentExtension(vec![b'c'; 600]))
+			.unwrap();
+		writer.push(image()).unwrap();
+		writer.push(image()).unwrap();
+		writer.done().unwrap();
+
+		let (tx, mut rx) = tokio::io::duplex(64);
+		let write = async move {
+			let mut writer = AsyncWriter::new(tx, 64, 64, Some(palette)).await?;
+			writer.repeat(LoopCount::Forever).await?;
+			writer
+				.push(Block::CommentExtension(vec![b'c'; 600]))
+				.await?;
+			writer.push(image()).await?;
+			writer.push(image()).await?;
+			writer.done().await
+		};
+		let mut written = vec![];
+		let read = rx.read_to_end(&mut written);
+
+		let (write, read) = tokio::join!(write, read);
+		write.unwrap();
+		read.unwrap();
+		assert_eq!(written, expected);
+	}
+}
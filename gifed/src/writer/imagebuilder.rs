@@ -4,7 +4,8 @@ use crate::{
 		packed::ImagePacked,
 		ImageDescriptor, IndexedImage, Palette, Version,
 	},
-	EncodeError,
+	quantize::{QuantizeOptions, Quantized, Quantizer},
+	Color, EncodeError,
 };
 
 pub struct ImageBuilder {
@@ -13,6 +14,7 @@ pub struct ImageBuilder {
 	width: u16,
 	height: u16,
 	color_table: Option<Palette>,
+	interlaced: bool,
 
 	delay: u16,
 	disposal_method: DisposalMethod,
@@ -27,6 +29,7 @@ impl ImageBuilder {
 			width,
 			height,
 			color_table: None,
+			interlaced: false,
 			delay: 0,
 			disposal_method: DisposalMethod::NoAction,
 			transparent_index: None,
@@ -44,6 +47,13 @@ impl ImageBuilder {
 		self
 	}
 
+	/// Store the rows of the image interlaced, in four passes, so a decoder
+	/// can show a rough version of the image before it has all of it.
+	pub fn interlaced(mut self, interlaced: bool) -> Self {
+		self.interlaced = interlaced;
+		self
+	}
+
 	/// Time to wait, in hundredths of a second, before this image is drawn
 	pub fn delay(mut self, hundredths: u16) -> Self {
 		self.delay = hundredths;
@@ -114,6 +124,7 @@ impl ImageBuilder {
 		};
 
 		imgdesc.set_color_table_metadata(self.color_table.as_ref());
+		imgdesc.packed.set_interlaced(self.interlaced);
 
 		let image = IndexedImage {
 			image_descriptor: imgdesc,
@@ -123,6 +134,45 @@ impl ImageBuilder {
 
 		Ok(BuiltImage { image, gce })
 	}
+
+	/// Quantize truecolor pixels with `quantizer` and build the image from
+	/// them. The palette is used as the local color table, replacing any set
+	/// with [ImageBuilder::palette], and if a transparent color was reserved
+	/// it becomes the transparent index. If the options ask for dithering,
+	/// the pixels are dithered on to the palette.
+	pub fn build_quantized<Q: Quantizer + ?Sized>(
+		self,
+		pixels: &[Color],
+		quantizer: &Q,
+		options: QuantizeOptions,
+	) -> Result<BuiltImage, EncodeError> {
+		let quantized = quantizer.quantize(pixels, self.width as usize, options);
+		self.build_from(quantized)
+	}
+
+	/// Quantize RGBA data, four bytes a pixel, and build the image from it.
+	/// This works like [ImageBuilder::build_quantized], but if any pixels
+	/// have an alpha below the options'
+	/// [alpha threshold](QuantizeOptions::alpha_threshold) a palette entry
+	/// is kept for them and it becomes the transparent index.
+	pub fn build_rgba<Q: Quantizer + ?Sized>(
+		self,
+		rgba: &[u8],
+		quantizer: &Q,
+		options: QuantizeOptions,
+	) -> Result<BuiltImage, EncodeError> {
+		let quantized = quantizer.quantize_rgba(rgba, self.width as usize, options);
+		self.build_from(quantized)
+	}
+
+	fn build_from(self, quantized: Quantized) -> Result<BuiltImage, EncodeError> {
+		let mut builder = self.palette(quantized.palette);
+		if quantized.transparent_index.is_some() {
+			builder = builder.transparent_index(quantized.transparent_index);
+		}
+
+		builder.build(quantized.indicies)
+	}
 }
 
 pub struct BuiltImage {
@@ -1,23 +1,39 @@
+#[cfg(feature = "tokio")]
+mod asynchronous;
 mod imagebuilder;
 
 use std::{error::Error, fmt, io::Write};
 
+#[cfg(feature = "tokio")]
+pub use asynchronous::AsyncWriter;
 pub use imagebuilder::{BuiltImage, ImageBuilder};
 
 use crate::block::{
-	encode_block, Block, CompressedImage, IndexedImage, LoopCount, Palette, ScreenDescriptor,
-	Version,
+	write_block, Block, CompressedImage, ImageDescriptor, IndexedImage, LoopCount, Palette,
+	ScreenDescriptor, Version,
 };
 
 pub struct Writer<W: Write> {
 	writer: W,
 	global_palette: Option<Palette>,
+	/// Set if we're writing GIF87a and have to keep out anything newer
+	strict: Option<Strict87a>,
+}
+
+/// What to do with blocks and flags that need [Version::Gif89a] when writing
+/// a [Version::Gif87a] file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strict87a {
+	/// Fail with [EncodeError::RequiresGif89a].
+	Error,
+	/// Leave the blocks out and clear the flags.
+	Strip,
 }
 
 impl<W: Write> Writer<W> {
 	/// Write the required bits of a GIF. The version written to the stream is
-	/// [Version::Gif89a]. If for some reason you need [Version::Gif87a] I
-	/// recommend you use [Writer::from_parts].
+	/// [Version::Gif89a], because we can't know what's going to be pushed. If
+	/// you need [Version::Gif87a] use [Writer::strict_87a].
 	pub fn new(
 		writer: W,
 		width: u16,
@@ -30,6 +46,25 @@ impl<W: Write> Writer<W> {
 		Self::from_parts(writer, Version::Gif89a, screen_descriptor, global_palette)
 	}
 
+	/// Write a [Version::Gif87a] file. Anything pushed that needs
+	/// [Version::Gif89a], like an extension or a Graphic Control Extension
+	/// from an [ImageBuilder], is handled the way `strict` says.
+	pub fn strict_87a(
+		writer: W,
+		width: u16,
+		height: u16,
+		global_palette: Option<Palette>,
+		strict: Strict87a,
+	) -> Result<Self, EncodeError> {
+		let mut screen_descriptor = ScreenDescriptor::new(width, height);
+		screen_descriptor.set_color_table_metadata(global_palette.as_ref());
+
+		let mut this =
+			Self::from_parts(writer, Version::Gif87a, screen_descriptor, global_palette)?;
+		this.strict = Some(strict);
+		Ok(this)
+	}
+
 	//FIXME: gen- This name sucks
 	/// Create a new [Writer] from the provided required blocks.
 	pub fn from_parts(
@@ -41,12 +76,13 @@ impl<W: Write> Writer<W> {
 		let mut this = Self {
 			writer,
 			global_palette,
+			strict: None,
 		};
 		this.write_all(version.as_bytes())?;
-		this.write_all(&screen_descriptor.as_bytes())?;
+		screen_descriptor.write_to(&mut this.writer)?;
 
 		if let Some(palette) = this.global_palette.as_ref() {
-			this.write_all(&palette.as_bytes())?;
+			palette.write_to(&mut this.writer)?;
 		}
 
 		Ok(this)
@@ -59,32 +95,63 @@ impl<W: Write> Writer<W> {
 	}
 
 	pub fn repeat(&mut self, count: LoopCount) -> Result<(), EncodeError> {
-		self.write_all(&encode_block(&Block::LoopingExtension(count)))
+		self.push(count)
 	}
 
 	pub fn push<I: Into<EncodeBlock>>(&mut self, image: I) -> Result<(), EncodeError> {
 		match image.into() {
-			EncodeBlock::CompressedImage(compressed) => self.write_all(&compressed.as_bytes()),
-			EncodeBlock::IndexedImage(indexed) => {
+			EncodeBlock::CompressedImage(mut compressed) => {
+				self.check_image(&mut compressed.image_descriptor)?;
+				Ok(compressed.write_to(&mut self.writer)?)
+			}
+			EncodeBlock::IndexedImage(mut indexed) => {
+				self.check_image(&mut indexed.image_descriptor)?;
 				let lzw_code_size = self.global_palette.as_ref().map(|p| p.lzw_code_size());
 
-				let compressed = indexed.compress(lzw_code_size)?;
-				self.write_all(&compressed.as_bytes())
+				indexed.compress_to(lzw_code_size, &mut self.writer)
 			}
-			EncodeBlock::BuiltImage(built) => {
+			EncodeBlock::BuiltImage(mut built) => {
 				if let Some(gce) = built.gce {
-					self.write_all(&encode_block(&Block::GraphicControlExtension(gce)))?;
+					self.push(Block::GraphicControlExtension(gce))?;
 				}
 
+				self.check_image(&mut built.image.image_descriptor)?;
 				let lzw_code_size = self.global_palette.as_ref().map(|p| p.lzw_code_size());
 
-				let compressed = built.image.compress(lzw_code_size)?;
-				self.write_all(&compressed.as_bytes())
+				built.image.compress_to(lzw_code_size, &mut self.writer)
+			}
+			EncodeBlock::Block(Block::CompressedImage(compressed)) => self.push(compressed),
+			EncodeBlock::Block(block) => {
+				if self.allowed(block.required_version())? {
+					Ok(write_block(&block, &mut self.writer)?)
+				} else {
+					Ok(())
+				}
 			}
-			EncodeBlock::Block(block) => self.write_all(&encode_block(&block)),
 		}
 	}
 
+	/// Whether something that needs `version` can be written.
+	fn allowed(&self, version: Version) -> Result<bool, EncodeError> {
+		match self.strict {
+			Some(Strict87a::Error) if version == Version::Gif89a => {
+				Err(EncodeError::RequiresGif89a)
+			}
+			Some(Strict87a::Strip) if version == Version::Gif89a => Ok(false),
+			_ => Ok(true),
+		}
+	}
+
+	/// Images can't be left out, but the flags that need GIF89a can be
+	/// cleared.
+	fn check_image(&self, descriptor: &mut ImageDescriptor) -> Result<(), EncodeError> {
+		if !self.allowed(descriptor.required_version())? {
+			descriptor.packed.set_sorted(false);
+		}
+
+		Ok(())
+	}
+
 	pub fn done(mut self) -> Result<(), EncodeError> {
 		self.write_all(&[0x3B])
 	}
@@ -92,10 +159,19 @@ impl<W: Write> Writer<W> {
 
 #[derive(Debug)]
 pub enum EncodeError {
-	IoError { error: std::io::Error },
+	IoError {
+		error: std::io::Error,
+	},
 	TooManyColors,
-	IndicieSizeMismatch { expected: usize, got: usize },
-	InvalidCodeSize { lzw_code_size: u8 },
+	IndicieSizeMismatch {
+		expected: usize,
+		got: usize,
+	},
+	InvalidCodeSize {
+		lzw_code_size: u8,
+	},
+	/// Something needs GIF89a but it's being written as GIF87a.
+	RequiresGif89a,
 }
 
 impl Error for EncodeError {}
@@ -112,6 +188,7 @@ impl fmt::Display for EncodeError {
 			Self::InvalidCodeSize { lzw_code_size } => {
 				write!(f, "InvalidCodeSize => {lzw_code_size}")
 			}
+			Self::RequiresGif89a => write!(f, "GIF87a can't hold this, it needs GIF89a"),
 		}
 	}
 }
@@ -152,3 +229,38 @@ impl<T: Into<Block>> From<T> for EncodeBlock {
 		EncodeBlock::Block(ib.into())
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn strict_87a_strips() {
+		let palette: Palette = vec![(0, 0, 0), (255, 255, 255)].try_into().unwrap();
+		let mut out = vec![];
+
+		let mut writer =
+			Writer::strict_87a(&mut out, 1, 1, Some(palette), Strict87a::Strip).unwrap();
+		writer.repeat(LoopCount::Forever).unwrap();
+		writer
+			.push(ImageBuilder::new(1, 1).delay(10).build(vec![1]).unwrap())
+			.unwrap();
+		writer.done().unwrap();
+
+		let gif = crate::reader::Decoder::new(std::io::Cursor::new(&out))
+			.read_all()
+			.unwrap();
+		assert_eq!(gif.version, Version::Gif87a);
+		assert_eq!(gif.blocks.len(), 1);
+	}
+
+	#[test]
+	fn strict_87a_errors() {
+		let mut writer = Writer::strict_87a(vec![], 1, 1, None, Strict87a::Error).unwrap();
+
+		assert!(matches!(
+			writer.push(Block::CommentExtension(vec![])),
+			Err(EncodeError::RequiresGif89a)
+		));
+	}
+}
@@ -3,9 +3,10 @@ use std::ops::Range;
 use gifed::{
 	block::{
 		Block::{self},
-		CompressedImage,
+		CompressedImage, LoopCount,
 	},
 	reader::Decoder,
+	DecodeError,
 };
 use owo_colors::OwoColorize;
 
@@ -31,8 +32,10 @@ fn main() {
 		}
 	}
 
-	let decoder = Decoder::file(&file).unwrap();
-	let mut reader = decoder.read().unwrap();
+	let mut reader = match Decoder::file(&file).and_then(Decoder::read) {
+		Ok(reader) => reader,
+		Err(e) => report_error(e),
+	};
 
 	println!("Version {}", reader.version.yellow());
 	println!(
@@ -74,10 +77,7 @@ fn main() {
 		let block = match reader.block() {
 			Ok(Some(block)) => block,
 			Ok(None) => break,
-			Err(e) => {
-				eprintln!("error reading file: {e}");
-				std::process::exit(-1);
-			}
+			Err(e) => report_error(e),
 		};
 
 		let offset = block.offset;
@@ -110,7 +110,49 @@ fn main() {
 					dispose_string.yellow()
 				)
 			}
-			Block::LoopingExtension(_) => todo!(),
+			Block::LoopingExtension(count, app) => {
+				print!("Looping Extension");
+				print_offset(offset);
+
+				println!("\tApplication {}", app.identifier().escape_ascii().yellow());
+				match count {
+					LoopCount::Forever => println!("\tLoop {}", "forever".yellow()),
+					LoopCount::Number(count) => println!("\tLoop {}", count.yellow()),
+				}
+			}
+			Block::BufferingExtension(size, app) => {
+				print!("Buffering Extension");
+				print_offset(offset);
+
+				println!("\tApplication {}", app.identifier().escape_ascii().yellow());
+				println!("\tBuffer Size {}", format!("{size} bytes").yellow());
+			}
+			Block::PlainTextExtension(text) => {
+				print!("Plain Text Extension");
+				print_offset(offset);
+
+				println!(
+					"\tGrid Offset {}x{}\n\tGrid Dimensions {}x{}\n\tCell Dimensions {}x{}",
+					text.left.yellow(),
+					text.top.yellow(),
+					text.width.yellow(),
+					text.height.yellow(),
+					text.cell_width.yellow(),
+					text.cell_height.yellow()
+				);
+				println!(
+					"\tForeground Index {}\n\tBackground Index {}",
+					text.foreground_color_index.yellow(),
+					text.background_color_index.yellow()
+				);
+
+				let string = String::from_utf8_lossy(text.text());
+				if expand {
+					println!("\tText \"{}\"", string.yellow());
+				} else {
+					println!("\tText Length {}", text.text().len().yellow());
+				}
+			}
 			Block::CommentExtension(cmt) => {
 				print!("Comment Extension");
 				print_offset(offset);
@@ -124,7 +166,7 @@ fn main() {
 							println!("\tString {}", "Content is not utf8".red())
 						} else {
 							let lossy = String::from_utf8_lossy(&cmt);
-							if lossy.len() > 0 {
+							if !lossy.is_empty() {
 								println!("\tString (lossy) \"{}\"", lossy.yellow())
 							} else {
 								println!(
@@ -136,6 +178,18 @@ fn main() {
 					}
 				}
 			}
+			Block::UnknownExtension { label, data } => {
+				print!("Unknown Extension");
+				print_offset(offset);
+
+				let length: usize = data.iter().map(|sub| sub.len()).sum();
+				println!(
+					"\tLabel {}\n\tSub-blocks {}\n\tLength {}",
+					format!("{label:02X}").yellow(),
+					data.len().yellow(),
+					length.yellow()
+				);
+			}
 			Block::ApplicationExtension(app) => {
 				let auth = app.authentication_code();
 				let app_ident = String::from_utf8_lossy(app.identifier());
@@ -151,34 +205,23 @@ fn main() {
 					auth[2].yellow()
 				);
 
-				if app_ident == "NETSCAPE" {
-					let data = app.data();
-					let looping = u16::from_le_bytes([data[1], data[2]]);
+				let data = app.data();
 
-					if looping == 0 {
-						println!("\tLoop {}", "forever".yellow())
-					} else {
-						println!("\tLoop {}", looping.yellow());
-					}
-				} else {
-					let data = app.data();
-
-					match String::from_utf8(data.to_vec()) {
-						Ok(s) => {
-							println!(
-								"\tData {}",
-								format!("Valid UTF-8, {} bytes", s.len()).yellow()
-							);
+				match String::from_utf8(data.to_vec()) {
+					Ok(s) => {
+						println!(
+							"\tData {}",
+							format!("Valid UTF-8, {} bytes", s.len()).yellow()
+						);
 
-							if expand {
-								println!("\tString \"{}\"", s.yellow());
-							}
+						if expand {
+							println!("\tString \"{}\"", s.yellow());
 						}
-						Err(_e) => println!(
-							"\tData {}",
-							format!("Invalid UTF-8, {} bytes", data.len()).yellow()
-						),
 					}
+					Err(_e) => println!(
+						"\tData {}",
+						format!("Invalid UTF-8, {} bytes", data.len()).yellow()
+					),
 				}
 			}
 		}
@@ -256,6 +299,23 @@ fn describe_image(bli: CompressedImage, offset: Range<usize>, expand: bool, colo
 	}
 }
 
+/// Say what went wrong, and where, and exit.
+fn report_error(error: DecodeError) -> ! {
+	eprintln!("{} {}", "Error".red(), error.root());
+
+	if let Some(block) = error.block() {
+		eprintln!("\tWhile Reading {}", block.yellow());
+	}
+	if let Some(offset) = error.offset() {
+		eprintln!("\tOffset {} ({:#X})", offset.cyan(), offset);
+	}
+	if let Some(frame) = error.frame() {
+		eprintln!("\tFrame {}", frame.yellow());
+	}
+
+	std::process::exit(-1);
+}
+
 fn print_offset(offset: Range<usize>) {
 	print!(" [");
 	print_usize(offset.start);